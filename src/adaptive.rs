@@ -0,0 +1,121 @@
+use crate::vec3::Vec3;
+
+/// Running statistics over the samples taken for a single pixel.
+///
+/// Uses Welford's algorithm so the mean and variance can be updated one
+/// sample at a time without keeping the samples around.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PixelStats {
+    count: usize,
+    mean: Vec3,
+    m2: Vec3,
+}
+
+impl PixelStats {
+    pub fn add(&mut self, sample: Vec3) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        let delta2 = sample - self.mean;
+        self.m2 += delta * delta2;
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn variance(&self) -> Vec3 {
+        if self.count < 2 {
+            return Vec3::default();
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    /// Variance of the pixel's mean luminance, which shrinks as more samples
+    /// are taken.
+    pub fn variance_of_mean(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        self.variance().luminance() / self.count as f64
+    }
+
+    /// Estimated error of the pixel after gamma correction.
+    ///
+    /// This is the standard error of the mean luminance, carried through the
+    /// square root of the default `Encoding::Gamma2` so that the same
+    /// threshold behaves the same in dark and bright regions of the image.
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let standard_error = self.variance_of_mean().sqrt();
+        if standard_error == 0.0 {
+            return 0.0;
+        }
+        standard_error / (2.0 * self.mean.luminance().max(1e-8).sqrt())
+    }
+}
+
+pub struct AdaptiveSampling {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    pub noise_threshold: f64,
+}
+
+impl AdaptiveSampling {
+    pub fn new(min_samples: usize, max_samples: usize, noise_threshold: f64) -> Self {
+        let min_samples = min_samples.clamp(2, max_samples.max(2));
+        Self {
+            min_samples,
+            max_samples,
+            noise_threshold,
+        }
+    }
+
+    pub fn done(&self, stats: &PixelStats) -> bool {
+        let count = stats.count();
+        count >= self.max_samples
+            || (count >= self.min_samples && stats.error() < self.noise_threshold)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AdaptiveSampling, PixelStats};
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn stats_should_compute_mean_and_variance() {
+        let mut stats = PixelStats::default();
+        for value in &[1.0, 2.0, 3.0, 4.0] {
+            stats.add(Vec3::new(*value, *value, *value));
+        }
+        assert_eq!(stats.count(), 4);
+        assert!((stats.mean.x - 2.5).abs() < 1e-12);
+        assert!((stats.variance().x - 5.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn constant_pixel_should_stop_at_min_samples() {
+        let sampling = AdaptiveSampling::new(4, 64, 0.01);
+        let mut stats = PixelStats::default();
+        while !sampling.done(&stats) {
+            stats.add(Vec3::new(0.5, 0.7, 1.0));
+        }
+        assert_eq!(stats.count(), 4);
+    }
+
+    #[test]
+    fn noisy_pixel_should_run_to_max_samples() {
+        let sampling = AdaptiveSampling::new(4, 64, 1e-6);
+        let mut stats = PixelStats::default();
+        let mut flip = false;
+        while !sampling.done(&stats) {
+            flip = !flip;
+            let value = if flip { 1.0 } else { 0.0 };
+            stats.add(Vec3::new(value, value, value));
+        }
+        assert_eq!(stats.count(), 64);
+    }
+}
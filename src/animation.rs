@@ -0,0 +1,202 @@
+use crate::{options::ParseOptionError, vec3::Vec3};
+use std::str::FromStr;
+
+/// Where the camera is and what it is doing at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    /// Field of view in degrees.
+    pub fov: f64,
+    pub focus_dist: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// A Catmull-Rom spline through the keyframes, which moves smoothly
+    /// through each one instead of turning sharply.
+    Spline,
+}
+
+impl FromStr for Interpolation {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Interpolation::Linear),
+            "spline" => Ok(Interpolation::Spline),
+            _ => Err(ParseOptionError::new("interpolation", s)),
+        }
+    }
+}
+
+/// A camera moving between keyframes, which are kept sorted by time.
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl CameraPath {
+    /// Panics if there are no keyframes.
+    pub fn new(mut keyframes: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        assert!(!keyframes.is_empty(), "a camera path needs a keyframe");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            keyframes,
+            interpolation,
+        }
+    }
+
+    pub fn start(&self) -> f64 {
+        self.keyframes[0].time
+    }
+
+    pub fn end(&self) -> f64 {
+        self.keyframes[self.keyframes.len() - 1].time
+    }
+
+    /// The camera at `time`, holding still before the first keyframe and
+    /// after the last.
+    pub fn at(&self, time: f64) -> Keyframe {
+        let keys = &self.keyframes;
+        let last = keys.len() - 1;
+        if time <= keys[0].time {
+            return Keyframe { time, ..keys[0] };
+        }
+        if time >= keys[last].time {
+            return Keyframe { time, ..keys[last] };
+        }
+        let i = keys.partition_point(|key| key.time <= time) - 1;
+        let (k0, k1) = (&keys[i], &keys[i + 1]);
+        let span = k1.time - k0.time;
+        let t = (time - k0.time) / span;
+        match self.interpolation {
+            Interpolation::Linear => Keyframe {
+                time,
+                look_from: lerp(k0.look_from, k1.look_from, t),
+                look_at: lerp(k0.look_at, k1.look_at, t),
+                fov: lerp(k0.fov, k1.fov, t),
+                focus_dist: lerp(k0.focus_dist, k1.focus_dist, t),
+            },
+            Interpolation::Spline => {
+                let before = &keys[i.saturating_sub(1)];
+                let after = &keys[(i + 2).min(last)];
+                Keyframe {
+                    time,
+                    look_from: spline(before, k0, k1, after, t, |key| key.look_from),
+                    look_at: spline(before, k0, k1, after, t, |key| key.look_at),
+                    fov: spline(before, k0, k1, after, t, |key| key.fov),
+                    focus_dist: spline(before, k0, k1, after, t, |key| key.focus_dist),
+                }
+            }
+        }
+    }
+}
+
+/// Interpolates a value between `k0` and `k1` with a cubic Hermite curve,
+/// taking its slope at each end from the keyframes either side.
+fn spline<T>(
+    before: &Keyframe,
+    k0: &Keyframe,
+    k1: &Keyframe,
+    after: &Keyframe,
+    t: f64,
+    value: impl Fn(&Keyframe) -> T,
+) -> T
+where
+    T: std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T> + std::ops::Sub<Output = T>,
+{
+    let span = k1.time - k0.time;
+    let m0 = tangent(before, k0, k1, &value) * span;
+    let m1 = tangent(k0, k1, after, &value) * span;
+    hermite(value(k0), m0, value(k1), m1, t)
+}
+
+/// The rate of change at `current`, from the keyframes either side of it.
+/// At the ends of the path one of those is `current` itself.
+fn tangent<T>(
+    previous: &Keyframe,
+    current: &Keyframe,
+    next: &Keyframe,
+    value: impl Fn(&Keyframe) -> T,
+) -> T
+where
+    T: std::ops::Mul<f64, Output = T> + std::ops::Sub<Output = T>,
+{
+    let dt = next.time - previous.time;
+    if dt <= 0.0 {
+        return value(current) * 0.0;
+    }
+    (value(next) - value(previous)) * (1.0 / dt)
+}
+
+fn lerp<T>(a: T, b: T, t: f64) -> T
+where
+    T: std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T>,
+{
+    a * (1.0 - t) + b * t
+}
+
+fn hermite<T>(p0: T, m0: T, p1: T, m1: T, t: f64) -> T
+where
+    T: std::ops::Mul<f64, Output = T> + std::ops::Add<Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m0 * (t3 - 2.0 * t2 + t)
+        + p1 * (-2.0 * t3 + 3.0 * t2)
+        + m1 * (t3 - t2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CameraPath, Interpolation, Keyframe};
+    use crate::vec3::Vec3;
+
+    fn keyframe(time: f64, x: f64) -> Keyframe {
+        Keyframe {
+            time,
+            look_from: Vec3::new(x, 2.0, 3.0),
+            look_at: Vec3::new(0.0, 0.0, 0.0),
+            fov: 20.0 + x,
+            focus_dist: 10.0,
+        }
+    }
+
+    #[test]
+    fn linear_path_should_interpolate_between_keyframes() {
+        let path = CameraPath::new(
+            vec![keyframe(2.0, 4.0), keyframe(0.0, 0.0)],
+            Interpolation::Linear,
+        );
+        let middle = path.at(1.0);
+        assert_eq!(middle.look_from, Vec3::new(2.0, 2.0, 3.0));
+        assert_eq!(middle.fov, 22.0);
+        assert_eq!(path.at(5.0).look_from, Vec3::new(4.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn spline_path_should_pass_through_keyframes() {
+        let keys = vec![
+            keyframe(0.0, 0.0),
+            keyframe(1.0, 3.0),
+            keyframe(3.0, -1.0),
+            keyframe(4.0, 2.0),
+        ];
+        let path = CameraPath::new(keys.clone(), Interpolation::Spline);
+        for key in keys {
+            let at = path.at(key.time);
+            assert!((at.look_from - key.look_from).length() < 1e-9);
+            assert!((at.fov - key.fov).abs() < 1e-9);
+        }
+        // Points on a straight line stay on it.
+        let path = CameraPath::new(
+            vec![keyframe(0.0, 0.0), keyframe(1.0, 1.0), keyframe(2.0, 2.0)],
+            Interpolation::Spline,
+        );
+        assert!((path.at(0.5).look_from.x - 0.5).abs() < 1e-9);
+    }
+}
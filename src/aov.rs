@@ -0,0 +1,278 @@
+use crate::{
+    denoise::Features,
+    film::Film,
+    filter::{BoxFilter, FilterKind},
+    hit::{Hit, Hittables},
+    ray::{Radiance, Ray},
+    vec3::Vec3,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    rc::Rc,
+};
+
+/// Extra buffers rendered alongside the beauty image for compositing.
+///
+/// The lighting buffers use the same reconstruction filter as the beauty
+/// pass, and take the same splats, so that `direct + indirect` adds back up
+/// to it. Geometric buffers are
+/// a plain per-pixel mean, and ids come from the first sample in each pixel
+/// since averaging them would make up ids that don't exist. Pixels where the
+/// camera ray escaped are left at zero, and ids start at one.
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    direct: Film,
+    indirect: Film,
+    splats: Vec<Radiance>,
+    depth: Film,
+    normal: Film,
+    albedo: Film,
+    position: Film,
+    object_id: Vec<Option<f64>>,
+    material_id: Vec<Option<f64>>,
+    material_ids: HashMap<usize, usize>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, filter: FilterKind, radius: Option<f64>) -> Self {
+        let mean = || Film::new(width, height, Box::new(BoxFilter::new(0.5)));
+        Self {
+            width,
+            height,
+            direct: Film::new(width, height, filter.build(radius)),
+            indirect: Film::new(width, height, filter.build(radius)),
+            splats: vec![Radiance::default(); width * height],
+            depth: mean(),
+            normal: mean(),
+            albedo: mean(),
+            position: mean(),
+            object_id: vec![None; width * height],
+            material_id: vec![None; width * height],
+            material_ids: HashMap::new(),
+        }
+    }
+
+    pub fn add_sample<'material, T>(
+        &mut self,
+        x: f64,
+        y: f64,
+        ray: Option<&Ray>,
+        world: &Hittables<T>,
+        radiance: &Radiance,
+    ) where
+        T: Hit<'material>,
+    {
+        self.direct.add_sample(x, y, radiance.direct);
+        self.indirect.add_sample(x, y, radiance.indirect);
+        // Samples blocked by the lens see no surface at all.
+        let ray = match ray {
+            Some(ray) => ray,
+            None => return,
+        };
+
+        let index =
+            (y as usize).min(self.height - 1) * self.width + (x as usize).min(self.width - 1);
+        let first = self.object_id[index].is_none();
+        match world.hit_object(ray, 0.001, f64::INFINITY) {
+            Some((object, record)) => {
+                let depth = record.t * ray.direction.length();
+                self.depth.add_sample(x, y, Vec3::new(depth, depth, depth));
+                self.normal.add_sample(x, y, record.normal);
+                self.albedo
+                    .add_sample(x, y, record.material.borrow().albedo(&record));
+                self.position.add_sample(x, y, record.point);
+                if first {
+                    let next_id = self.material_ids.len() + 1;
+                    let key = Rc::as_ptr(&record.material) as *const () as usize;
+                    let material = *self.material_ids.entry(key).or_insert(next_id);
+                    self.object_id[index] = Some((object + 1) as f64);
+                    self.material_id[index] = Some(material as f64);
+                }
+            }
+            None => {
+                for film in [
+                    &mut self.depth,
+                    &mut self.normal,
+                    &mut self.albedo,
+                    &mut self.position,
+                ] {
+                    film.add_sample(x, y, Vec3::default());
+                }
+                if first {
+                    self.object_id[index] = Some(0.0);
+                    self.material_id[index] = Some(0.0);
+                }
+            }
+        }
+    }
+
+    /// Adds the light integrators splatted onto the image once rendering is
+    /// done, already scaled as `Splats::pixels` gives it.
+    pub fn add_splats(&mut self, splats: &[Radiance]) {
+        for (pixel, splat) in self.splats.iter_mut().zip(splats) {
+            *pixel += *splat;
+        }
+    }
+
+    /// The albedo, normal and depth buffers, in the row order of `Film`.
+    pub fn features(&self) -> Features {
+        Features {
+            albedo: self.albedo.pixels(),
+            normal: self.normal.pixels(),
+            depth: self.depth.pixels().iter().map(|depth| depth.x).collect(),
+        }
+    }
+
+    /// Writes each buffer to its own PFM file in `directory`.
+    pub fn write(&self, directory: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let lighting = |film: &Film, part: fn(&Radiance) -> Vec3| {
+            film.pixels()
+                .iter()
+                .zip(&self.splats)
+                .map(|(pixel, splat)| *pixel + part(splat))
+                .collect::<Vec<_>>()
+        };
+        let buffers = [
+            ("direct", lighting(&self.direct, |splat| splat.direct)),
+            ("indirect", lighting(&self.indirect, |splat| splat.indirect)),
+            ("depth", self.depth.pixels()),
+            ("normal", self.normal.pixels()),
+            ("albedo", self.albedo.pixels()),
+            ("position", self.position.pixels()),
+        ];
+        for (name, pixels) in buffers.iter() {
+            write_pfm(
+                &directory.join(format!("{}.pfm", name)),
+                self.width,
+                self.height,
+                pixels,
+            )?;
+        }
+        for (name, ids) in [
+            ("object_id", &self.object_id),
+            ("material_id", &self.material_id),
+        ]
+        .iter()
+        {
+            let pixels = ids
+                .iter()
+                .map(|id| {
+                    let id = id.unwrap_or(0.0);
+                    Vec3::new(id, id, id)
+                })
+                .collect::<Vec<_>>();
+            write_pfm(
+                &directory.join(format!("{}.pfm", name)),
+                self.width,
+                self.height,
+                &pixels,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a portable float map, with rows ordered bottom to top as the
+/// format expects.
+pub fn write_pfm(
+    path: &Path,
+    width: usize,
+    height: usize,
+    pixels: &[Vec3],
+) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(format!("PF\n{} {}\n-1.0\n", width, height).as_bytes())?;
+    for pixel in pixels {
+        for value in &[pixel.x, pixel.y, pixel.z] {
+            file.write_all(&(*value as f32).to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        material::{Lambertian, Material},
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, convert::TryInto, fs};
+
+    /// Reads back the pixels of a PFM file this module wrote.
+    fn read_pfm(path: &Path) -> Vec<Vec3> {
+        let bytes = fs::read(path).unwrap();
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let values = bytes[header.len()..]
+            .chunks(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()) as f64)
+            .collect::<Vec<_>>();
+        values
+            .chunks(3)
+            .map(|pixel| Vec3::new(pixel[0], pixel[1], pixel[2]))
+            .collect()
+    }
+
+    #[test]
+    fn buffers_hold_what_the_camera_saw_and_splats_add_to_the_lighting() {
+        let albedo = Vec3::new(0.2, 0.4, 0.6);
+        let material: Box<dyn Material> = Box::new(Lambertian::new(albedo));
+        let mut world = Hittables::new();
+        world.add(Sphere::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            1.0,
+            Rc::new(RefCell::new(material)),
+        ));
+
+        // The left pixel looks at the sphere, the right one past it.
+        let mut aovs = AovBuffers::new(2, 1, FilterKind::Box, None);
+        let towards = Ray::new(Vec3::default(), Vec3::new(0.0, 0.0, -2.0));
+        let away = Ray::new(Vec3::default(), Vec3::new(0.0, 1.0, 0.0));
+        let radiance = Radiance {
+            direct: Vec3::new(1.0, 0.0, 0.0),
+            indirect: Vec3::new(0.0, 1.0, 0.0),
+        };
+        aovs.add_sample(0.5, 0.5, Some(&towards), &world, &radiance);
+        aovs.add_sample(1.5, 0.5, Some(&away), &world, &Radiance::default());
+        let splat = Radiance {
+            direct: Vec3::new(0.0, 0.0, 1.0),
+            indirect: Vec3::new(0.0, 0.0, 2.0),
+        };
+        aovs.add_splats(&[splat, splat]);
+
+        let directory = std::env::temp_dir().join(format!("aov-test-{}", std::process::id()));
+        aovs.write(&directory).unwrap();
+        let read = |name: &str| read_pfm(&directory.join(format!("{}.pfm", name)));
+        assert_eq!(read("depth"), [Vec3::new(2.0, 2.0, 2.0), Vec3::default()]);
+        assert_eq!(read("normal"), [Vec3::new(0.0, 0.0, 1.0), Vec3::default()]);
+        let stored = Vec3::new(
+            albedo.x as f32 as f64,
+            albedo.y as f32 as f64,
+            albedo.z as f32 as f64,
+        );
+        assert_eq!(read("albedo"), [stored, Vec3::default()]);
+        assert_eq!(
+            read("position"),
+            [Vec3::new(0.0, 0.0, -2.0), Vec3::default()]
+        );
+        assert_eq!(read("object_id")[0], Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(read("material_id")[1], Vec3::default());
+        assert_eq!(
+            read("direct"),
+            [Vec3::new(1.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0)]
+        );
+        assert_eq!(
+            read("indirect"),
+            [Vec3::new(0.0, 1.0, 2.0), Vec3::new(0.0, 0.0, 2.0)]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,560 @@
+use crate::{
+    film::Splats,
+    hit::HitRecord,
+    integrator::{Integrator, SceneView},
+    ray::{Radiance, Ray},
+    sampler::{sample_unit_disk, Sampler},
+    vec3::{dot, perpendicular, unit_vector, Vec3},
+};
+use std::f64::consts::PI;
+
+#[derive(Clone)]
+enum Kind<'material> {
+    /// A point on the lens.
+    Camera,
+    /// The sky, where a camera path escaped or a light path started.
+    Sky,
+    Surface(HitRecord<'material>),
+}
+
+/// A vertex of a camera or light subpath.
+///
+/// Densities are per unit area at the vertex, except on the sky, where they
+/// are per unit solid angle.
+#[derive(Clone)]
+struct Vertex<'material> {
+    kind: Kind<'material>,
+    point: Vec3,
+    /// The surface normal, facing the side the path arrived from.
+    normal: Vec3,
+    /// The direction back along the path.
+    wo: Vec3,
+    /// The path's contribution up to and including this vertex, divided by
+    /// the density of sampling it.
+    throughput: Vec3,
+    /// The density of sampling this vertex from the one before it on its
+    /// own subpath.
+    pdf_fwd: f64,
+    /// The density of sampling this vertex the other way, from the one
+    /// after it.
+    pdf_rev: f64,
+    /// Whether the path scattered specularly here.
+    delta: bool,
+}
+
+impl<'material> Vertex<'material> {
+    fn new(kind: Kind<'material>, point: Vec3, throughput: Vec3, pdf_fwd: f64) -> Self {
+        Self {
+            kind,
+            point,
+            normal: Vec3::default(),
+            wo: Vec3::default(),
+            throughput,
+            pdf_fwd,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    fn is_sky(&self) -> bool {
+        matches!(self.kind, Kind::Sky)
+    }
+
+    fn is_surface(&self) -> bool {
+        matches!(self.kind, Kind::Surface(_))
+    }
+
+    /// Whether the vertex can be joined to a vertex of the other subpath.
+    fn is_connectible(&self) -> bool {
+        match &self.kind {
+            Kind::Surface(record) => !record.material.borrow().is_specular(),
+            _ => true,
+        }
+    }
+
+    /// The BSDF for light scattering here between `next` and the previous
+    /// vertex.
+    fn bsdf(&self, next: &Vertex) -> Vec3 {
+        match &self.kind {
+            Kind::Surface(record) => {
+                let wi = unit_vector(next.point - self.point);
+                record.material.borrow().bsdf(record, self.wo, wi)
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    /// Converts a density per unit solid angle at this vertex into one per
+    /// unit area at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        if next.is_sky() {
+            return pdf;
+        }
+        let w = next.point - self.point;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.is_surface() {
+            pdf *= dot(next.normal, w / distance_squared.sqrt()).abs();
+        }
+        pdf
+    }
+
+    /// The density of sampling `next` from this vertex, having arrived from
+    /// `prev`.
+    fn pdf(&self, scene: &SceneView, radius: f64, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        let wn = unit_vector(next.point - self.point);
+        let pdf = match &self.kind {
+            Kind::Sky => return self.pdf_light(radius, next),
+            Kind::Camera => scene
+                .camera
+                .importance_pdf(&Ray::new(self.point, wn))
+                .map_or(0.0, |(_, direction)| direction),
+            Kind::Surface(record) => {
+                let wp = prev.map_or(self.wo, |prev| unit_vector(prev.point - self.point));
+                record.material.borrow().pdf(record, wp, wn)
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// The density of a light path starting on the sky and reaching `next`
+    /// first, for a sky vertex. Light paths start on a disk as wide as the
+    /// scene's bounding sphere.
+    fn pdf_light(&self, radius: f64, next: &Vertex) -> f64 {
+        let w = unit_vector(next.point - self.point);
+        let mut pdf = 1.0 / (PI * radius * radius);
+        if next.is_surface() {
+            pdf *= dot(next.normal, w).abs();
+        }
+        pdf
+    }
+
+    /// The radiance the sky sends towards `prev`, for a sky vertex.
+    fn emitted(&self, scene: &SceneView, prev: &Vertex) -> Vec3 {
+        scene
+            .sky
+            .color(&Ray::new(prev.point, self.point - prev.point))
+    }
+}
+
+/// The two halves of a sample's paths, and what's needed to join them.
+struct Subpaths<'material> {
+    light: Vec<Vertex<'material>>,
+    camera: Vec<Vertex<'material>>,
+    /// The radius of the scene's bounding sphere.
+    radius: f64,
+    /// Whether light vertices can be joined straight to the camera.
+    light_tracing: bool,
+}
+
+/// A bidirectional path tracer.
+///
+/// Each sample traces a path from the camera and another from the sky, then
+/// joins every prefix of one to every prefix of the other. Each join is a
+/// different way the same light path could have been sampled, and multiple
+/// importance sampling with the balance heuristic weights them so the ones
+/// most likely to find it count for most. Joins straight to the camera land
+/// elsewhere on the image and go to the splats; those need a camera that
+/// supports light tracing, and are skipped otherwise.
+///
+/// Specular materials, fuzzy metals included, can be passed through but
+/// never joined at. The sky is the only light it traces from, so area
+/// lights in the scene are left dark.
+pub struct Bdpt {
+    max_depth: u16,
+}
+
+impl Bdpt {
+    pub fn new(max_depth: u16) -> Self {
+        Self { max_depth }
+    }
+
+    /// Extends `path` from its last vertex along `ray`, which was sampled
+    /// with density `pdf_fwd`, following scattered rays until they escape
+    /// or stop scattering, or the path is as long as it may get. Camera
+    /// paths that escape end with a vertex on the sky.
+    fn random_walk<'material>(
+        &self,
+        scene: &SceneView<'_, 'material>,
+        mut ray: Ray,
+        mut pdf_fwd: f64,
+        from_camera: bool,
+        sampler: &mut dyn Sampler,
+        path: &mut Vec<Vertex<'material>>,
+    ) {
+        // Camera paths have one more vertex for the lens.
+        let max_vertices = self.max_depth as usize + if from_camera { 2 } else { 1 };
+        let mut throughput = path[path.len() - 1].throughput;
+        while path.len() < max_vertices {
+            let record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(record) => record,
+                None => {
+                    if from_camera {
+                        let mut sky =
+                            Vertex::new(Kind::Sky, ray.origin + ray.direction, throughput, pdf_fwd);
+                        sky.wo = -ray.direction;
+                        path.push(sky);
+                    }
+                    return;
+                }
+            };
+            let prev = path.len() - 1;
+            let mut vertex =
+                Vertex::new(Kind::Surface(record.clone()), record.point, throughput, 0.0);
+            vertex.normal = record.normal;
+            vertex.wo = -ray.direction;
+            vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                return;
+            }
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Vec3::default();
+            let mut material = record.material.borrow_mut();
+            if !material.scatter(&ray, &record, &mut attenuation, &mut scattered, sampler) {
+                return;
+            }
+            let wo = -ray.direction;
+            let wi = unit_vector(scattered.direction);
+            let pdf_rev = if material.is_specular() {
+                path[prev + 1].delta = true;
+                pdf_fwd = 0.0;
+                0.0
+            } else {
+                pdf_fwd = material.pdf(&record, wo, wi);
+                if pdf_fwd == 0.0 {
+                    return;
+                }
+                material.pdf(&record, wi, wo)
+            };
+            throughput *= attenuation;
+            path[prev].pdf_rev = path[prev + 1].convert_density(pdf_rev, &path[prev]);
+            ray = Ray::new(record.point, wi);
+        }
+    }
+
+    /// A light path starting on a disk just outside the scene's bounding
+    /// sphere, heading across it in a direction picked by the sky.
+    fn light_path<'material>(
+        &self,
+        scene: &SceneView<'_, 'material>,
+        (center, radius): (Vec3, f64),
+        sampler: &mut dyn Sampler,
+    ) -> Vec<Vertex<'material>> {
+        // The sky picks directions to look at it in, light heads the other
+        // way.
+        let (towards_sky, pdf_direction) = scene.sky.sample_direction(sampler.next_2d());
+        let direction = -towards_sky;
+        let (tangent, bitangent) = perpendicular(direction);
+        let disk = radius * sample_unit_disk(sampler.next_2d());
+        let origin = center - radius * direction + disk.x * tangent + disk.y * bitangent;
+        let pdf_position = 1.0 / (PI * radius * radius);
+        let emitted = scene.sky.color(&Ray::new(origin, -direction));
+
+        // The first vertex's throughput is only used to start the walk, as
+        // joins to the sky sample a new vertex there.
+        let throughput = emitted / (pdf_position * pdf_direction);
+        let mut path = vec![Vertex::new(Kind::Sky, origin, throughput, pdf_position)];
+        let ray = Ray::new(origin, direction);
+        self.random_walk(scene, ray, pdf_direction, false, sampler, &mut path);
+        // Moving the starting point over the disk is what reaches the first
+        // surface, not choosing the direction.
+        if let Some(first) = path.get_mut(1) {
+            first.pdf_fwd = pdf_position * dot(direction, first.normal).abs();
+        }
+        path[0].pdf_fwd = pdf_direction;
+        path
+    }
+
+    /// The contribution of the path made by joining the first `s` light
+    /// vertices to the first `t` camera vertices, weighted for MIS, and
+    /// where on the image it lands if that isn't where the camera path
+    /// started.
+    fn connect(
+        &self,
+        scene: &SceneView,
+        paths: &Subpaths,
+        s: usize,
+        t: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Option<(f64, f64)>)> {
+        let (light, camera) = (&paths.light, &paths.camera);
+        if t > 1 && s != 0 && camera[t - 1].is_sky() {
+            return None;
+        }
+        let mut sampled = None;
+        let mut image = None;
+        let contribution = if s == 0 {
+            let pt = &camera[t - 1];
+            if !pt.is_sky() {
+                return None;
+            }
+            pt.emitted(scene, &camera[t - 2]) * pt.throughput
+        } else if t == 1 {
+            let qs = &light[s - 1];
+            if !qs.is_connectible() {
+                return None;
+            }
+            let importance = scene
+                .camera
+                .sample_importance(qs.point, sampler.next_2d())?;
+            if importance.pdf <= 0.0 || importance.importance <= 0.0 {
+                return None;
+            }
+            let weight = importance.importance / importance.pdf;
+            let lens = Vertex::new(
+                Kind::Camera,
+                importance.lens_point,
+                Vec3::new(weight, weight, weight),
+                0.0,
+            );
+            let wi = unit_vector(lens.point - qs.point);
+            let contribution =
+                qs.throughput * qs.bsdf(&lens) * lens.throughput * dot(wi, qs.normal).abs();
+            if contribution.near_zero() || !unoccluded(scene, qs.point, lens.point) {
+                return None;
+            }
+            image = Some(importance.image);
+            sampled = Some(lens);
+            contribution
+        } else if s == 1 {
+            let pt = &camera[t - 1];
+            if !pt.is_connectible() {
+                return None;
+            }
+            let (direction, pdf) = scene.sky.sample_direction(sampler.next_2d());
+            let emitted = scene.sky.color(&Ray::new(pt.point, direction));
+            let sky = Vertex::new(
+                Kind::Sky,
+                pt.point + 2.0 * paths.radius * direction,
+                emitted / pdf,
+                pdf,
+            );
+            let contribution =
+                pt.throughput * pt.bsdf(&sky) * sky.throughput * dot(direction, pt.normal).abs();
+            if contribution.near_zero()
+                || scene
+                    .world
+                    .hit(&Ray::new(pt.point, direction), 0.001, f64::INFINITY)
+                    .is_some()
+            {
+                return None;
+            }
+            sampled = Some(sky);
+            contribution
+        } else {
+            let (qs, pt) = (&light[s - 1], &camera[t - 1]);
+            if !qs.is_connectible() || !pt.is_connectible() {
+                return None;
+            }
+            let contribution = qs.throughput * qs.bsdf(pt) * pt.bsdf(qs) * pt.throughput;
+            if contribution.near_zero() {
+                return None;
+            }
+            let g = geometry_term(qs, pt);
+            if g == 0.0 || !unoccluded(scene, qs.point, pt.point) {
+                return None;
+            }
+            contribution * g
+        };
+        let weight = self.mis_weight(scene, paths, sampled, s, t);
+        Some((contribution * weight, image))
+    }
+
+    /// The balance heuristic weight for the `(s, t)` strategy: its density
+    /// over the sum of the densities of every strategy that could have made
+    /// the same path, worked out as ratios walking out from the join.
+    fn mis_weight<'material>(
+        &self,
+        scene: &SceneView,
+        paths: &Subpaths<'material>,
+        sampled: Option<Vertex<'material>>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let radius = paths.radius;
+        let mut light = paths.light[..s].to_vec();
+        let mut camera = paths.camera[..t].to_vec();
+        if let Some(sampled) = sampled {
+            if s == 1 {
+                light[0] = sampled;
+            } else {
+                camera[0] = sampled;
+            }
+        }
+
+        // Work out the densities the join changes before changing any.
+        let pt = &camera[t - 1];
+        let pt_minus = (t > 1).then(|| &camera[t - 2]);
+        let qs = (s > 0).then(|| &light[s - 1]);
+        let qs_minus = (s > 1).then(|| &light[s - 2]);
+        let pt_rev = match qs {
+            Some(qs) => qs.pdf(scene, radius, qs_minus, pt),
+            None => scene.sky.pdf(pt.point - pt_minus.unwrap().point),
+        };
+        let pt_minus_rev = pt_minus.map(|pt_minus| match qs {
+            Some(qs) => pt.pdf(scene, radius, Some(qs), pt_minus),
+            None => pt.pdf_light(radius, pt_minus),
+        });
+        let qs_rev = qs.map(|qs| pt.pdf(scene, radius, pt_minus, qs));
+        let qs_minus_rev = qs
+            .zip(qs_minus)
+            .map(|(qs, qs_minus)| qs.pdf(scene, radius, Some(pt), qs_minus));
+
+        camera[t - 1].pdf_rev = pt_rev;
+        camera[t - 1].delta = false;
+        if let Some(pdf) = pt_minus_rev {
+            camera[t - 2].pdf_rev = pdf;
+        }
+        if let Some(pdf) = qs_rev {
+            light[s - 1].pdf_rev = pdf;
+            light[s - 1].delta = false;
+        }
+        if let Some(pdf) = qs_minus_rev {
+            light[s - 2].pdf_rev = pdf;
+        }
+
+        // Deltas have no density to compare, and cancel out in the ratios.
+        let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+            if !camera[i].delta && !camera[i - 1].delta && (i > 1 || paths.light_tracing) {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+            if !light[i].delta && (i == 0 || !light[i - 1].delta) {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        splats: &mut Splats,
+    ) -> Radiance {
+        let bounds = scene.world.bounding_box();
+        let center = bounds.centroid();
+        let radius = (bounds.max - center).length();
+
+        let ray = Ray::new(ray.origin, unit_vector(ray.direction));
+        let camera_pdf = scene.camera.importance_pdf(&ray);
+        let mut camera = vec![Vertex::new(
+            Kind::Camera,
+            ray.origin,
+            Vec3::new(1.0, 1.0, 1.0),
+            0.0,
+        )];
+        let pdf_direction = camera_pdf.map_or(1.0, |(_, direction)| direction);
+        self.random_walk(scene, ray, pdf_direction, true, sampler, &mut camera);
+        let paths = Subpaths {
+            light: self.light_path(scene, (center, radius), sampler),
+            camera,
+            radius,
+            light_tracing: camera_pdf.is_some(),
+        };
+
+        let mut radiance = Radiance::default();
+        for t in 1..=paths.camera.len() {
+            for s in 0..=paths.light.len() {
+                let depth = s + t;
+                if (s == 1 && t == 1) || depth < 2 || depth - 2 > self.max_depth as usize {
+                    continue;
+                }
+                if t == 1 && !paths.light_tracing {
+                    continue;
+                }
+                match self.connect(scene, &paths, s, t, sampler) {
+                    Some((contribution, Some(image))) => {
+                        let mut splat = Radiance::default();
+                        splat.add((depth - 2) as u16, contribution);
+                        splats.add(image, splat);
+                    }
+                    Some((contribution, None)) => radiance.add((depth - 2) as u16, contribution),
+                    None => (),
+                }
+            }
+        }
+        radiance
+    }
+}
+
+/// How strongly two vertices exchange light, not counting whether they can
+/// see each other.
+fn geometry_term(a: &Vertex, b: &Vertex) -> f64 {
+    let d = a.point - b.point;
+    let distance_squared = d.length_squared();
+    let d = d / distance_squared.sqrt();
+    let mut g = 1.0 / distance_squared;
+    if a.is_surface() {
+        g *= dot(a.normal, d).abs();
+    }
+    if b.is_surface() {
+        g *= dot(b.normal, d).abs();
+    }
+    g
+}
+
+/// Whether nothing lies on the straight line between two points.
+fn unoccluded(scene: &SceneView, from: Vec3, to: Vec3) -> bool {
+    let d = to - from;
+    let distance = d.length();
+    scene
+        .world
+        .hit(&Ray::new(from, d / distance), 0.001, distance - 0.001)
+        .is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        integrator::{
+            test::{estimate, test_scene},
+            PathLength, PathTracer,
+        },
+    };
+
+    #[test]
+    fn bdpt_converges_to_the_path_traced_image() {
+        let fixture = test_scene();
+        assert!(fixture.camera.importance_pdf(&Ray::default()).is_some());
+        let scene = fixture.view();
+
+        // Deep enough that where each integrator cuts paths off doesn't
+        // matter.
+        let path = PathTracer::new(PathLength {
+            max_depth: 20,
+            roulette_depth: u16::MAX,
+        });
+        let (expected, expected_error) = estimate(&path, &scene, 1);
+        let (bdpt, bdpt_error) = estimate(&Bdpt::new(20), &scene, 2);
+        let error = (expected_error.powi(2) + bdpt_error.powi(2)).sqrt();
+        assert!(
+            (expected - bdpt).abs() < 4.0 * error,
+            "{} ± {} against {} ± {}",
+            expected,
+            expected_error,
+            bdpt,
+            bdpt_error
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{ray::Ray, vec3::Vec3};
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// A box that contains nothing, and so grows to fit whatever it is
+    /// joined with.
+    pub fn empty() -> Self {
+        Self::new(
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        )
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    /// The smallest box around `points`, grown by `margin` on every side so
+    /// that flat shapes don't end up with a box of no thickness.
+    pub fn around(points: &[Vec3], margin: f64) -> Self {
+        let margin = Vec3::new(margin, margin, margin);
+        points.iter().fold(Aabb::empty(), |total, point| {
+            total.union(&Aabb::new(*point - margin, *point + margin))
+        })
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Whether the ray passes through the box between `t_min` and `t_max`,
+    /// by clipping it against each pair of slabs in turn.
+    pub fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction.component(axis);
+            let mut t0 = (self.min.component(axis) - ray.origin.component(axis)) * inverse;
+            let mut t1 = (self.max.component(axis) - ray.origin.component(axis)) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+enum Node {
+    /// Objects `order[start..start + count]`.
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    /// The left child follows its parent directly, the right child is at
+    /// `right`.
+    Interior { bounds: Aabb, right: usize },
+}
+
+/// A bounding volume hierarchy over a list of objects, referred to by their
+/// index in the list.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+
+const LEAF_SIZE: usize = 2;
+
+impl Bvh {
+    /// Builds the hierarchy by splitting objects at the median centroid
+    /// along the longest axis of their centroids' bounds.
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            order: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let objects = &mut self.order[start..end];
+        let node_bounds = objects
+            .iter()
+            .fold(Aabb::empty(), |total, object| total.union(&bounds[*object]));
+        if objects.len() <= LEAF_SIZE {
+            self.nodes.push(Node::Leaf {
+                bounds: node_bounds,
+                start,
+                count: end - start,
+            });
+            return;
+        }
+
+        let centroids = objects.iter().fold(Aabb::empty(), |total, object| {
+            let centroid = bounds[*object].centroid();
+            total.union(&Aabb::new(centroid, centroid))
+        });
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = objects.len() / 2;
+        objects.select_nth_unstable_by(middle, |a, b| {
+            let a = bounds[*a].centroid().component(axis);
+            let b = bounds[*b].centroid().component(axis);
+            a.total_cmp(&b)
+        });
+
+        let index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            bounds: node_bounds,
+            right: 0,
+        });
+        self.build(bounds, start, start + middle);
+        let right_index = self.nodes.len();
+        if let Node::Interior { right, .. } = &mut self.nodes[index] {
+            *right = right_index;
+        }
+        self.build(bounds, start + middle, end);
+    }
+
+    /// Walks the nodes the ray passes through, calling `hit` with each
+    /// object they hold and the closest distance found so far. `hit`
+    /// returns the distance to the object if the ray hits it any closer.
+    /// Every box tested is counted in `tests`.
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+        mut hit: impl FnMut(usize, f64) -> Option<f64>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut closest = t_max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            *tests += 1;
+            match &self.nodes[index] {
+                Node::Leaf {
+                    bounds,
+                    start,
+                    count,
+                } => {
+                    if !bounds.hit(ray, t_min, closest) {
+                        continue;
+                    }
+                    for object in &self.order[*start..start + count] {
+                        if let Some(t) = hit(*object, closest) {
+                            closest = t;
+                        }
+                    }
+                }
+                Node::Interior { bounds, right } => {
+                    if bounds.hit(ray, t_min, closest) {
+                        stack.push(*right);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traversal_skips_boxes_the_ray_misses() {
+        // A row of unit boxes along x, and rays down through each of them.
+        let bounds = (0..10)
+            .map(|i| {
+                let min = Vec3::new(i as f64 * 2.0, 0.0, 0.0);
+                Aabb::new(min, min + Vec3::new(1.0, 1.0, 1.0))
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::new(&bounds);
+        for (i, target) in bounds.iter().enumerate() {
+            let origin = target.centroid() + Vec3::new(0.0, 5.0, 0.0);
+            let ray = Ray::new(origin, Vec3::new(0.0, -1.0, 0.0));
+            let mut visited = Vec::new();
+            let mut tests = 0;
+            bvh.traverse(&ray, 0.0, f64::INFINITY, &mut tests, |object, _| {
+                visited.push(object);
+                None
+            });
+            assert!(visited.contains(&i));
+            assert!(visited.len() <= LEAF_SIZE);
+            assert!(tests < bounds.len());
+        }
+    }
+}
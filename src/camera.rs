@@ -1,20 +1,179 @@
 use crate::{
+    distribution::Distribution2D,
+    hit::{Hit, Hittables},
+    image::Image,
+    options::ParseOptionError,
     ray::Ray,
-    vec3::{cross, random_in_unit_disk, unit_vector, Vec3},
+    sampler::{sample_unit_disk, Sampler},
+    vec3::{cross, dot, unit_vector, Vec3},
 };
+use std::{error::Error, f64::consts::PI, str::FromStr};
 
-pub struct Camera {
+/// Turns a position on the image into a ray leaving the camera.
+///
+/// `s` and `t` run from 0 to 1 across the image, left to right and bottom to
+/// top. Returns `None` if the lens blocks the sample.
+pub trait Camera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+
+    /// For light tracing: picks a point on the lens that `point` can be
+    /// seen from, and where on the image it shows up. Cameras that light
+    /// paths can't be connected to return `None`.
+    fn sample_importance(&self, _point: Vec3, _u: (f64, f64)) -> Option<Importance> {
+        None
+    }
+
+    /// The densities with which `get_ray` would pick the origin of `ray`,
+    /// per unit lens area, and its direction, per unit solid angle. Cameras
+    /// that don't support light tracing return `None`.
+    fn importance_pdf(&self, _ray: &Ray) -> Option<(f64, f64)> {
+        None
+    }
+}
+
+/// A sampled connection from a point in the scene to the camera.
+#[derive(Debug, Clone, Copy)]
+pub struct Importance {
+    pub lens_point: Vec3,
+    /// How strongly the camera responds to light arriving at `lens_point`
+    /// from the point, normalised so it integrates to one over the image.
+    pub importance: f64,
+    /// The density of picking `lens_point`, per unit solid angle as seen
+    /// from the point.
+    pub pdf: f64,
+    /// The image position, as passed to `get_ray`.
+    pub image: (f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    Cubemap,
+}
+
+impl FromStr for Projection {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perspective" => Ok(Projection::Perspective),
+            "orthographic" => Ok(Projection::Orthographic),
+            "fisheye" => Ok(Projection::Fisheye),
+            "equirectangular" => Ok(Projection::Equirectangular),
+            "cubemap" => Ok(Projection::Cubemap),
+            _ => Err(ParseOptionError::new("projection", s)),
+        }
+    }
+}
+
+/// An orthonormal basis for a camera, with `w` pointing backwards out of the
+/// lens.
+#[derive(Debug, Clone, Copy)]
+struct Basis {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Basis {
+    fn new(look_from: Vec3, look_at: Vec3, vertical_up: Vec3) -> Self {
+        let w = unit_vector(look_from - look_at);
+        let u = unit_vector(cross(vertical_up, w));
+        let v = cross(w, u);
+        Self { u, v, w }
+    }
+}
+
+/// The shape of the lens opening, which sets the shape of out of focus
+/// highlights.
+pub enum Aperture {
+    Circle,
+    /// A regular polygon formed by `blades` straight blades, rotated by
+    /// `rotation` degrees.
+    Polygon {
+        blades: usize,
+        rotation: f64,
+    },
+    /// An image whose brightness gives how much light passes through each
+    /// part of the lens, stretched over the square around the lens, so its
+    /// corners reach past the lens radius.
+    Mask(Distribution2D),
+}
+
+impl Aperture {
+    /// An aperture shaped like `image`, which must have some light pixels.
+    pub fn mask(image: &Image) -> Result<Self, Box<dyn Error>> {
+        if image.width == 0 || image.height == 0 {
+            return Err("the aperture mask has no pixels".into());
+        }
+        let weights = image
+            .pixels
+            .iter()
+            .map(|pixel| pixel.luminance())
+            .collect::<Vec<_>>();
+        if !weights.iter().any(|&weight| weight > 0.0) {
+            return Err("the aperture mask is black, so it lets no light through".into());
+        }
+        Ok(Aperture::Mask(Distribution2D::new(
+            &weights,
+            image.width,
+            image.height,
+        )))
+    }
+
+    /// Maps a square sample to a point on the aperture, in lens radii. Shapes
+    /// fall within the unit disk, masks within the square around it.
+    pub fn sample(&self, u: (f64, f64)) -> Vec3 {
+        match self {
+            Aperture::Circle => sample_unit_disk(u),
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles fanning out from the centre, then
+                // a point uniformly within it.
+                let blades = (*blades).max(3);
+                let scaled = u.0 * blades as f64;
+                let blade = (scaled as usize).min(blades - 1);
+                let u0 = scaled - blade as f64;
+                let angle =
+                    |blade: usize| rotation.to_radians() + 2.0 * PI * blade as f64 / blades as f64;
+                let (a0, a1) = (angle(blade), angle(blade + 1));
+                let r = u0.sqrt();
+                Vec3::new(
+                    r * ((1.0 - u.1) * a0.cos() + u.1 * a1.cos()),
+                    r * ((1.0 - u.1) * a0.sin() + u.1 * a1.sin()),
+                    0.0,
+                )
+            }
+            Aperture::Mask(distribution) => {
+                let ((x, y), _) = distribution.sample_continuous(u);
+                // Image rows run from the top down.
+                Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+            }
+        }
+    }
+}
+
+/// A thin lens perspective camera.
+pub struct PerspectiveCamera {
     origin: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
     lower_left_corner: Vec3,
-    w: Vec3,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
     lens_radius: f64,
+    aspect_ratio: f64,
+    focus_point: Vec3,
+    focus_normal: Vec3,
+    aperture: Aperture,
+    cats_eye: f64,
+    tilt_shift: bool,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         look_from: Vec3,
         look_at: Vec3,
@@ -28,9 +187,7 @@ impl Camera {
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
-        let w = unit_vector(look_from - look_at);
-        let u = unit_vector(cross(vertical_up, w));
-        let v = cross(w, u);
+        let Basis { u, v, w } = Basis::new(look_from, look_at, vertical_up);
         let origin = look_from;
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
@@ -41,19 +198,583 @@ impl Camera {
             horizontal,
             vertical,
             lower_left_corner,
-            w,
             u,
             v,
+            w,
             lens_radius,
+            aspect_ratio,
+            focus_point: origin - focus_dist * w,
+            focus_normal: w,
+            aperture: Aperture::Circle,
+            cats_eye: 0.0,
+            tilt_shift: false,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    /// Clips the aperture by the lens barrel, which moves towards the edge of
+    /// the lens as the image point moves out from the centre. This darkens
+    /// the corners and turns highlights there into cat's eyes. At a strength
+    /// of one the barrel has moved a full lens radius by the image corners.
+    pub fn with_cats_eye(mut self, strength: f64) -> Self {
+        self.cats_eye = strength;
+        self
+    }
+
+    /// Tilts the plane of focus by `tilt` degrees about the camera's
+    /// horizontal axis, and shifts the image by `shift`, as a fraction of its
+    /// width and height, without turning the camera.
+    pub fn with_tilt_shift(mut self, tilt: f64, shift: (f64, f64)) -> Self {
+        let tilt = tilt.to_radians();
+        self.focus_normal = tilt.cos() * self.w + tilt.sin() * self.v;
+        self.lower_left_corner += shift.0 * self.horizontal + shift.1 * self.vertical;
+        self.tilt_shift = tilt != 0.0 || shift != (0.0, 0.0);
+        self
+    }
+
+    /// The lens and image areas that importance is spread over, with the
+    /// image measured on a plane a unit distance in front of the lens. Only
+    /// a plain round lens is supported.
+    fn importance_areas(&self) -> Option<(f64, f64)> {
+        if !matches!(self.aperture, Aperture::Circle) || self.cats_eye > 0.0 || self.tilt_shift {
+            return None;
+        }
+        let focus_dist = dot(self.origin - self.focus_point, self.w);
+        let image = self.horizontal.length() * self.vertical.length() / focus_dist.powi(2);
+        let lens = if self.lens_radius > 0.0 {
+            PI * self.lens_radius.powi(2)
+        } else {
+            // A pinhole: densities over the lens become deltas.
+            1.0
+        };
+        Some((lens, image))
+    }
+
+    /// Where a ray leaving the lens lands on the image, and the cosine of
+    /// its angle to the view direction. `None` if it misses the image.
+    fn project(&self, ray: &Ray) -> Option<((f64, f64), f64)> {
+        let direction = unit_vector(ray.direction);
+        let cosine = -dot(direction, self.w);
+        if cosine <= 0.0 {
+            return None;
+        }
+        let focus_dist = dot(self.origin - self.focus_point, self.w);
+        let focus = ray.origin + direction * (focus_dist / cosine);
+        let offset = focus - self.lower_left_corner;
+        let s = dot(offset, self.horizontal) / self.horizontal.length_squared();
+        let t = dot(offset, self.vertical) / self.vertical.length_squared();
+        ((0.0..1.0).contains(&s) && (0.0..1.0).contains(&t)).then_some(((s, t), cosine))
+    }
+}
+
+/// Camera settings in the terms a photographer would use.
+///
+/// Lengths on the sensor and lens are in millimetres, while the scene is
+/// taken to be measured in metres.
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalLens {
+    pub sensor_width: f64,
+    pub sensor_height: f64,
+    pub focal_length: f64,
+    pub f_number: f64,
+    /// Exposure time in seconds.
+    pub shutter: f64,
+    pub iso: f64,
+}
+
+impl PhysicalLens {
+    /// The vertical field of view in degrees, for an image with the given
+    /// aspect ratio filling as much of the sensor as it can.
+    pub fn vertical_fov(&self, aspect_ratio: f64) -> f64 {
+        let height = if aspect_ratio * self.sensor_height > self.sensor_width {
+            self.sensor_width / aspect_ratio
+        } else {
+            self.sensor_height
+        };
+        (2.0 * (height / (2.0 * self.focal_length)).atan()).to_degrees()
+    }
+
+    /// Diameter of the entrance pupil in scene units.
+    pub fn aperture(&self) -> f64 {
+        self.focal_length / self.f_number / 1000.0
+    }
+
+    /// How much to scale scene radiance by for these settings.
+    ///
+    /// The renderer's radiance is treated as daylight, so settings following
+    /// the sunny 16 rule (f/16 with a shutter of 1/ISO seconds) give a scale
+    /// of one. Every stop more light doubles it.
+    pub fn exposure(&self) -> f64 {
+        let sunny_16 = (1.0 / 100.0) / 16.0_f64.powi(2);
+        (self.shutter * self.iso / 100.0) / self.f_number.powi(2) / sunny_16
+    }
+}
+
+/// The distance to whatever is in the centre of the image, for focusing on.
+pub fn autofocus<'material, T>(world: &Hittables<T>, look_from: Vec3, look_at: Vec3) -> Option<f64>
+where
+    T: Hit<'material>,
+{
+    let ray = Ray::new(look_from, unit_vector(look_at - look_from));
+    world.hit(&ray, 0.001, f64::INFINITY).map(|record| record.t)
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let lens = self.aperture.sample(sampler.next_2d());
+        if self.cats_eye > 0.0 {
+            let diagonal = (self.aspect_ratio.powi(2) + 1.0).sqrt();
+            let barrel_x = self.cats_eye * (2.0 * s - 1.0) * self.aspect_ratio / diagonal;
+            let barrel_y = self.cats_eye * (2.0 * t - 1.0) / diagonal;
+            if (lens.x - barrel_x).powi(2) + (lens.y - barrel_y).powi(2) > 1.0 {
+                return None;
+            }
+        }
+        let rd = self.lens_radius * lens;
         let offset = self.u * rd.x + self.v * rd.y;
-        let direction = self.lower_left_corner + (s * self.horizontal) + (t * self.vertical)
-            - self.origin
-            - offset;
-        Ray::new(self.origin + offset, direction)
+        // Rays from every point on the lens meet where the ray through the
+        // lens centre crosses the plane of focus.
+        let target = self.lower_left_corner + (s * self.horizontal) + (t * self.vertical);
+        let centre_direction = target - self.origin;
+        let distance = dot(self.focus_point - self.origin, self.focus_normal)
+            / dot(centre_direction, self.focus_normal);
+        // A tilted plane can run parallel to, or behind, some of the rays.
+        let focus = if distance.is_finite() && distance > 0.0 {
+            self.origin + distance * centre_direction
+        } else {
+            target
+        };
+        Some(Ray::new(self.origin + offset, focus - self.origin - offset))
+    }
+
+    fn sample_importance(&self, point: Vec3, u: (f64, f64)) -> Option<Importance> {
+        let (lens_area, image_area) = self.importance_areas()?;
+        let rd = self.lens_radius * sample_unit_disk(u);
+        let lens_point = self.origin + self.u * rd.x + self.v * rd.y;
+        let to_point = point - lens_point;
+        let (image, cosine) = self.project(&Ray::new(lens_point, to_point))?;
+        Some(Importance {
+            lens_point,
+            importance: 1.0 / (image_area * lens_area * cosine.powi(4)),
+            pdf: to_point.length_squared() / (cosine * lens_area),
+            image,
+        })
+    }
+
+    fn importance_pdf(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (lens_area, image_area) = self.importance_areas()?;
+        match self.project(ray) {
+            Some((_, cosine)) => Some((1.0 / lens_area, 1.0 / (image_area * cosine.powi(3)))),
+            None => Some((0.0, 0.0)),
+        }
+    }
+}
+
+/// Parallel rays leaving a rectangle, for architectural elevations and plans.
+pub struct OrthographicCamera {
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    /// `viewport_height` is the height of the view in world units.
+    pub fn new(
+        look_from: Vec3,
+        look_at: Vec3,
+        vertical_up: Vec3,
+        viewport_height: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        let Basis { u, v, w } = Basis::new(look_from, look_at, vertical_up);
+        let horizontal = aspect_ratio * viewport_height * u;
+        let vertical = viewport_height * v;
+        Self {
+            lower_left_corner: look_from - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, s: f64, t: f64, _: &mut dyn Sampler) -> Option<Ray> {
+        let origin = self.lower_left_corner + s * self.horizontal + t * self.vertical;
+        Some(Ray::new(origin, self.direction))
+    }
+}
+
+/// An equidistant fisheye, where the angle from the view direction grows
+/// linearly with distance from the centre of the image.
+pub struct FisheyeCamera {
+    origin: Vec3,
+    basis: Basis,
+    aspect_ratio: f64,
+    half_fov: f64,
+}
+
+impl FisheyeCamera {
+    /// `fov` is the angle, in degrees, covered by the height of the image.
+    pub fn new(
+        look_from: Vec3,
+        look_at: Vec3,
+        vertical_up: Vec3,
+        fov: f64,
+        aspect_ratio: f64,
+    ) -> Self {
+        Self {
+            origin: look_from,
+            basis: Basis::new(look_from, look_at, vertical_up),
+            aspect_ratio,
+            half_fov: fov.to_radians() / 2.0,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, s: f64, t: f64, _: &mut dyn Sampler) -> Option<Ray> {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        let theta = r * self.half_fov;
+        let (cos_phi, sin_phi) = if r == 0.0 { (1.0, 0.0) } else { (x / r, y / r) };
+        let Basis { u, v, w } = self.basis;
+        let direction = theta.sin() * (cos_phi * u + sin_phi * v) - theta.cos() * w;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// A full 360 by 180 degree latitude-longitude panorama, with the view
+/// direction in the centre of the image.
+/// It needs an image twice as wide as it is tall to keep its pixels square.
+pub struct EquirectangularCamera {
+    origin: Vec3,
+    basis: Basis,
+}
+
+impl EquirectangularCamera {
+    pub fn new(look_from: Vec3, look_at: Vec3, vertical_up: Vec3) -> Self {
+        Self {
+            origin: look_from,
+            basis: Basis::new(look_from, look_at, vertical_up),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, s: f64, t: f64, _: &mut dyn Sampler) -> Option<Ray> {
+        let phi = (s - 0.5) * 2.0 * PI;
+        let elevation = (t - 0.5) * PI;
+        let Basis { u, v, w } = self.basis;
+        let direction = elevation.cos() * (phi.sin() * u - phi.cos() * w) + elevation.sin() * v;
+        Some(Ray::new(self.origin, direction))
+    }
+}
+
+/// Six 90 degree views packed into a 3 by 2 grid.
+///
+/// The top row holds the right, left and up faces and the bottom row the
+/// down, front and back faces, each seen from inside the cube.
+pub struct CubemapCamera {
+    origin: Vec3,
+    basis: Basis,
+}
+
+impl CubemapCamera {
+    pub fn new(look_from: Vec3, look_at: Vec3, vertical_up: Vec3) -> Self {
+        Self {
+            origin: look_from,
+            basis: Basis::new(look_from, look_at, vertical_up),
+        }
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn get_ray(&self, s: f64, t: f64, _: &mut dyn Sampler) -> Option<Ray> {
+        let column = ((s * 3.0) as usize).min(2);
+        let row = if t >= 0.5 { 0 } else { 1 };
+        // Position within the face, from -1 to 1.
+        let a = (s * 3.0 - column as f64) * 2.0 - 1.0;
+        let b = ((t * 2.0).fract()) * 2.0 - 1.0;
+        let Basis { u, v, w } = self.basis;
+        let forward = -w;
+        let (face, right, up) = match (row, column) {
+            (0, 0) => (u, w, v),
+            (0, 1) => (-u, forward, v),
+            (0, 2) => (v, u, w),
+            (1, 0) => (-v, u, forward),
+            (1, 1) => (forward, u, v),
+            _ => (w, -u, v),
+        };
+        Some(Ray::new(self.origin, face + a * right + b * up))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        material::{Lambertian, Material},
+        sampler::IndependentSampler,
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    /// A camera at the origin looking down -z with a lens of radius one,
+    /// focused two units away.
+    fn wide_open() -> PerspectiveCamera {
+        PerspectiveCamera::new(
+            Vec3::default(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            2.0,
+            2.0,
+        )
+    }
+
+    #[test]
+    fn projections_look_where_the_camera_points() {
+        let mut sampler = IndependentSampler::new(4);
+        let look_from = Vec3::new(1.0, 2.0, 3.0);
+        let look_at = Vec3::new(1.0, 2.0, -1.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let (right, forward) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let direction = |camera: &dyn Camera, s, t, sampler: &mut IndependentSampler| {
+            let ray = camera.get_ray(s, t, sampler).unwrap();
+            (ray.origin, unit_vector(ray.direction))
+        };
+
+        let perspective = PerspectiveCamera::new(look_from, look_at, up, 40.0, 2.0, 0.0, 4.0);
+        let orthographic = OrthographicCamera::new(look_from, look_at, up, 2.0, 2.0);
+        let fisheye = FisheyeCamera::new(look_from, look_at, up, 180.0, 2.0);
+        let panorama = EquirectangularCamera::new(look_from, look_at, up);
+        let cubemap = CubemapCamera::new(look_from, look_at, up);
+        // The cubemap's middle is an edge between faces, so it's the middle
+        // of its front face that looks ahead.
+        let centres: [(&dyn Camera, f64, f64); 5] = [
+            (&perspective, 0.5, 0.5),
+            (&orthographic, 0.5, 0.5),
+            (&fisheye, 0.5, 0.5),
+            (&panorama, 0.5, 0.5),
+            (&cubemap, 0.5, 0.25),
+        ];
+        for (camera, s, t) in centres.iter() {
+            let (origin, centre) = direction(*camera, *s, *t, &mut sampler);
+            assert!((origin - look_from).length() < 1e-9, "{:?}", origin);
+            assert!((centre - forward).length() < 1e-9, "{:?}", centre);
+        }
+
+        // Orthographic rays all run the same way, from across a rectangle
+        // the size of the view.
+        let (left, towards_left) = direction(&orthographic, 0.0, 0.5, &mut sampler);
+        let (corner, towards_corner) = direction(&orthographic, 1.0, 1.0, &mut sampler);
+        assert!((towards_left - forward).length() < 1e-9);
+        assert!((towards_corner - forward).length() < 1e-9);
+        assert!(
+            (left - (look_from - 2.0 * right)).length() < 1e-9,
+            "{:?}",
+            left
+        );
+        assert!(
+            (corner - (look_from + 2.0 * right + up)).length() < 1e-9,
+            "{:?}",
+            corner
+        );
+
+        // The panorama's top and bottom rows are its poles, and its left and
+        // right edges meet behind the camera.
+        for &s in &[0.0, 0.3, 0.9] {
+            let (_, top) = direction(&panorama, s, 1.0, &mut sampler);
+            let (_, bottom) = direction(&panorama, s, 0.0, &mut sampler);
+            assert!((top - up).length() < 1e-9, "{:?}", top);
+            assert!((bottom + up).length() < 1e-9, "{:?}", bottom);
+        }
+        let (_, behind) = direction(&panorama, 0.0, 0.5, &mut sampler);
+        assert!((behind + forward).length() < 1e-9, "{:?}", behind);
+
+        // Right, left and up along the top row, down, front and back along
+        // the bottom one.
+        let faces = [
+            (0.75, right),
+            (0.75, -right),
+            (0.75, up),
+            (0.25, -up),
+            (0.25, forward),
+            (0.25, -forward),
+        ];
+        for (index, (t, axis)) in faces.iter().enumerate() {
+            let s = ((index % 3) as f64 + 0.5) / 3.0;
+            let (_, face) = direction(&cubemap, s, *t, &mut sampler);
+            assert!((face - *axis).length() < 1e-9, "face {}: {:?}", index, face);
+        }
+    }
+
+    #[test]
+    fn lens_samples_land_on_the_aperture() {
+        let mut sampler = IndependentSampler::new(1);
+        let hexagon = Aperture::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        };
+        let apothem = (PI / 6.0).cos();
+        for _ in 0..1000 {
+            let point = Aperture::Circle.sample(sampler.next_2d());
+            assert!(point.length() <= 1.0);
+            let point = hexagon.sample(sampler.next_2d());
+            for edge in 0..6 {
+                let angle = PI / 6.0 + edge as f64 * PI / 3.0;
+                let normal = Vec3::new(angle.cos(), angle.sin(), 0.0);
+                assert!(dot(point, normal) <= apothem + 1e-9, "{:?}", point);
+            }
+        }
+
+        // Only the top left pixel lets light through, so samples land in the
+        // top left quarter of the square around the lens.
+        let dark = Vec3::default();
+        let light = Vec3::new(1.0, 1.0, 1.0);
+        let mask = Image {
+            width: 2,
+            height: 2,
+            pixels: vec![light, dark, dark, dark],
+        };
+        let mask = Aperture::mask(&mask).unwrap();
+        for _ in 0..1000 {
+            let point = mask.sample(sampler.next_2d());
+            assert!((-1.0..=0.0).contains(&point.x), "{:?}", point);
+            assert!((0.0..=1.0).contains(&point.y), "{:?}", point);
+        }
+
+        let empty = Image {
+            width: 0,
+            height: 3,
+            pixels: Vec::new(),
+        };
+        assert!(Aperture::mask(&empty).is_err());
+        let black = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![dark, dark],
+        };
+        assert!(Aperture::mask(&black).is_err());
+    }
+
+    #[test]
+    fn the_barrel_clips_the_lens_towards_the_corners() {
+        let camera = wide_open().with_cats_eye(1.0);
+        let mut sampler = IndependentSampler::new(2);
+        for _ in 0..1000 {
+            assert!(camera.get_ray(0.5, 0.5, &mut sampler).is_some());
+        }
+        // In the top right corner the barrel has moved up and right, so
+        // some samples are blocked and the rest lean that way.
+        let mut blocked = 0;
+        let mut offset = Vec3::default();
+        for _ in 0..1000 {
+            match camera.get_ray(1.0, 1.0, &mut sampler) {
+                Some(ray) => offset += ray.origin,
+                None => blocked += 1,
+            }
+        }
+        assert!(blocked > 100 && blocked < 900, "{}", blocked);
+        assert!(offset.x > 0.0 && offset.y > 0.0, "{:?}", offset);
+    }
+
+    #[test]
+    fn tilt_tilts_the_plane_of_focus_and_shift_moves_the_image() {
+        let mut sampler = IndependentSampler::new(3);
+        // Every ray through an image point meets the others on the plane of
+        // focus, which tilts about the horizontal axis.
+        let tilt = 30.0_f64;
+        let normal = Vec3::new(0.0, tilt.to_radians().sin(), tilt.to_radians().cos());
+        let camera = wide_open().with_tilt_shift(tilt, (0.0, 0.0));
+        let mut depths = Vec::new();
+        for &(s, t) in &[(0.5, 0.2), (0.5, 0.8), (0.1, 0.5)] {
+            let focus = |ray: Ray| ray.origin + ray.direction;
+            let first = focus(camera.get_ray(s, t, &mut sampler).unwrap());
+            for _ in 0..10 {
+                let other = focus(camera.get_ray(s, t, &mut sampler).unwrap());
+                assert!((other - first).length() < 1e-9);
+            }
+            assert!(dot(first - Vec3::new(0.0, 0.0, -2.0), normal).abs() < 1e-9);
+            depths.push(first.z);
+        }
+        // Tilted up, the plane reaches further away higher in the image.
+        assert!(depths[1] < depths[0] - 0.5, "{:?}", depths);
+
+        // Shifting by a quarter of the width looks where a quarter of the
+        // way further right would have, without turning the camera.
+        let pinhole = || {
+            PerspectiveCamera::new(
+                Vec3::default(),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                90.0,
+                1.0,
+                0.0,
+                2.0,
+            )
+        };
+        let shifted = pinhole().with_tilt_shift(0.0, (0.25, 0.0));
+        let shifted = shifted.get_ray(0.5, 0.5, &mut sampler).unwrap();
+        let plain = pinhole().get_ray(0.75, 0.5, &mut sampler).unwrap();
+        assert!((unit_vector(shifted.direction) - unit_vector(plain.direction)).length() < 1e-9);
+    }
+
+    #[test]
+    fn physical_lenses_follow_photographic_conventions() {
+        let lens = PhysicalLens {
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            focal_length: 50.0,
+            f_number: 16.0,
+            shutter: 1.0 / 100.0,
+            iso: 100.0,
+        };
+        // A 3:2 image fills the full frame sensor, a 2:1 image is limited
+        // by its width.
+        let expected = (2.0 * (12.0_f64 / 50.0).atan()).to_degrees();
+        assert!((lens.vertical_fov(1.5) - expected).abs() < 1e-9);
+        let expected = (2.0 * (9.0_f64 / 50.0).atan()).to_degrees();
+        assert!((lens.vertical_fov(2.0) - expected).abs() < 1e-9);
+        assert!((lens.aperture() - 0.05 / 16.0).abs() < 1e-12);
+
+        // Sunny 16 is an exposure of one, and each stop doubles it.
+        assert!((lens.exposure() - 1.0).abs() < 1e-9);
+        let slower = PhysicalLens {
+            shutter: 1.0 / 50.0,
+            ..lens
+        };
+        assert!((slower.exposure() - 2.0).abs() < 1e-9);
+        let wider = PhysicalLens {
+            f_number: 8.0,
+            iso: 200.0,
+            ..lens
+        };
+        assert!((wider.exposure() - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn autofocus_finds_what_is_in_the_middle() {
+        let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let material = Rc::new(RefCell::new(material));
+        let mut world = Hittables::new();
+        world.add(Sphere::new(
+            Vec3::new(0.0, 0.0, -10.0),
+            1.0,
+            material.clone(),
+        ));
+        world.add(Sphere::new(Vec3::new(5.0, 0.0, -3.0), 1.0, material));
+        let look_from = Vec3::default();
+        let distance = autofocus(&world, look_from, Vec3::new(0.0, 0.0, -1.0));
+        assert!((distance.unwrap() - 9.0).abs() < 1e-9);
+        assert!(autofocus(&world, look_from, Vec3::new(0.0, 1.0, 0.0)).is_none());
     }
 }
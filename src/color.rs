@@ -0,0 +1,323 @@
+use crate::{options::ParseOptionError, vec3::Vec3};
+use std::str::FromStr;
+
+/// The chromaticities of an RGB color space's primaries and white point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Primaries {
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white: (f64, f64),
+}
+
+const D65: (f64, f64) = (0.3127, 0.3290);
+
+/// Rec. 709, which sRGB shares.
+const SRGB: Primaries = Primaries {
+    red: (0.64, 0.33),
+    green: (0.30, 0.60),
+    blue: (0.15, 0.06),
+    white: D65,
+};
+
+const DISPLAY_P3: Primaries = Primaries {
+    red: (0.680, 0.320),
+    green: (0.265, 0.690),
+    blue: (0.150, 0.060),
+    white: D65,
+};
+
+const REC_2020: Primaries = Primaries {
+    red: (0.708, 0.292),
+    green: (0.170, 0.797),
+    blue: (0.131, 0.046),
+    white: D65,
+};
+
+/// ACES AP1, with the ACES white point near D60.
+const ACES_CG: Primaries = Primaries {
+    red: (0.713, 0.293),
+    green: (0.165, 0.830),
+    blue: (0.128, 0.044),
+    white: (0.32168, 0.33767),
+};
+
+/// The linear RGB space colors are rendered in.
+///
+/// Material and sky colors are authored in sRGB and converted into the
+/// working space when the scene is built. Wider spaces make saturated
+/// colors mix more like they would physically, at the cost of the odd out
+/// of gamut value once converted back for display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WorkingSpace {
+    LinearSrgb,
+    AcesCg,
+    Rec2020,
+}
+
+impl WorkingSpace {
+    pub fn primaries(self) -> Primaries {
+        match self {
+            WorkingSpace::LinearSrgb => SRGB,
+            WorkingSpace::AcesCg => ACES_CG,
+            WorkingSpace::Rec2020 => REC_2020,
+        }
+    }
+
+    /// Converts a linear sRGB color into this space.
+    pub fn convert_from_srgb(self, color: Vec3) -> Vec3 {
+        Matrix::conversion(SRGB, self.primaries()) * color
+    }
+
+    /// Converts a CIE XYZ color, relative to a D65 white, into this space.
+    pub fn convert_from_xyz(self, color: Vec3) -> Vec3 {
+        self.convert_from_srgb(Matrix::rgb_to_xyz(SRGB).inverse() * color)
+    }
+}
+
+impl FromStr for WorkingSpace {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" | "linear-srgb" => Ok(WorkingSpace::LinearSrgb),
+            "acescg" => Ok(WorkingSpace::AcesCg),
+            "rec2020" => Ok(WorkingSpace::Rec2020),
+            _ => Err(ParseOptionError::new("working space", s)),
+        }
+    }
+}
+
+/// The display space images are written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputSpace {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+}
+
+impl OutputSpace {
+    pub fn primaries(self) -> Primaries {
+        match self {
+            OutputSpace::Srgb => SRGB,
+            OutputSpace::DisplayP3 => DISPLAY_P3,
+            OutputSpace::Rec2020 => REC_2020,
+        }
+    }
+
+    /// Applies the transfer function, taking linear values to the encoded
+    /// values stored in integer image formats.
+    pub fn encode(self, linear: f64) -> f64 {
+        let linear = linear.clamp(0.0, 1.0);
+        match self {
+            // Display P3 uses the sRGB curve.
+            OutputSpace::Srgb | OutputSpace::DisplayP3 => {
+                if linear <= 0.0031308 {
+                    12.92 * linear
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            OutputSpace::Rec2020 => {
+                if linear < 0.018053968510807 {
+                    4.5 * linear
+                } else {
+                    1.09929682680944 * linear.powf(0.45) - 0.09929682680944
+                }
+            }
+        }
+    }
+
+    /// The ITU-T H.273 color primaries and transfer characteristics codes
+    /// for the space, as a PNG cICP chunk records them.
+    pub fn cicp(self) -> (u8, u8) {
+        match self {
+            OutputSpace::Srgb => (1, 13),
+            OutputSpace::DisplayP3 => (12, 13),
+            OutputSpace::Rec2020 => (9, 14),
+        }
+    }
+}
+
+impl FromStr for OutputSpace {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(OutputSpace::Srgb),
+            "p3" | "display-p3" => Ok(OutputSpace::DisplayP3),
+            "rec2020" => Ok(OutputSpace::Rec2020),
+            _ => Err(ParseOptionError::new("output space", s)),
+        }
+    }
+}
+
+/// The curve taking linear values to those stored in integer image formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// A plain square root, as the renderer has always written images.
+    Gamma2,
+    /// The output space's own transfer function, which is what viewers
+    /// expect of a tagged image.
+    Standard,
+}
+
+impl Encoding {
+    pub fn encode(self, space: OutputSpace, linear: f64) -> f64 {
+        match self {
+            Encoding::Gamma2 => linear.clamp(0.0, 1.0).sqrt(),
+            Encoding::Standard => space.encode(linear),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gamma2" => Ok(Encoding::Gamma2),
+            "standard" => Ok(Encoding::Standard),
+            _ => Err(ParseOptionError::new("encoding", s)),
+        }
+    }
+}
+
+/// A 3x3 matrix acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix(pub [[f64; 3]; 3]);
+
+impl Matrix {
+    /// Converts linear colors between two spaces, adapting the white point
+    /// with the Bradford transform when the spaces' whites differ.
+    pub fn conversion(from: Primaries, to: Primaries) -> Self {
+        let adapt = if from.white == to.white {
+            Matrix::identity()
+        } else {
+            let source = BRADFORD * xyz(from.white);
+            let destination = BRADFORD * xyz(to.white);
+            let scale = Matrix([
+                [destination.x / source.x, 0.0, 0.0],
+                [0.0, destination.y / source.y, 0.0],
+                [0.0, 0.0, destination.z / source.z],
+            ]);
+            BRADFORD.inverse() * scale * BRADFORD
+        };
+        Matrix::rgb_to_xyz(to).inverse() * adapt * Matrix::rgb_to_xyz(from)
+    }
+
+    fn rgb_to_xyz(primaries: Primaries) -> Self {
+        let (r, g, b) = (
+            xyz(primaries.red),
+            xyz(primaries.green),
+            xyz(primaries.blue),
+        );
+        let columns = Matrix([[r.x, g.x, b.x], [r.y, g.y, b.y], [r.z, g.z, b.z]]);
+        // Scale the primaries so that they add up to the white point.
+        let scale = columns.inverse() * xyz(primaries.white);
+        let Matrix(m) = columns;
+        Matrix([
+            [m[0][0] * scale.x, m[0][1] * scale.y, m[0][2] * scale.z],
+            [m[1][0] * scale.x, m[1][1] * scale.y, m[1][2] * scale.z],
+            [m[2][0] * scale.x, m[2][1] * scale.y, m[2][2] * scale.z],
+        ])
+    }
+
+    fn identity() -> Self {
+        Matrix([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    fn inverse(&self) -> Self {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let determinant =
+            m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        Matrix(adjugate.map(|row| row.map(|value| value / determinant)))
+    }
+}
+
+impl std::ops::Mul<Vec3> for Matrix {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl std::ops::Mul for Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: Matrix) -> Matrix {
+        let mut product = [[0.0; 3]; 3];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Matrix(product)
+    }
+}
+
+const BRADFORD: Matrix = Matrix([
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+]);
+
+/// The XYZ color with chromaticity `(x, y)` and unit luminance.
+fn xyz((x, y): (f64, f64)) -> Vec3 {
+    Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn srgb_converts_to_known_rec2020() {
+        // The standard BT.2087 matrix.
+        let matrix = Matrix::conversion(SRGB, REC_2020);
+        let expected = [
+            [0.6274, 0.3293, 0.0433],
+            [0.0691, 0.9195, 0.0114],
+            [0.0164, 0.0880, 0.8956],
+        ];
+        for (row, expected) in matrix.0.iter().zip(expected.iter()) {
+            for (value, expected) in row.iter().zip(expected.iter()) {
+                assert!((value - expected).abs() < 1e-3, "{:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn white_stays_white_across_white_points() {
+        let white = WorkingSpace::AcesCg.convert_from_srgb(Vec3::new(1.0, 1.0, 1.0));
+        for channel in &[white.x, white.y, white.z] {
+            assert!((channel - 1.0).abs() < 1e-6, "{:?}", white);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{
+    film::Splats,
+    hit::{Hit, HitRecord},
+    integrator::{Integrator, PathTracer, SceneView},
+    ray::{Radiance, Ray},
+    sampler::{sample_unit_vector, Sampler},
+    vec3::{unit_vector, Vec3},
+};
+
+/// Bounce counts at and above this show as the hottest color.
+const HOT_BOUNCES: f64 = 10.0;
+
+/// Intersection test counts at and above this show as the hottest color.
+const HOT_TESTS: f64 = 100.0;
+
+/// Distance at which the depth view is mid grey.
+const DEPTH_SCALE: f64 = 10.0;
+
+/// The albedo every surface gets in clay renders.
+const CLAY_ALBEDO: f64 = 0.7;
+
+/// What the first surface a camera ray hits looks like, with no lighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Geometry {
+    /// Normals mapped from `[-1, 1]` to `[0, 1]`.
+    Normal,
+    /// White up close, fading towards black with distance.
+    Depth,
+    Uv,
+    /// Surfaces that aren't triangles show black.
+    Barycentric,
+    Albedo,
+}
+
+pub struct GeometryIntegrator {
+    geometry: Geometry,
+}
+
+impl GeometryIntegrator {
+    pub fn new(geometry: Geometry) -> Self {
+        Self { geometry }
+    }
+}
+
+impl Integrator for GeometryIntegrator {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        _: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let record = match scene.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(record) => record,
+            None => return Radiance::default(),
+        };
+        let color = match self.geometry {
+            Geometry::Normal => 0.5 * (record.normal + Vec3::new(1.0, 1.0, 1.0)),
+            Geometry::Depth => {
+                let depth = record.t * ray.direction.length();
+                let shade = DEPTH_SCALE / (DEPTH_SCALE + depth);
+                Vec3::new(shade, shade, shade)
+            }
+            Geometry::Uv => Vec3::new(record.uv.0, record.uv.1, 0.0),
+            Geometry::Barycentric => record.barycentric.unwrap_or_default(),
+            Geometry::Albedo => record.material.borrow().albedo(&record),
+        };
+        lit(color)
+    }
+}
+
+/// Ambient occlusion: white where a cosine-weighted ray from the surface
+/// escapes without hitting anything within `radius`, black where it doesn't.
+pub struct AmbientOcclusion {
+    radius: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let shade = match scene.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(record) if occluded(scene.world, &record, self.radius, sampler) => 0.0,
+            _ => 1.0,
+        };
+        lit(Vec3::new(shade, shade, shade))
+    }
+}
+
+/// A quick preview that ignores materials: every surface is the same
+/// neutral diffuse grey, lit by a uniform white sky and darkened by ambient
+/// occlusion within `radius`.
+pub struct Clay {
+    radius: f64,
+}
+
+impl Clay {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Integrator for Clay {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let shade = match scene.world.hit(ray, 0.001, f64::INFINITY) {
+            Some(record) if occluded(scene.world, &record, self.radius, sampler) => 0.0,
+            Some(_) => CLAY_ALBEDO,
+            None => 1.0,
+        };
+        lit(Vec3::new(shade, shade, shade))
+    }
+}
+
+/// Whether a cosine-weighted ray leaving the surface hits anything within
+/// `radius`.
+fn occluded<'material>(
+    world: &dyn Hit<'material>,
+    record: &HitRecord,
+    radius: f64,
+    sampler: &mut dyn Sampler,
+) -> bool {
+    let direction = record.normal + sample_unit_vector(sampler.next_2d());
+    let occluder = Ray::new(record.point, unit_vector(direction));
+    world.hit(&occluder, 0.001, radius).is_some()
+}
+
+/// A heatmap of how many bounding boxes and objects were tested to find
+/// what each camera ray hits.
+pub struct BvhCost;
+
+impl Integrator for BvhCost {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        _: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let mut tests = 0;
+        scene
+            .world
+            .hit_counting(ray, 0.001, f64::INFINITY, &mut tests);
+        lit(heat(tests as f64 / HOT_TESTS))
+    }
+}
+
+/// A heatmap of how many times paths bounced before they escaped or ended.
+pub struct BounceCount {
+    tracer: PathTracer,
+}
+
+impl BounceCount {
+    pub fn new(tracer: PathTracer) -> Self {
+        Self { tracer }
+    }
+}
+
+impl Integrator for BounceCount {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let (_, bounces) = self.tracer.trace(ray, scene, sampler);
+        lit(heat(bounces as f64 / HOT_BOUNCES))
+    }
+}
+
+/// Debug colors go straight to the camera.
+fn lit(color: Vec3) -> Radiance {
+    Radiance {
+        direct: color,
+        indirect: Vec3::default(),
+    }
+}
+
+/// Maps `[0, 1]` through blue, cyan, green and yellow to red.
+fn heat(t: f64) -> Vec3 {
+    let stops = [
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(0.0, 1.0, 1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    ];
+    let x = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+    let i = (x as usize).min(stops.len() - 2);
+    let f = x - i as f64;
+    (1.0 - f) * stops[i] + f * stops[i + 1]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        color::WorkingSpace,
+        hit::Hittables,
+        integrator::PathLength,
+        light_sampler::UniformLightSampler,
+        material::{Lambertian, Material},
+        ray::Sky,
+        sampler::IndependentSampler,
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    /// A sphere of the given size at the origin.
+    fn ball(radius: f64) -> Sphere<'static> {
+        let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.2, 0.4, 0.6)));
+        Sphere::new(Vec3::default(), radius, Rc::new(RefCell::new(material)))
+    }
+
+    /// What `integrator` sees along `ray` on average over `samples`.
+    fn render(
+        integrator: &dyn Integrator,
+        world: &Hittables<Sphere>,
+        ray: Ray,
+        samples: usize,
+    ) -> Vec3 {
+        let sky = Sky::new(WorkingSpace::LinearSrgb);
+        let camera = PerspectiveCamera::new(
+            ray.origin,
+            ray.origin + ray.direction,
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let scene = SceneView {
+            world,
+            sky: &sky,
+            lights: &[],
+            light_sampler: &UniformLightSampler::new(0),
+            camera: &camera,
+        };
+        let mut sampler = IndependentSampler::new(1);
+        let mut splats = Splats::new(1, 1);
+        let mut sum = Vec3::default();
+        for _ in 0..samples {
+            sum += integrator
+                .radiance(&ray, &scene, &mut sampler, &mut splats)
+                .total();
+        }
+        sum / samples as f64
+    }
+
+    #[test]
+    fn debug_views_show_what_the_camera_hits() {
+        let mut world = Hittables::new();
+        world.add(ball(1.0));
+        let hit = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let miss = Ray::new(Vec3::new(0.0, 5.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let view = |geometry, ray| render(&GeometryIntegrator::new(geometry), &world, ray, 1);
+
+        assert_eq!(view(Geometry::Normal, hit), Vec3::new(0.5, 0.5, 1.0));
+        let shade = DEPTH_SCALE / (DEPTH_SCALE + 4.0);
+        assert!((view(Geometry::Depth, hit) - Vec3::new(shade, shade, shade)).length() < 1e-9);
+        assert_eq!(view(Geometry::Albedo, hit), Vec3::new(0.2, 0.4, 0.6));
+        assert_eq!(view(Geometry::Barycentric, hit), Vec3::default());
+        for geometry in [Geometry::Normal, Geometry::Depth, Geometry::Albedo] {
+            assert_eq!(view(geometry, miss), Vec3::default());
+        }
+
+        // Nothing is tested in an empty world, every sphere is in a long
+        // list of them.
+        let blue = Vec3::new(0.0, 0.0, 1.0);
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(render(&BvhCost, &Hittables::new(), hit, 1), blue);
+        let mut crowd = Hittables::new();
+        for _ in 0..HOT_TESTS as usize {
+            crowd.add(ball(1.0));
+        }
+        assert_eq!(render(&BvhCost, &crowd, hit, 1), red);
+
+        // Paths escape straight away, or bounce around inside a ball until
+        // they reach the maximum depth.
+        let bounces = BounceCount::new(PathTracer::new(PathLength {
+            max_depth: HOT_BOUNCES as u16,
+            roulette_depth: u16::MAX,
+        }));
+        assert_eq!(render(&bounces, &world, miss, 1), blue);
+        let inside = Ray::new(Vec3::default(), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(render(&bounces, &world, inside, 10), red);
+    }
+
+    #[test]
+    fn occlusion_darkens_enclosed_surfaces() {
+        let mut world = Hittables::new();
+        world.add(ball(1.0));
+        let outside = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let inside = Ray::new(Vec3::default(), Vec3::new(0.0, 0.0, -1.0));
+        let white = Vec3::new(1.0, 1.0, 1.0);
+
+        // Nothing is above the outside of the ball, while every ray from its
+        // inside hits the far wall, unless the radius stops short of it.
+        let occlusion = AmbientOcclusion::new(10.0);
+        assert_eq!(render(&occlusion, &world, outside, 100), white);
+        assert_eq!(render(&occlusion, &world, inside, 100), Vec3::default());
+        let short = AmbientOcclusion::new(1e-6);
+        assert_eq!(render(&short, &world, inside, 100), white);
+
+        // Clay shows the sky as white and surfaces in a uniform grey, whatever
+        // their material.
+        let clay = Clay::new(10.0);
+        let grey = CLAY_ALBEDO * white;
+        assert!((render(&clay, &world, outside, 100) - grey).length() < 1e-9);
+        assert_eq!(render(&clay, &world, inside, 100), Vec3::default());
+        let miss = Ray::new(Vec3::new(0.0, 5.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(render(&clay, &world, miss, 1), white);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::vec3::{dot, unit_vector, Vec3};
+
+/// First-hit feature buffers used to decide which pixels belong together.
+pub struct Features {
+    pub albedo: Vec<Vec3>,
+    pub normal: Vec<Vec3>,
+    pub depth: Vec<f64>,
+}
+
+const ITERATIONS: usize = 5;
+const SIGMA_LUMINANCE: f64 = 4.0;
+const SIGMA_NORMAL: i32 = 128;
+const SIGMA_DEPTH: f64 = 0.05;
+const SIGMA_ALBEDO: f64 = 0.1;
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Denoises a rendered image with an edge-avoiding à-trous wavelet filter,
+/// along the lines of SVGF.
+///
+/// The albedo is divided out first so texture and material detail isn't
+/// blurred, then the remaining irradiance is filtered with a widening
+/// 5x5 kernel. Neighbours only contribute if their normal, depth and albedo
+/// match, and if their luminance is within a few standard deviations given
+/// the pixel's sample variance. `variance` is the variance of each pixel's
+/// mean luminance, so pixels that have converged are left mostly alone.
+pub fn denoise(
+    width: usize,
+    height: usize,
+    color: &[Vec3],
+    variance: &[f64],
+    features: &Features,
+) -> Vec<Vec3> {
+    let albedo = features
+        .albedo
+        .iter()
+        .map(|albedo| Vec3::new(albedo.x.max(1e-3), albedo.y.max(1e-3), albedo.z.max(1e-3)))
+        .collect::<Vec<_>>();
+    let mut irradiance = color
+        .iter()
+        .zip(&albedo)
+        .map(|(color, albedo)| color / albedo)
+        .collect::<Vec<_>>();
+    let mut variance = variance
+        .iter()
+        .zip(&albedo)
+        .map(|(variance, albedo)| variance / albedo.luminance().powi(2))
+        .collect::<Vec<_>>();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let blurred = blur_variance(width, height, &variance);
+        let mut next_irradiance = vec![Vec3::default(); irradiance.len()];
+        let mut next_variance = vec![0.0; variance.len()];
+        for j in 0..height {
+            for i in 0..width {
+                let p = j * width + i;
+                let luminance_p = irradiance[p].luminance();
+                let luminance_scale = SIGMA_LUMINANCE * blurred[p].max(0.0).sqrt() + 1e-6;
+                let mut sum = Vec3::default();
+                let mut sum_variance = 0.0;
+                let mut total = 0.0;
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let x = i as isize + (dx as isize - 2) * step;
+                        let y = j as isize + (dy as isize - 2) * step;
+                        if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+                            continue;
+                        }
+                        let q = y as usize * width + x as usize;
+                        let distance =
+                            (((x - i as isize).pow(2) + (y - j as isize).pow(2)) as f64).sqrt();
+                        let luminance =
+                            (luminance_p - irradiance[q].luminance()).abs() / luminance_scale;
+                        let depth = depth_weight(features.depth[p], features.depth[q], distance);
+                        let albedo_distance = (features.albedo[p] - features.albedo[q]).length();
+                        let weight = kx
+                            * ky
+                            * normal_weight(features.normal[p], features.normal[q])
+                            * depth
+                            * (-albedo_distance / SIGMA_ALBEDO).exp()
+                            * (-luminance).exp();
+                        sum += weight * irradiance[q];
+                        sum_variance += weight * weight * variance[q];
+                        total += weight;
+                    }
+                }
+                // Pixels with nothing that matches them, not even themselves
+                // once tiny weights underflow, are left as they were.
+                if total > 0.0 {
+                    next_irradiance[p] = sum / total;
+                    next_variance[p] = sum_variance / (total * total);
+                } else {
+                    next_irradiance[p] = irradiance[p];
+                    next_variance[p] = variance[p];
+                }
+            }
+        }
+        irradiance = next_irradiance;
+        variance = next_variance;
+    }
+
+    irradiance
+        .iter()
+        .zip(&albedo)
+        .map(|(irradiance, albedo)| irradiance * albedo)
+        .collect()
+}
+
+fn normal_weight(p: Vec3, q: Vec3) -> f64 {
+    // Both pixels missing the scene (zero normals) count as the same surface.
+    if p.near_zero() && q.near_zero() {
+        return 1.0;
+    }
+    if p.near_zero() || q.near_zero() {
+        return 0.0;
+    }
+    // Normals averaged over a pixel come out short where it straddles an
+    // edge.
+    dot(unit_vector(p), unit_vector(q))
+        .max(0.0)
+        .powi(SIGMA_NORMAL)
+}
+
+fn depth_weight(p: f64, q: f64, distance: f64) -> f64 {
+    if distance == 0.0 {
+        return 1.0;
+    }
+    (-(p - q).abs() / (SIGMA_DEPTH * p.max(q).max(1e-3) * distance)).exp()
+}
+
+/// A 3x3 Gaussian over the variance, which is itself a noisy estimate.
+fn blur_variance(width: usize, height: usize, variance: &[f64]) -> Vec<f64> {
+    let kernel = [0.25, 0.5, 0.25];
+    let mut blurred = vec![0.0; variance.len()];
+    for j in 0..height {
+        for i in 0..width {
+            let mut sum = 0.0;
+            let mut total = 0.0;
+            for (dy, ky) in kernel.iter().enumerate() {
+                for (dx, kx) in kernel.iter().enumerate() {
+                    let x = i as isize + dx as isize - 1;
+                    let y = j as isize + dy as isize - 1;
+                    if x < 0 || y < 0 || x >= width as isize || y >= height as isize {
+                        continue;
+                    }
+                    sum += kx * ky * variance[y as usize * width + x as usize];
+                    total += kx * ky;
+                }
+            }
+            let p = j * width + i;
+            blurred[p] = if total > 0.0 {
+                sum / total
+            } else {
+                variance[p]
+            };
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod test {
+    use super::{denoise, Features};
+    use crate::vec3::Vec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn denoise_should_smooth_noise_but_keep_edges() {
+        let (width, height) = (32, 32);
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut color = Vec::new();
+        let mut normal = Vec::new();
+        for _ in 0..height {
+            for i in 0..width {
+                let (base, n) = if i < width / 2 {
+                    (0.2, Vec3::new(0.0, 1.0, 0.0))
+                } else {
+                    (0.8, Vec3::new(1.0, 0.0, 0.0))
+                };
+                let noisy = base + rng.gen_range(-0.1..0.1);
+                color.push(Vec3::new(noisy, noisy, noisy));
+                normal.push(n);
+            }
+        }
+        let features = Features {
+            albedo: vec![Vec3::new(1.0, 1.0, 1.0); width * height],
+            normal,
+            depth: vec![1.0; width * height],
+        };
+        let variance = vec![0.01 / 3.0; width * height];
+        let denoised = denoise(width, height, &color, &variance, &features);
+
+        let error = |image: &[Vec3]| {
+            image
+                .iter()
+                .enumerate()
+                .map(|(index, pixel)| {
+                    let base = if index % width < width / 2 { 0.2 } else { 0.8 };
+                    (pixel.x - base).powi(2)
+                })
+                .sum::<f64>()
+        };
+        assert!(error(&denoised) < error(&color) / 10.0);
+        let row = 16 * width;
+        assert!((denoised[row + width / 2 - 1].x - 0.2).abs() < 0.05);
+        assert!((denoised[row + width / 2].x - 0.8).abs() < 0.05);
+    }
+
+    #[test]
+    fn short_normals_on_edges_still_count_as_matching() {
+        // Normals averaged across a silhouette, nearly cancelling out.
+        let (width, height) = (8, 8);
+        let color = (0..width * height)
+            .map(|index| Vec3::new(1.0, 1.0, 1.0) * (index % 2) as f64)
+            .collect::<Vec<_>>();
+        let features = Features {
+            albedo: vec![Vec3::new(1.0, 1.0, 1.0); width * height],
+            normal: vec![Vec3::new(0.01, 0.0, 0.0); width * height],
+            depth: vec![1.0; width * height],
+        };
+        let variance = vec![0.25; width * height];
+        let denoised = denoise(width, height, &color, &variance, &features);
+        for pixel in denoised {
+            assert!((pixel.x - 0.5).abs() < 0.2, "{:?}", pixel);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    bvh::Aabb,
+    hit::{face_normal, tangent_frame, Hit, HitRecord},
+    material::Material,
+    ray::Ray,
+    sampler::sample_unit_disk,
+    shape::{Shape, ShapeSample},
+    vec3::{dot, perpendicular, unit_vector, Vec3},
+};
+use std::{cell::RefCell, f64::consts::PI, rc::Rc};
+
+/// A flat circle, facing the way its normal points.
+pub struct Disk<'material> {
+    center: Vec3,
+    normal: Vec3,
+    radius: f64,
+    /// Directions across the disk, for its surface coordinates and for
+    /// placing samples.
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Rc<RefCell<Box<dyn Material + 'material>>>,
+}
+
+impl<'material> Disk<'material> {
+    pub fn new(
+        center: Vec3,
+        normal: Vec3,
+        radius: f64,
+        material: Rc<RefCell<Box<dyn Material + 'material>>>,
+    ) -> Self {
+        let normal = unit_vector(normal);
+        let (tangent, bitangent) = perpendicular(normal);
+        Self {
+            center,
+            normal,
+            radius,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl<'material> Hit<'material> for Disk<'material> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        let denominator = dot(self.normal, ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = dot(self.normal, self.center - ray.origin) / denominator;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let point = ray.at(t);
+        let offset = point - self.center;
+        if offset.length_squared() > self.radius.powi(2) {
+            return None;
+        }
+        let (x, y) = (dot(offset, self.tangent), dot(offset, self.bitangent));
+        let (front_face, normal) = face_normal(ray, self.normal);
+        let (tangent, bitangent) =
+            tangent_frame(self.normal, x * self.bitangent - y * self.tangent, offset);
+        Some(HitRecord {
+            point,
+            normal,
+            t,
+            front_face,
+            // Round the rim, then out from the middle.
+            uv: (
+                (y.atan2(x) + PI) / (2.0 * PI),
+                offset.length() / self.radius,
+            ),
+            barycentric: None,
+            material: self.material.clone(),
+            light: None,
+            tangent,
+            bitangent,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // How far the rim reaches along each axis.
+        let extent = |axis: f64| self.radius * (1.0 - axis.powi(2)).max(0.0).sqrt();
+        let extent = Vec3::new(
+            extent(self.normal.x),
+            extent(self.normal.y),
+            extent(self.normal.z),
+        );
+        Aabb::around(&[self.center - extent, self.center + extent], 1e-4)
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        if self.material.borrow().is_specular() {
+            vec![self.bounding_box()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl<'material> Shape<'material> for Disk<'material> {
+    fn area(&self) -> f64 {
+        PI * self.radius.powi(2)
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        let disk = self.radius * sample_unit_disk(u);
+        ShapeSample {
+            point: self.center + disk.x * self.tangent + disk.y * self.bitangent,
+            normal: self.normal,
+            pdf: 1.0 / self.area(),
+        }
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        (self.normal, 1.0)
+    }
+}
@@ -0,0 +1,92 @@
+/// A piecewise-constant 1D distribution that can be sampled in proportion
+/// to its values.
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: Vec<f64>) -> Self {
+        let count = function.len();
+        let mut cdf = vec![0.0; count + 1];
+        for i in 0..count {
+            cdf[i + 1] = cdf[i] + function[i].abs() / count as f64;
+        }
+        let integral = cdf[count];
+        for (i, value) in cdf.iter_mut().enumerate().skip(1) {
+            *value = if integral == 0.0 {
+                i as f64 / count as f64
+            } else {
+                *value / integral
+            };
+        }
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Samples a position in `[0, 1)` along with its density.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64) {
+        let offset = self.find_interval(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = if self.integral > 0.0 {
+            self.function[offset].abs() / self.integral
+        } else {
+            1.0
+        };
+        let x = (offset as f64 + du) / self.function.len() as f64;
+        (x.min(1.0 - f64::EPSILON), pdf)
+    }
+
+    /// Samples one of the values' indices in proportion to the value.
+    pub fn sample_discrete(&self, u: f64) -> usize {
+        self.find_interval(u)
+    }
+
+    /// The chance of `sample_discrete` picking `index`.
+    pub fn discrete_pmf(&self, index: usize) -> f64 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+
+    fn find_interval(&self, u: f64) -> usize {
+        let index = self.cdf.partition_point(|value| *value <= u);
+        index.saturating_sub(1).min(self.function.len() - 1)
+    }
+}
+
+/// A piecewise-constant 2D distribution over `[0, 1)^2`, sampled by picking a
+/// row from the marginal distribution and then a column within it.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `function` holds `height` rows of `width` values.
+    pub fn new(function: &[f64], width: usize, height: usize) -> Self {
+        let conditional = function
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y) = self.marginal.sample_continuous(u.1);
+        let row = ((y * self.conditional.len() as f64) as usize).min(self.conditional.len() - 1);
+        let (x, pdf_x) = self.conditional[row].sample_continuous(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{filter::Filter, ray::Radiance, vec3::Vec3};
+
+#[derive(Default, Clone, Copy)]
+struct FilmPixel {
+    sum: Vec3,
+    weight: f64,
+}
+
+/// Accumulates filtered samples into an image.
+///
+/// Pixel `(i, j)` covers `[i, i + 1) x [j, j + 1)` in film space, with `j`
+/// counting up from the bottom row. Each sample is splatted into every pixel
+/// whose centre is within the filter radius, and pixels are normalised by the
+/// total filter weight they received.
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Box<dyn Filter>,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Box<dyn Filter>) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); width * height],
+        }
+    }
+
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Vec3) {
+        let radius = self.filter.radius();
+        let x0 = (x - 0.5 - radius).ceil().max(0.0) as usize;
+        let y0 = (y - 0.5 - radius).ceil().max(0.0) as usize;
+        let x1 = ((x - 0.5 + radius).floor() as isize).min(self.width as isize - 1);
+        let y1 = ((y - 0.5 + radius).floor() as isize).min(self.height as isize - 1);
+        for j in y0 as isize..=y1 {
+            for i in x0 as isize..=x1 {
+                let weight = self.filter.evaluate(i as f64 + 0.5 - x, j as f64 + 0.5 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[j as usize * self.width + i as usize];
+                pixel.sum += weight * color;
+                pixel.weight += weight;
+            }
+        }
+    }
+
+    pub fn pixel(&self, i: usize, j: usize) -> Vec3 {
+        let pixel = &self.pixels[j * self.width + i];
+        if pixel.weight == 0.0 {
+            Vec3::default()
+        } else {
+            pixel.sum / pixel.weight
+        }
+    }
+
+    /// Every pixel, row by row from the bottom of the image.
+    pub fn pixels(&self) -> Vec<Vec3> {
+        (0..self.height)
+            .flat_map(|j| (0..self.width).map(move |i| (i, j)))
+            .map(|(i, j)| self.pixel(i, j))
+            .collect()
+    }
+}
+
+/// Light carried to the camera by paths traced from the lights, which can
+/// land on any pixel rather than the one being sampled.
+///
+/// Splats are summed rather than averaged. Each is an estimate for the whole
+/// image, so the sums are scaled by the pixel count over the number of
+/// camera samples taken once rendering is done.
+pub struct Splats {
+    width: usize,
+    height: usize,
+    pixels: Vec<Radiance>,
+}
+
+impl Splats {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Radiance::default(); width * height],
+        }
+    }
+
+    /// Adds light landing at `(s, t)` on the image, in the same terms as
+    /// `Camera::get_ray` takes.
+    pub fn add(&mut self, (s, t): (f64, f64), radiance: Radiance) {
+        let i = (s * self.width as f64) as usize;
+        let j = (t * self.height as f64) as usize;
+        if i < self.width && j < self.height {
+            self.pixels[j * self.width + i] += radiance;
+        }
+    }
+
+    /// Every pixel's scaled sum, row by row from the bottom of the image,
+    /// given the total number of camera samples taken.
+    pub fn pixels(&self, samples: usize) -> Vec<Radiance> {
+        let scale = (self.width * self.height) as f64 / samples.max(1) as f64;
+        self.pixels.iter().map(|pixel| *pixel * scale).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Film;
+    use crate::{filter::FilterKind, vec3::Vec3};
+
+    #[test]
+    fn constant_samples_should_give_constant_image() {
+        let color = Vec3::new(0.2, 0.4, 0.8);
+        for kind in &[
+            FilterKind::Box,
+            FilterKind::Tent,
+            FilterKind::Gaussian,
+            FilterKind::Mitchell,
+            FilterKind::Lanczos,
+        ] {
+            let mut film = Film::new(4, 3, kind.build(None));
+            for j in 0..3 {
+                for i in 0..4 {
+                    for (dx, dy) in &[(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+                        film.add_sample(i as f64 + dx, j as f64 + dy, color);
+                    }
+                }
+            }
+            for j in 0..3 {
+                for i in 0..4 {
+                    let pixel = film.pixel(i, j);
+                    assert!((pixel - color).length() < 1e-9, "{:?} {:?}", kind, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_should_not_leak_into_neighbours() {
+        let mut film = Film::new(2, 1, FilterKind::Box.build(None));
+        film.add_sample(0.5, 0.5, Vec3::new(1.0, 1.0, 1.0));
+        film.add_sample(1.5, 0.5, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(film.pixel(0, 0), Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(film.pixel(1, 0), Vec3::new(0.0, 0.0, 0.0));
+    }
+}
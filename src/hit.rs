@@ -1,20 +1,104 @@
 use crate::{
+    bvh::{Aabb, Bvh},
     material::Material,
     ray::Ray,
-    vec3::{dot, Vec3},
+    sampler::hash_unit,
+    vec3::{cross, dot, perpendicular, unit_vector, Vec3},
+};
+use std::{
+    cell::{OnceCell, RefCell},
+    rc::Rc,
 };
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
 
 pub trait Hit<'material> {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>>;
+
+    fn bounding_box(&self) -> Aabb;
+
+    /// Bounds around the parts of the object with specular materials, which
+    /// can focus light into caustics.
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        Vec::new()
+    }
+
+    /// Like `hit`, but also adds the number of intersection tests it took
+    /// to `tests`.
+    fn hit_counting(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<HitRecord<'material>> {
+        *tests += 1;
+        self.hit(ray, t_min, t_max)
+    }
 }
 
+#[derive(Clone)]
 pub struct HitRecord<'material> {
     pub point: Vec3,
     pub normal: Vec3,
     pub t: f64,
     pub front_face: bool,
+    /// Surface coordinates, both in `[0, 1]`.
+    pub uv: (f64, f64),
+    /// Where the hit lies within a triangle, for surfaces made of them.
+    pub barycentric: Option<Vec3>,
     pub material: Rc<RefCell<Box<dyn Material + 'material>>>,
+    /// Which of the scene's lights was hit, for surfaces that are area
+    /// lights.
+    pub light: Option<usize>,
+    /// Unit vectors along the surface in the directions `uv.0` and `uv.1`
+    /// increase, made perpendicular to each other and the outward normal,
+    /// for orienting normal maps.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+}
+
+/// The `tangent` and `bitangent` of a hit on a surface with outward normal
+/// `normal`, where the point moves by `dpdu` and `dpdv` as `uv` changes.
+pub fn tangent_frame(normal: Vec3, dpdu: Vec3, dpdv: Vec3) -> (Vec3, Vec3) {
+    let tangent = dpdu - dot(dpdu, normal) * normal;
+    let tangent = if tangent.near_zero() {
+        perpendicular(normal).0
+    } else {
+        unit_vector(tangent)
+    };
+    let bitangent = dpdv - dot(dpdv, normal) * normal - dot(dpdv, tangent) * tangent;
+    let bitangent = if bitangent.near_zero() {
+        cross(normal, tangent)
+    } else {
+        unit_vector(bitangent)
+    };
+    (tangent, bitangent)
+}
+
+/// Whether `ray` really hit the surface, rather than a part its material's
+/// alpha cuts away. Partly there surfaces stop rays at random in proportion
+/// to their alpha, but always the same way for the same ray and point, so
+/// that checking a hit again agrees.
+pub fn is_opaque(ray: &Ray, record: &HitRecord) -> bool {
+    let alpha = record.material.borrow().alpha(record);
+    if alpha >= 1.0 {
+        return true;
+    }
+    if alpha <= 0.0 {
+        return false;
+    }
+    let (origin, direction, point) = (ray.origin, ray.direction, record.point);
+    let values = [
+        origin.x,
+        origin.y,
+        origin.z,
+        direction.x,
+        direction.y,
+        direction.z,
+        point.x,
+        point.y,
+        point.z,
+    ];
+    hash_unit(&values.map(f64::to_bits)) < alpha
 }
 
 pub fn face_normal(ray: &Ray, outward_normal: Vec3) -> (bool, Vec3) {
@@ -26,33 +110,185 @@ pub fn face_normal(ray: &Ray, outward_normal: Vec3) -> (bool, Vec3) {
     }
 }
 
-pub struct Hittables<T>(VecDeque<T>);
+/// Lets objects be shared, such as between the world and its lights.
+impl<'material, T> Hit<'material> for Rc<T>
+where
+    T: Hit<'material> + ?Sized,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        (**self).specular_bounds()
+    }
+
+    fn hit_counting(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<HitRecord<'material>> {
+        (**self).hit_counting(ray, t_min, t_max, tests)
+    }
+}
+
+/// A list of objects, with a bounding volume hierarchy over them that is
+/// built the first time the list is traced after a change. Hits that
+/// materials cut away with their alpha are skipped over.
+pub struct Hittables<T> {
+    objects: Vec<T>,
+    bvh: OnceCell<Bvh>,
+}
 
 impl<'material, T> Hittables<T>
 where
     T: Hit<'material>,
 {
     pub fn new() -> Self {
-        Self(VecDeque::new())
+        Self {
+            objects: Vec::new(),
+            bvh: OnceCell::new(),
+        }
     }
 
-    pub fn clear(&mut self) {
-        self.0.clear()
+    pub fn add(&mut self, hittable: T) {
+        self.objects.push(hittable);
+        self.bvh = OnceCell::new();
     }
 
-    pub fn add(&mut self, hittable: T) {
-        self.0.push_back(hittable)
+    /// Like `hit`, but also returns the index of the object that was hit.
+    pub fn hit_object(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(usize, HitRecord<'material>)> {
+        self.traverse(ray, t_min, t_max, &mut 0)
     }
 
-    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+    fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<(usize, HitRecord<'material>)> {
+        let bvh = self.bvh.get_or_init(|| {
+            let bounds = self
+                .objects
+                .iter()
+                .map(|object| object.bounding_box())
+                .collect::<Vec<_>>();
+            Bvh::new(&bounds)
+        });
         let mut hit_record = None;
-        let mut closest_to = t_max;
-        for obj in self.0.iter() {
-            if let Some(new_record) = obj.hit(ray, t_min, closest_to) {
-                closest_to = new_record.t;
-                hit_record = Some(new_record);
-            }
-        }
+        let (mut box_tests, mut object_tests) = (0, 0);
+        bvh.traverse(ray, t_min, t_max, &mut box_tests, |index, closest_to| {
+            let mut near = t_min;
+            let record = loop {
+                let object = &self.objects[index];
+                let record = object.hit_counting(ray, near, closest_to, &mut object_tests)?;
+                if is_opaque(ray, &record) {
+                    break record;
+                }
+                // Carry on past a cut away hit, in case the object is hit
+                // again further along.
+                near = record.t + record.t.abs().max(1.0) * 1e-9;
+            };
+            let t = record.t;
+            hit_record = Some((index, record));
+            Some(t)
+        });
+        *tests += box_tests + object_tests;
         hit_record
     }
 }
+
+impl<'material, T> Hit<'material> for Hittables<T>
+where
+    T: Hit<'material>,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        self.hit_object(ray, t_min, t_max).map(|(_, record)| record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(Aabb::empty(), |total, object| {
+            total.union(&object.bounding_box())
+        })
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.specular_bounds())
+            .collect()
+    }
+
+    fn hit_counting(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<HitRecord<'material>> {
+        self.traverse(ray, t_min, t_max, tests)
+            .map(|(_, record)| record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{material::Lambertian, principled::Principled, sphere::Sphere};
+
+    #[test]
+    fn cut_away_hits_are_skipped() {
+        let scene = |alpha: f64, alpha_cutoff: Option<f64>| {
+            let mut hittables = Hittables::new();
+            let front: Box<dyn Material> = Box::new(Principled {
+                alpha: Box::new(alpha),
+                alpha_cutoff,
+                ..Principled::new(Box::new(0.5))
+            });
+            let back: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+            let (front, back) = (Rc::new(RefCell::new(front)), Rc::new(RefCell::new(back)));
+            hittables.add(Sphere::new(Vec3::default(), 1.0, front));
+            hittables.add(Sphere::new(Vec3::new(0.0, 0.0, -10.0), 5.0, back));
+            hittables
+        };
+        let ray = |i: usize| {
+            Ray::new(
+                Vec3::new(i as f64 * 1e-4, 0.0, 5.0),
+                Vec3::new(0.0, 0.0, -1.0),
+            )
+        };
+        // Through both sides of the front sphere to the back one, at t = 10.
+        let through = |hittables: &Hittables<Sphere>, ray: &Ray| {
+            hittables.hit(ray, 0.001, f64::INFINITY).unwrap().t > 9.0
+        };
+
+        assert!(!through(&scene(1.0, None), &ray(0)));
+        assert!(through(&scene(0.0, None), &ray(0)));
+        assert!(through(&scene(0.3, Some(0.5)), &ray(0)));
+        assert!(!through(&scene(0.3, Some(0.2)), &ray(0)));
+
+        // Each side stops half the rays, so a quarter get through both, and
+        // the same ray always goes the same way.
+        let half = scene(0.5, None);
+        let rays = 4000;
+        let passed = (0..rays).filter(|&i| through(&half, &ray(i))).count();
+        assert!(
+            (passed as f64 / rays as f64 - 0.25).abs() < 0.03,
+            "{}",
+            passed
+        );
+        assert!((0..100).all(|i| through(&half, &ray(i)) == through(&half, &ray(i))));
+    }
+}
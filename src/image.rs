@@ -0,0 +1,408 @@
+use crate::{
+    color::{Encoding, OutputSpace, Primaries},
+    vec3::Vec3,
+};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// An image loaded from disk, with channels scaled to `[0, 1]` and rows
+/// stored from the top of the image down.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    /// Reads a PGM or PPM file, in either the plain or the raw encoding.
+    pub fn read_netpbm(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        let mut position = 0;
+        let magic = next_token(&data, &mut position)?;
+        let channels = match magic.as_str() {
+            "P2" | "P5" => 1,
+            "P3" | "P6" => 3,
+            _ => return Err(format!("unsupported image format {}", magic).into()),
+        };
+        let width: usize = next_token(&data, &mut position)?.parse()?;
+        let height: usize = next_token(&data, &mut position)?.parse()?;
+        let max_value: f64 = next_token(&data, &mut position)?.parse()?;
+
+        let count = width * height * channels;
+        let values = if magic == "P2" || magic == "P3" {
+            (0..count)
+                .map(|_| Ok(next_token(&data, &mut position)?.parse::<f64>()?))
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?
+        } else {
+            // A single whitespace character separates the header from the
+            // raster.
+            position += 1;
+            let bytes_per_value = if max_value < 256.0 { 1 } else { 2 };
+            let raster = data
+                .get(position..position + count * bytes_per_value)
+                .ok_or("image data is truncated")?;
+            raster
+                .chunks(bytes_per_value)
+                .map(|bytes| {
+                    bytes
+                        .iter()
+                        .fold(0.0, |value, byte| value * 256.0 + *byte as f64)
+                })
+                .collect()
+        };
+        let pixels = values
+            .chunks(channels)
+            .map(|channel| {
+                let channel = channel
+                    .iter()
+                    .map(|value| value / max_value)
+                    .collect::<Vec<_>>();
+                match channel[..] {
+                    [value] => Vec3::new(value, value, value),
+                    [r, g, b] => Vec3::new(r, g, b),
+                    _ => unreachable!(),
+                }
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+fn next_token(data: &[u8], position: &mut usize) -> Result<String, Box<dyn Error>> {
+    loop {
+        match data.get(*position) {
+            Some(b'#') => {
+                while !matches!(data.get(*position), Some(b'\n') | None) {
+                    *position += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            Some(_) => break,
+            None => return Err("unexpected end of image".into()),
+        }
+    }
+    let start = *position;
+    while matches!(data.get(*position), Some(byte) if !byte.is_ascii_whitespace()) {
+        *position += 1;
+    }
+    Ok(String::from_utf8_lossy(&data[start..*position]).into_owned())
+}
+
+/// Writes an 8-bit RGB PNG from display-encoded rows, top row first.
+///
+/// The pixel data goes in stored deflate blocks, so the file is no smaller
+/// than the raw pixels. Standard encodings record the color space in a cICP
+/// chunk, with cHRM and gAMA alongside for readers that predate it. Gamma 2
+/// images only have cHRM and gAMA.
+pub fn write_png(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[[u8; 3]],
+    space: OutputSpace,
+    encoding: Encoding,
+) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, no filtering, no interlace.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+
+    if encoding == Encoding::Standard {
+        let (primaries, transfer) = space.cicp();
+        // Matrix coefficients zero for RGB, and full range.
+        write_chunk(writer, b"cICP", &[primaries, transfer, 0, 1])?;
+        if space == OutputSpace::Srgb {
+            // Perceptual rendering intent.
+            write_chunk(writer, b"sRGB", &[0])?;
+        }
+    }
+    let Primaries {
+        red,
+        green,
+        blue,
+        white,
+    } = space.primaries();
+    let mut chromaticities = Vec::new();
+    for (x, y) in &[white, red, green, blue] {
+        chromaticities.extend_from_slice(&((x * 100000.0).round() as u32).to_be_bytes());
+        chromaticities.extend_from_slice(&((y * 100000.0).round() as u32).to_be_bytes());
+    }
+    write_chunk(writer, b"cHRM", &chromaticities)?;
+    let gamma = match encoding {
+        Encoding::Gamma2 => 50000u32,
+        // Both standard curves are close to a plain 1 / 2.2 power.
+        Encoding::Standard => 45455,
+    };
+    write_chunk(writer, b"gAMA", &gamma.to_be_bytes())?;
+
+    let mut raw = Vec::with_capacity(height * (1 + 3 * width));
+    for row in pixels.chunks(width) {
+        // No filter on each scanline.
+        raw.push(0);
+        raw.extend(row.iter().flatten());
+    }
+    // A zlib stream with the fastest compression level in its header.
+    let mut data = vec![0x78, 0x01];
+    let blocks = raw.chunks(u16::MAX as usize).collect::<Vec<_>>();
+    for (index, block) in blocks.iter().enumerate() {
+        data.push((index + 1 == blocks.len()) as u8);
+        data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_chunk(writer, b"IDAT", &data)?;
+    write_chunk(writer, b"IEND", &[])?;
+    writer.flush()
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()));
+    writer.write_all(&crc.to_be_bytes())
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Writes an uncompressed OpenEXR image of linear 32-bit float RGB, top row
+/// first, tagged with the chromaticities of its primaries.
+pub fn write_exr(
+    writer: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Vec3],
+    primaries: Primaries,
+) -> io::Result<()> {
+    let mut header = Vec::new();
+    // Magic number, then version 2 for a single part scanline image.
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut channels = Vec::new();
+    // Channels are listed in alphabetical order, each as 32-bit float.
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&2i32.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    attribute(&mut header, "channels", "chlist", &channels);
+
+    let Primaries {
+        red,
+        green,
+        blue,
+        white,
+    } = primaries;
+    let chromaticities = [red, green, blue, white]
+        .iter()
+        .flat_map(|(x, y)| [*x as f32, *y as f32])
+        .flat_map(f32::to_le_bytes)
+        .collect::<Vec<_>>();
+    attribute(
+        &mut header,
+        "chromaticities",
+        "chromaticities",
+        &chromaticities,
+    );
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Scanlines stored top to bottom.
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    writer.write_all(&header)?;
+
+    // Each scanline is its own block: its y, its size, then each channel.
+    let block_size = 8 + 3 * 4 * width;
+    let first_block = header.len() + 8 * height;
+    for y in 0..height {
+        writer.write_all(&((first_block + y * block_size) as u64).to_le_bytes())?;
+    }
+    for (y, row) in pixels.chunks(width).enumerate() {
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&((block_size - 8) as i32).to_le_bytes())?;
+        for channel in &[|p: &Vec3| p.z, |p: &Vec3| p.y, |p: &Vec3| p.x] {
+            for pixel in row {
+                writer.write_all(&(channel(pixel) as f32).to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789".iter()), 0xcbf43926);
+        assert_eq!(crc32([].iter()), 0);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    /// Splits a PNG into its chunks, checking each one's CRC.
+    fn read_chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc32(body.iter()).to_be_bytes(), crc[..4]);
+            chunks.push((body[..4].try_into().unwrap(), body[4..].to_vec()));
+            rest = &crc[4..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn png_chunks_hold_the_pixels_and_color_space() {
+        let pixels = vec![[255, 0, 0], [0, 255, 0], [0, 0, 255], [1, 2, 3]];
+        let mut png = Vec::new();
+        write_png(
+            &mut png,
+            2,
+            2,
+            &pixels,
+            OutputSpace::Srgb,
+            Encoding::Standard,
+        )
+        .unwrap();
+        let chunks = read_chunks(&png);
+        let kinds = chunks.iter().map(|(kind, _)| kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [b"IHDR", b"cICP", b"sRGB", b"cHRM", b"gAMA", b"IDAT", b"IEND"]
+        );
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(chunks[1].1, [1, 13, 0, 1]);
+        assert_eq!(chunks[4].1, 45455u32.to_be_bytes());
+
+        // A zlib header, one final stored block, then the checksum.
+        let data = &chunks[5].1;
+        let raw = [0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 1, 2, 3];
+        assert_eq!(data[..3], [0x78, 0x01, 1]);
+        assert_eq!(data[3..7], [14, 0, !14, !0]);
+        assert_eq!(data[7..21], raw);
+        assert_eq!(data[21..], adler32(&raw).to_be_bytes());
+
+        // Gamma 2 images leave out the tags for the standard curves.
+        let mut png = Vec::new();
+        write_png(
+            &mut png,
+            2,
+            2,
+            &pixels,
+            OutputSpace::Rec2020,
+            Encoding::Gamma2,
+        )
+        .unwrap();
+        let chunks = read_chunks(&png);
+        let kinds = chunks.iter().map(|(kind, _)| kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [b"IHDR", b"cHRM", b"gAMA", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[2].1, 50000u32.to_be_bytes());
+    }
+
+    #[test]
+    fn exr_scanlines_follow_their_offsets() {
+        let pixels = vec![
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(4.0, 5.0, 6.0),
+            Vec3::new(0.5, 0.25, 0.125),
+            Vec3::default(),
+        ];
+        let mut exr = Vec::new();
+        write_exr(&mut exr, 2, 2, &pixels, OutputSpace::Srgb.primaries()).unwrap();
+        assert_eq!(exr[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // Each block is its y, its size, then the blue, green and red
+        // channels of the row.
+        let block_size = 8 + 3 * 4 * 2;
+        let header_end = exr.len() - 2 * 8 - 2 * block_size;
+        assert_eq!(exr[header_end - 1], 0);
+        let float = |at: usize| f32::from_le_bytes(exr[at..at + 4].try_into().unwrap());
+        let int = |at: usize| i32::from_le_bytes(exr[at..at + 4].try_into().unwrap());
+        for y in 0..2 {
+            let at = header_end + 8 * y;
+            let offset = u64::from_le_bytes(exr[at..at + 8].try_into().unwrap()) as usize;
+            assert_eq!(offset, header_end + 16 + y * block_size);
+            assert_eq!(int(offset), y as i32);
+            assert_eq!(int(offset + 4), block_size as i32 - 8);
+            for x in 0..2 {
+                let pixel = pixels[2 * y + x];
+                let channel = |index: usize| float(offset + 8 + 4 * (2 * index + x));
+                assert_eq!(channel(0), pixel.z as f32);
+                assert_eq!(channel(1), pixel.y as f32);
+                assert_eq!(channel(2), pixel.x as f32);
+            }
+        }
+    }
+}
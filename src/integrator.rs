@@ -0,0 +1,561 @@
+use crate::{
+    bdpt::Bdpt,
+    camera::Camera,
+    debug::{AmbientOcclusion, BounceCount, BvhCost, Clay, Geometry, GeometryIntegrator},
+    film::Splats,
+    hit::{Hit, HitRecord},
+    light::{power_heuristic, Light},
+    light_sampler::LightSampler,
+    mlt::{Mlt, MltSettings},
+    options::ParseOptionError,
+    photon::{PhotonMapper, PhotonSettings},
+    ray::{Radiance, Ray, Sky},
+    sampler::Sampler,
+    spectrum::{Spectrum, Wavelengths},
+    vec3::{dot, unit_vector, Vec3},
+};
+use std::str::FromStr;
+
+/// Everything an integrator can look at while rendering a frame.
+pub struct SceneView<'a, 'material> {
+    pub world: &'a dyn Hit<'material>,
+    pub sky: &'a Sky,
+    /// Lights that can be sampled directly. Area lights are also part of
+    /// `world`, so rays can hit them too.
+    pub lights: &'a [Box<dyn Light + 'a>],
+    /// Picks which of `lights` to sample.
+    pub light_sampler: &'a dyn LightSampler,
+    pub camera: &'a dyn Camera,
+}
+
+/// Estimates the light arriving along camera rays.
+pub trait Integrator {
+    /// Light that reaches the camera through some other point on the image
+    /// goes to `splats` instead of the returned radiance.
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        splats: &mut Splats,
+    ) -> Radiance;
+
+    /// How many passes each pixel's samples are split over.
+    fn passes(&self) -> usize {
+        1
+    }
+
+    /// Prepares for pass `pass`, before any of its samples are taken.
+    fn start_pass(&self, _scene: &SceneView, _pass: usize) {}
+
+    /// Whether all the light goes to `splats`, with nothing tied to the
+    /// pixel being sampled. Every pixel then takes the same number of
+    /// samples, as the noise in a pixel says nothing about where more are
+    /// needed.
+    fn splats_only(&self) -> bool {
+        false
+    }
+}
+
+/// The integrators that can be picked at runtime. Clay is a fast preview,
+/// and the rest after it are there to help find out why a scene looks wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegratorKind {
+    Path,
+    /// Bidirectional path tracing, which always works in RGB.
+    Bdpt,
+    /// Photon mapping, which also always works in RGB.
+    Photon,
+    /// Metropolis light transport, which also always works in RGB.
+    Mlt,
+    Clay,
+    Normals,
+    Depth,
+    Uv,
+    Barycentrics,
+    Albedo,
+    AmbientOcclusion,
+    BvhCost,
+    Bounces,
+}
+
+impl IntegratorKind {
+    pub fn build(
+        &self,
+        length: PathLength,
+        spectral: bool,
+        ao_radius: f64,
+        photons: PhotonSettings,
+        mlt: MltSettings,
+    ) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Path if spectral => Box::new(SpectralPathTracer::new(length)),
+            IntegratorKind::Path => Box::new(PathTracer::new(length)),
+            IntegratorKind::Bdpt => Box::new(Bdpt::new(length.max_depth)),
+            IntegratorKind::Photon => Box::new(PhotonMapper::new(length.max_depth, photons)),
+            IntegratorKind::Mlt => Box::new(Mlt::new(PathTracer::new(length), mlt)),
+            IntegratorKind::Clay => Box::new(Clay::new(ao_radius)),
+            IntegratorKind::Normals => Box::new(GeometryIntegrator::new(Geometry::Normal)),
+            IntegratorKind::Depth => Box::new(GeometryIntegrator::new(Geometry::Depth)),
+            IntegratorKind::Uv => Box::new(GeometryIntegrator::new(Geometry::Uv)),
+            IntegratorKind::Barycentrics => {
+                Box::new(GeometryIntegrator::new(Geometry::Barycentric))
+            }
+            IntegratorKind::Albedo => Box::new(GeometryIntegrator::new(Geometry::Albedo)),
+            IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion::new(ao_radius)),
+            IntegratorKind::BvhCost => Box::new(BvhCost),
+            IntegratorKind::Bounces => Box::new(BounceCount::new(PathTracer::new(length))),
+        }
+    }
+
+    /// Whether the integrator samples `SceneView::lights` through shadow
+    /// rays. Delta lights such as the sun can't be hit, so the others leave
+    /// them dark.
+    pub fn samples_lights(&self, spectral: bool) -> bool {
+        match self {
+            IntegratorKind::Path => !spectral,
+            IntegratorKind::Mlt => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the integrator adds the light of emitters its paths hit. Only
+    /// the sky lights the paths bidirectional and photon mapping build, so
+    /// they leave area lights dark.
+    pub fn hits_emitters(&self) -> bool {
+        !matches!(self, IntegratorKind::Bdpt | IntegratorKind::Photon)
+    }
+
+    /// Whether the integrator estimates light, rather than showing some
+    /// property of the scene that exposure shouldn't scale.
+    pub fn is_physical(&self) -> bool {
+        matches!(
+            self,
+            IntegratorKind::Path
+                | IntegratorKind::Bdpt
+                | IntegratorKind::Photon
+                | IntegratorKind::Mlt
+        )
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "bdpt" => Ok(IntegratorKind::Bdpt),
+            "photon" => Ok(IntegratorKind::Photon),
+            "mlt" => Ok(IntegratorKind::Mlt),
+            "clay" => Ok(IntegratorKind::Clay),
+            "normals" => Ok(IntegratorKind::Normals),
+            "depth" => Ok(IntegratorKind::Depth),
+            "uv" => Ok(IntegratorKind::Uv),
+            "barycentrics" => Ok(IntegratorKind::Barycentrics),
+            "albedo" => Ok(IntegratorKind::Albedo),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "bvh-cost" => Ok(IntegratorKind::BvhCost),
+            "bounces" => Ok(IntegratorKind::Bounces),
+            _ => Err(ParseOptionError::new("integrator", s)),
+        }
+    }
+}
+
+/// How far paths are followed.
+#[derive(Debug, Clone, Copy)]
+pub struct PathLength {
+    /// Paths end after this many bounces no matter how bright they are.
+    pub max_depth: u16,
+    /// Bounces before Russian roulette may end a path.
+    pub roulette_depth: u16,
+}
+
+impl PathLength {
+    /// Russian roulette. Past `roulette_depth` a path only carries on with a
+    /// probability that follows its throughput, so dim paths tend to stop
+    /// early, and the ones that carry on are weighted up by the inverse of
+    /// that probability to keep the estimate unbiased. Returns the weight,
+    /// or `None` if the path should stop.
+    fn roulette(&self, bounces: u16, throughput: f64, sampler: &mut dyn Sampler) -> Option<f64> {
+        if bounces < self.roulette_depth {
+            return Some(1.0);
+        }
+        let survival = throughput.min(0.95);
+        (sampler.next_1d() < survival).then(|| 1.0 / survival)
+    }
+}
+
+/// A unidirectional path tracer that follows one scattered ray per bounce
+/// until it escapes to the sky.
+///
+/// At each non-specular bounce it also samples one of the scene's lights,
+/// picked by the scene's light sampler, and weights that against finding the
+/// same light with the scattered ray by multiple importance sampling.
+pub struct PathTracer {
+    length: PathLength,
+}
+
+impl PathTracer {
+    pub fn new(length: PathLength) -> Self {
+        Self { length }
+    }
+
+    /// The radiance along `ray`, and how many times its path bounced.
+    pub fn trace(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+    ) -> (Radiance, u16) {
+        let mut radiance = Radiance::default();
+        // The product of the attenuations so far, and of the weights that
+        // made up for roulette.
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let mut bounces = 0;
+        // Where the last bounce was, its normal and the density its ray was
+        // picked with, if a light sample could have found the same light.
+        let mut previous: Option<(Vec3, Vec3, f64)> = None;
+        while bounces < self.length.max_depth {
+            let record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(record) => record,
+                None => {
+                    radiance.add(bounces, throughput * scene.sky.color(&ray));
+                    break;
+                }
+            };
+            let emitted = record.material.borrow().emitted(&record);
+            if emitted != Vec3::default() {
+                let weight = match (previous, record.light) {
+                    (Some((point, normal, pdf)), Some(index)) => {
+                        let pmf = scene.light_sampler.pmf(point, normal, index);
+                        let light_pdf = pmf * scene.lights[index].pdf(point, ray.direction);
+                        power_heuristic(pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance.add(bounces, throughput * emitted * weight);
+            }
+            let wo = -unit_vector(ray.direction);
+            let specular = record.material.borrow().is_specular();
+            if !specular && !scene.lights.is_empty() {
+                let light = direct_light(scene, &record, wo, sampler);
+                radiance.add(bounces + 1, throughput * light);
+            }
+            let mut scattered = Ray::default();
+            let mut attenuation = Vec3::default();
+            let scatters = record.material.borrow_mut().scatter(
+                &ray,
+                &record,
+                &mut attenuation,
+                &mut scattered,
+                sampler,
+            );
+            if !scatters {
+                break;
+            }
+            previous = (!specular && !scene.lights.is_empty()).then(|| {
+                let pdf = record
+                    .material
+                    .borrow()
+                    .pdf(&record, wo, scattered.direction);
+                (record.point, record.normal, pdf)
+            });
+            bounces += 1;
+            throughput *= attenuation;
+            let brightest = throughput.x.max(throughput.y).max(throughput.z);
+            match self.length.roulette(bounces, brightest, sampler) {
+                Some(weight) => throughput *= weight,
+                None => break,
+            }
+            ray = scattered;
+        }
+        (radiance, bounces)
+    }
+}
+
+/// Light reaching a non-specular hit from one of the scene's lights, picked
+/// by the scene's light sampler, and reflected towards `wo`. Weighted against
+/// the chance of the material's own scattering finding the light, unless that
+/// can't happen because it's a delta light.
+fn direct_light(
+    scene: &SceneView,
+    record: &HitRecord,
+    wo: Vec3,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let u = sampler.next_1d();
+    let (index, pmf) = match scene.light_sampler.sample(record.point, record.normal, u) {
+        Some(picked) => picked,
+        None => return Vec3::default(),
+    };
+    let light = &scene.lights[index];
+    let sample = match light.sample(record.point, sampler.next_2d()) {
+        Some(sample) => sample,
+        None => return Vec3::default(),
+    };
+    let material = record.material.borrow();
+    let reflected =
+        material.bsdf(record, wo, sample.direction) * dot(sample.direction, record.normal).abs();
+    if reflected == Vec3::default() {
+        return Vec3::default();
+    }
+    let shadow = Ray::new(record.point, sample.direction);
+    if scene
+        .world
+        .hit(&shadow, 0.001, sample.distance - 0.001)
+        .is_some()
+    {
+        return Vec3::default();
+    }
+    let pdf = sample.pdf * pmf;
+    let weight = if light.is_delta() {
+        1.0
+    } else {
+        power_heuristic(pdf, material.pdf(record, wo, sample.direction))
+    };
+    reflected * sample.radiance * weight / pdf
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        self.trace(ray, scene, sampler).0
+    }
+}
+
+/// Like `PathTracer`, but carries a handful of wavelengths along each path
+/// instead of RGB, converting back to RGB once the path is done. It only
+/// finds lights by hitting them.
+pub struct SpectralPathTracer {
+    length: PathLength,
+}
+
+impl SpectralPathTracer {
+    pub fn new(length: PathLength) -> Self {
+        Self { length }
+    }
+}
+
+impl Integrator for SpectralPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        let mut wavelengths = Wavelengths::sample_visible(sampler.next_1d());
+        let (mut direct, mut indirect) = (Spectrum::default(), Spectrum::default());
+        let mut throughput = Spectrum::constant(1.0);
+        let mut ray = *ray;
+        let mut bounces = 0;
+        while bounces < self.length.max_depth {
+            let record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(record) => record,
+                None => {
+                    let light =
+                        throughput * Spectrum::from_rgb(scene.sky.color(&ray), &wavelengths);
+                    if bounces <= 1 {
+                        direct = direct + light;
+                    } else {
+                        indirect = indirect + light;
+                    }
+                    break;
+                }
+            };
+            let emitted = record.material.borrow().emitted(&record);
+            if emitted != Vec3::default() {
+                let light = throughput * Spectrum::from_rgb(emitted, &wavelengths);
+                if bounces <= 1 {
+                    direct = direct + light;
+                } else {
+                    indirect = indirect + light;
+                }
+            }
+            let mut scattered = Ray::default();
+            let mut attenuation = Spectrum::default();
+            let scatters = record.material.borrow_mut().scatter_spectral(
+                &ray,
+                &record,
+                &mut wavelengths,
+                &mut attenuation,
+                &mut scattered,
+                sampler,
+            );
+            if !scatters || attenuation.is_black() {
+                break;
+            }
+            bounces += 1;
+            throughput = throughput * attenuation;
+            match self.length.roulette(bounces, throughput.max(), sampler) {
+                Some(weight) => throughput = throughput * weight,
+                None => break,
+            }
+            ray = scattered;
+        }
+        Radiance {
+            direct: direct.to_rgb(&wavelengths),
+            indirect: indirect.to_rgb(&wavelengths),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        color::WorkingSpace,
+        hit::Hittables,
+        light_sampler::UniformLightSampler,
+        material::{Lambertian, Material},
+        sampler::SamplerKind,
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    /// The size of the image integrators are compared on, which splats
+    /// land on.
+    pub(crate) const WIDTH: usize = 4;
+    pub(crate) const HEIGHT: usize = 3;
+
+    /// A grey unit sphere resting on a much bigger one, lit by the sky and
+    /// small enough that light paths from the sky often land where the
+    /// camera can see them.
+    pub(crate) struct TestScene {
+        pub(crate) world: Hittables<Sphere<'static>>,
+        pub(crate) camera: PerspectiveCamera,
+        sky: Sky,
+        light_sampler: UniformLightSampler,
+    }
+
+    impl TestScene {
+        pub(crate) fn view(&self) -> SceneView<'_, 'static> {
+            SceneView {
+                world: &self.world,
+                sky: &self.sky,
+                lights: &[],
+                light_sampler: &self.light_sampler,
+                camera: &self.camera,
+            }
+        }
+    }
+
+    pub(crate) fn test_scene() -> TestScene {
+        let mut world = Hittables::new();
+        for (center, radius) in [
+            (Vec3::new(0.0, -20.0, 0.0), 20.0),
+            (Vec3::new(0.0, 1.0, 0.0), 1.0),
+        ] {
+            let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+            world.add(Sphere::new(center, radius, Rc::new(RefCell::new(material))));
+        }
+        TestScene {
+            world,
+            camera: PerspectiveCamera::new(
+                Vec3::new(0.0, 2.0, 6.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                40.0,
+                WIDTH as f64 / HEIGHT as f64,
+                0.0,
+                6.0,
+            ),
+            sky: Sky::new(WorkingSpace::LinearSrgb),
+            light_sampler: UniformLightSampler::new(0),
+        }
+    }
+
+    /// Mean and standard error of the image's average luminance, counting
+    /// each sample's splats towards it.
+    pub(crate) fn estimate(
+        integrator: &dyn Integrator,
+        scene: &SceneView,
+        seed: u64,
+    ) -> (f64, f64) {
+        let samples = 20000;
+        let mut sampler = SamplerKind::Independent.build(samples, seed);
+        let (mut sum, mut sum_squared) = (0.0, 0.0);
+        for index in 0..samples {
+            sampler.start_sample(0, 0, index);
+            let (s, t) = sampler.next_2d();
+            let ray = match scene.camera.get_ray(s, t, sampler.as_mut()) {
+                Some(ray) => ray,
+                None => continue,
+            };
+            let mut splats = Splats::new(WIDTH, HEIGHT);
+            let radiance = integrator.radiance(&ray, scene, sampler.as_mut(), &mut splats);
+            let splatted = splats
+                .pixels(1)
+                .iter()
+                .map(|pixel| pixel.total().luminance())
+                .sum::<f64>()
+                / (WIDTH * HEIGHT) as f64;
+            let value = radiance.total().luminance() + splatted;
+            sum += value;
+            sum_squared += value * value;
+        }
+        let mean = sum / samples as f64;
+        let variance = sum_squared / samples as f64 - mean * mean;
+        (mean, (variance / samples as f64).sqrt())
+    }
+
+    #[test]
+    fn roulette_converges_to_the_deep_render() {
+        // Bright diffuse surfaces facing each other keep paths going for a
+        // long time.
+        let mut world = Hittables::new();
+        for (center, radius) in [
+            (Vec3::new(0.0, -1000.0, 0.0), 1000.0),
+            (Vec3::new(0.0, 1.0, 0.0), 1.0),
+            (Vec3::new(-2.0, 1.0, -1.0), 1.0),
+        ] {
+            let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.9, 0.9, 0.9)));
+            world.add(Sphere::new(center, radius, Rc::new(RefCell::new(material))));
+        }
+        let sky = Sky::new(WorkingSpace::LinearSrgb);
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 1.0, 5.0),
+            Vec3::new(0.0, 0.8, 4.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            WIDTH as f64 / HEIGHT as f64,
+            0.0,
+            1.0,
+        );
+        let scene = SceneView {
+            world: &world,
+            sky: &sky,
+            lights: &[],
+            light_sampler: &UniformLightSampler::new(0),
+            camera: &camera,
+        };
+
+        let deep = PathTracer::new(PathLength {
+            max_depth: 200,
+            roulette_depth: u16::MAX,
+        });
+        let roulette = PathTracer::new(PathLength {
+            max_depth: 200,
+            roulette_depth: 3,
+        });
+        let (deep, deep_error) = estimate(&deep, &scene, 1);
+        let (roulette, roulette_error) = estimate(&roulette, &scene, 2);
+        let error = (deep_error.powi(2) + roulette_error.powi(2)).sqrt();
+        assert!(
+            (deep - roulette).abs() < 4.0 * error,
+            "{} ± {} against {} ± {}",
+            deep,
+            deep_error,
+            roulette,
+            roulette_error
+        );
+    }
+}
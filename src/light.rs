@@ -0,0 +1,468 @@
+use crate::{
+    bvh::Aabb,
+    hit::{Hit, HitRecord},
+    ray::Ray,
+    shape::{Shape, ShapeSample},
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+use std::{f64::consts::PI, rc::Rc};
+
+/// Light arriving at a point from a direction picked towards a light.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// The unit direction from the point towards the light.
+    pub direction: Vec3,
+    /// How far away the light is along `direction`, for shadow rays.
+    pub distance: f64,
+    pub radiance: Vec3,
+    /// The density per unit solid angle with which `direction` was picked,
+    /// or 1 for delta lights, whose light only arrives from the one
+    /// direction and so can't be weighed against other strategies.
+    pub pdf: f64,
+}
+
+/// Something lighting the scene that shading points can aim at directly,
+/// rather than waiting for a scattered ray to happen to hit it.
+pub trait Light {
+    /// Picks a direction from `point` towards the light.
+    fn sample(&self, point: Vec3, u: (f64, f64)) -> Option<LightSample>;
+
+    /// The density per unit solid angle with which `sample` picks
+    /// `direction` from `point`. Always zero for delta lights, which no
+    /// scattered ray can hit.
+    fn pdf(&self, point: Vec3, direction: Vec3) -> f64;
+
+    /// Whether light only arrives from the light along a single direction,
+    /// so that it only ever contributes through `sample` and shadow rays.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Where the light is, which way it shines and how much light it gives
+    /// off, for choosing between lights. `None` for lights infinitely far
+    /// away.
+    fn bounds(&self) -> Option<LightBounds>;
+}
+
+impl<T> Light for Rc<T>
+where
+    T: Light + ?Sized,
+{
+    fn sample(&self, point: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        (**self).sample(point, u)
+    }
+
+    fn pdf(&self, point: Vec3, direction: Vec3) -> f64 {
+        (**self).pdf(point, direction)
+    }
+
+    fn is_delta(&self) -> bool {
+        (**self).is_delta()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        (**self).bounds()
+    }
+}
+
+/// A conservative summary of one or more lights, after Conty Estevez and
+/// Kulla's light trees.
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// The total power given off, as luminance.
+    pub power: f64,
+    /// A cone, as its axis and the cosine of its half angle, holding the
+    /// directions the lights face.
+    pub direction: Vec3,
+    pub cos_normals: f64,
+    /// The cosine of how far past those directions light still leaves.
+    pub cos_emission: f64,
+}
+
+impl LightBounds {
+    /// Bounds holding both `self` and `other`.
+    pub fn union(&self, other: &LightBounds) -> Self {
+        if self.power == 0.0 {
+            return *other;
+        }
+        if other.power == 0.0 {
+            return *self;
+        }
+        let (direction, cos_normals) = union_cones(
+            (self.direction, self.cos_normals),
+            (other.direction, other.cos_normals),
+        );
+        Self {
+            bounds: self.bounds.union(&other.bounds),
+            power: self.power + other.power,
+            direction,
+            cos_normals,
+            cos_emission: self.cos_emission.min(other.cos_emission),
+        }
+    }
+
+    /// An estimate of how much light the lights could send to `point`, on
+    /// a surface facing `normal`, or anywhere round it if the normal is
+    /// zero. It never rules out a light that could contribute.
+    pub fn importance(&self, point: Vec3, normal: Vec3) -> f64 {
+        if self.power == 0.0 {
+            return 0.0;
+        }
+        let center = self.bounds.centroid();
+        let radius = 0.5 * (self.bounds.max - self.bounds.min).length();
+        let offset = point - center;
+        // Keep points close to or inside the bounds from getting an
+        // unbounded share.
+        let distance_squared = offset.length_squared().max(radius.powi(2)).max(1e-6);
+        if offset.length_squared() <= radius.powi(2) {
+            return self.power / distance_squared;
+        }
+        let to_point = unit_vector(offset);
+        // The angle the bounds take up as seen from the point.
+        let spread = (radius.powi(2) / offset.length_squared()).sqrt().asin();
+        // The smallest angle between a direction the lights face and one
+        // towards the point.
+        let angle = dot(self.direction, to_point).clamp(-1.0, 1.0).acos();
+        let outside = (angle - self.cos_normals.acos() - spread).max(0.0);
+        if outside.cos() <= self.cos_emission {
+            return 0.0;
+        }
+        let mut importance = self.power * outside.cos() / distance_squared;
+        if normal != Vec3::default() {
+            let incidence = dot(to_point, normal).abs().min(1.0).acos();
+            importance *= (incidence - spread).max(0.0).cos();
+        }
+        importance
+    }
+}
+
+/// The smallest cone holding two others, each given by its axis and the
+/// cosine of its half angle.
+fn union_cones(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    let (theta_a, theta_b) = (a.1.acos(), b.1.acos());
+    let between = dot(a.0, b.0).clamp(-1.0, 1.0).acos();
+    if (between + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (between + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+    let theta = 0.5 * (theta_a + between + theta_b);
+    let axis = cross(a.0, b.0);
+    if theta >= PI || axis.length_squared() == 0.0 {
+        return (a.0, -1.0);
+    }
+    // Turn a's axis towards b's until the cone just takes in both.
+    let (axis, turn) = (unit_vector(axis), theta - theta_a);
+    let direction = a.0 * turn.cos() + cross(axis, a.0) * turn.sin();
+    (direction, theta.cos())
+}
+
+/// A shape in the world with a material that gives off light. It goes in
+/// the world as well as the lights, and marks the hits on it with its
+/// index among the lights.
+pub struct AreaLight<'material> {
+    shape: Rc<dyn Shape<'material> + 'material>,
+    index: usize,
+}
+
+impl<'material> AreaLight<'material> {
+    pub fn new(shape: Rc<dyn Shape<'material> + 'material>, index: usize) -> Self {
+        Self { shape, index }
+    }
+}
+
+impl<'material> Hit<'material> for AreaLight<'material> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        let record = self.shape.hit(ray, t_min, t_max)?;
+        Some(HitRecord {
+            light: Some(self.index),
+            ..record
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        self.shape.specular_bounds()
+    }
+}
+
+impl<'material> Shape<'material> for AreaLight<'material> {
+    fn area(&self) -> f64 {
+        self.shape.area()
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        self.shape.sample_area(u)
+    }
+
+    fn sample(&self, reference: Vec3, u: (f64, f64)) -> Option<ShapeSample> {
+        self.shape.sample(reference, u)
+    }
+
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        self.shape.pdf(reference, direction)
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        self.shape.normal_cone()
+    }
+}
+
+impl<'material> Light for AreaLight<'material> {
+    fn sample(&self, point: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let sample = self.shape.sample(point, u)?;
+        // Finding the point again gives the hit record the material needs
+        // to say what it emits towards `point`.
+        let ray = Ray::new(point, unit_vector(sample.point - point));
+        let record = self.shape.hit(&ray, 0.001, f64::INFINITY)?;
+        let radiance = record.material.borrow().emitted(&record);
+        if radiance == Vec3::default() || sample.pdf == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: ray.direction,
+            distance: record.t,
+            radiance,
+            pdf: sample.pdf,
+        })
+    }
+
+    fn pdf(&self, point: Vec3, direction: Vec3) -> f64 {
+        self.shape.pdf(point, direction)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        // Look at the middle of the shape from just in front to find out
+        // what its material gives off.
+        let sample = self.shape.sample_area((0.5, 0.5));
+        let ray = Ray::new(sample.point + sample.normal, -sample.normal);
+        let radiance = self
+            .shape
+            .hit(&ray, 0.5, 1.5)
+            .map(|record| record.material.borrow().emitted(&record))
+            .unwrap_or_default();
+        let (direction, cos_normals) = self.shape.normal_cone();
+        Some(LightBounds {
+            bounds: self.shape.bounding_box(),
+            power: PI * self.shape.area() * radiance.luminance(),
+            direction,
+            cos_normals,
+            // Each point lights the hemisphere in front of it.
+            cos_emission: 0.0,
+        })
+    }
+}
+
+/// A light giving off `intensity` evenly in every direction from a single
+/// point.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Vec3, _: (f64, f64)) -> Option<LightSample> {
+        let offset = self.position - point;
+        let distance = offset.length();
+        Some(LightSample {
+            direction: offset / distance,
+            distance,
+            radiance: self.intensity / distance.powi(2),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _: Vec3, _: Vec3) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            power: 4.0 * PI * self.intensity.luminance(),
+            direction: Vec3::new(0.0, 0.0, 1.0),
+            cos_normals: -1.0,
+            cos_emission: 0.0,
+        })
+    }
+}
+
+/// A point light that only shines within a cone around `direction`. It's at
+/// full strength out to `falloff_start` degrees from the middle, then fades
+/// smoothly to nothing at `cone` degrees.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    cos_cone: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        cone: f64,
+        falloff_start: f64,
+        intensity: Vec3,
+    ) -> Self {
+        Self {
+            position,
+            direction: unit_vector(direction),
+            intensity,
+            cos_cone: cone.to_radians().cos(),
+            cos_falloff_start: falloff_start.min(cone).to_radians().cos(),
+        }
+    }
+
+    /// How much of the light's intensity goes out at an angle with cosine
+    /// `cosine` from the middle of the cone.
+    fn falloff(&self, cosine: f64) -> f64 {
+        if cosine >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let t = ((cosine - self.cos_cone) / (self.cos_falloff_start - self.cos_cone)).max(0.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3, _: (f64, f64)) -> Option<LightSample> {
+        let offset = self.position - point;
+        let distance = offset.length();
+        let direction = offset / distance;
+        let falloff = self.falloff(dot(-direction, self.direction));
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * falloff / distance.powi(2),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _: Vec3, _: Vec3) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let (cone, falloff_start) = (self.cos_cone.acos(), self.cos_falloff_start.acos());
+        Some(LightBounds {
+            bounds: Aabb::new(self.position, self.position),
+            power: 4.0 * PI * self.intensity.luminance(),
+            direction: self.direction,
+            cos_normals: self.cos_falloff_start,
+            cos_emission: (cone - falloff_start).cos(),
+        })
+    }
+}
+
+/// Light from so far away, like the sun's, that it arrives everywhere from
+/// the same direction. `irradiance` is what it gives a surface facing it.
+pub struct DirectionalLight {
+    /// The unit direction towards the light.
+    direction: Vec3,
+    irradiance: Vec3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            direction: unit_vector(direction),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _: Vec3, _: (f64, f64)) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _: Vec3, _: Vec3) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+/// Veach's power heuristic with an exponent of two, weighting a sample
+/// taken with density `pdf` against another strategy that could have
+/// taken it with density `other`.
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (pdf, other) = (pdf * pdf, other * other);
+    if pdf + other == 0.0 {
+        0.0
+    } else {
+        pdf / (pdf + other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spot_light_fades_out_towards_the_edge_of_its_cone() {
+        let light = SpotLight::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            30.0,
+            20.0,
+            Vec3::new(1.0, 1.0, 1.0),
+        );
+        let at_angle = |degrees: f64| {
+            let along = degrees.to_radians().tan();
+            light
+                .sample(Vec3::new(along, 0.0, 0.0), (0.5, 0.5))
+                .map_or(0.0, |sample| sample.radiance.x * sample.distance.powi(2))
+        };
+        assert_eq!(at_angle(0.0), 1.0);
+        assert!((at_angle(19.9) - 1.0).abs() < 1e-12);
+        let fading = [21.0, 25.0, 29.0].map(at_angle);
+        assert!(fading
+            .windows(2)
+            .all(|pair| pair[0] > pair[1] && pair[1] > 0.0));
+        assert_eq!(at_angle(31.0), 0.0);
+        // Point lights fall off with the square of the distance.
+        let point = PointLight::new(Vec3::default(), Vec3::new(8.0, 8.0, 8.0));
+        let sample = point.sample(Vec3::new(0.0, 2.0, 0.0), (0.5, 0.5)).unwrap();
+        assert_eq!(sample.radiance, Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(sample.direction, Vec3::new(0.0, -1.0, 0.0));
+    }
+}
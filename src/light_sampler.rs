@@ -0,0 +1,352 @@
+use crate::{
+    bvh::Aabb,
+    distribution::Distribution1D,
+    light::{Light, LightBounds},
+    options::ParseOptionError,
+    vec3::Vec3,
+};
+use std::str::FromStr;
+
+/// Picks which of the scene's lights to sample from a shading point.
+pub trait LightSampler {
+    /// Picks a light for `point` on a surface facing `normal`, which is
+    /// zero where there's no surface, and the chance of picking it. `None`
+    /// if no light can reach the point.
+    fn sample(&self, point: Vec3, normal: Vec3, u: f64) -> Option<(usize, f64)>;
+
+    /// The chance of `sample` picking the light at `index`.
+    fn pmf(&self, point: Vec3, normal: Vec3, index: usize) -> f64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSamplerKind {
+    Uniform,
+    Power,
+    Bvh,
+}
+
+impl LightSamplerKind {
+    pub fn build(&self, lights: &[Box<dyn Light + '_>]) -> Box<dyn LightSampler> {
+        match self {
+            LightSamplerKind::Uniform => Box::new(UniformLightSampler::new(lights.len())),
+            LightSamplerKind::Power => Box::new(PowerLightSampler::new(lights)),
+            LightSamplerKind::Bvh => Box::new(BvhLightSampler::new(lights)),
+        }
+    }
+}
+
+impl FromStr for LightSamplerKind {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(LightSamplerKind::Uniform),
+            "power" => Ok(LightSamplerKind::Power),
+            "bvh" => Ok(LightSamplerKind::Bvh),
+            _ => Err(ParseOptionError::new("light sampler", s)),
+        }
+    }
+}
+
+/// Every light has the same chance.
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl UniformLightSampler {
+    pub fn new(count: usize) -> Self {
+        Self { count }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _: Vec3, _: Vec3, u: f64) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+        let index = ((u * self.count as f64) as usize).min(self.count - 1);
+        Some((index, 1.0 / self.count as f64))
+    }
+
+    fn pmf(&self, _: Vec3, _: Vec3, _: usize) -> f64 {
+        1.0 / self.count as f64
+    }
+}
+
+/// Lights are picked in proportion to their power, wherever the shading
+/// point is. Lights infinitely far away get the average power of the rest.
+pub struct PowerLightSampler {
+    distribution: Option<Distribution1D>,
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &[Box<dyn Light + '_>]) -> Self {
+        let powers = lights
+            .iter()
+            .map(|light| light.bounds().map(|bounds| bounds.power))
+            .collect::<Vec<_>>();
+        let bounded = powers.iter().flatten().collect::<Vec<_>>();
+        let average = match bounded.len() {
+            0 => 1.0,
+            count => bounded.into_iter().sum::<f64>() / count as f64,
+        };
+        let powers = powers
+            .into_iter()
+            .map(|power| power.unwrap_or(average))
+            .collect::<Vec<_>>();
+        Self {
+            distribution: (!powers.is_empty()).then(|| Distribution1D::new(powers)),
+        }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _: Vec3, _: Vec3, u: f64) -> Option<(usize, f64)> {
+        let distribution = self.distribution.as_ref()?;
+        let index = distribution.sample_discrete(u);
+        Some((index, distribution.discrete_pmf(index)))
+    }
+
+    fn pmf(&self, _: Vec3, _: Vec3, index: usize) -> f64 {
+        self.distribution
+            .as_ref()
+            .map_or(0.0, |distribution| distribution.discrete_pmf(index))
+    }
+}
+
+/// A node of a `BvhLightSampler`'s tree. An interior node's first child
+/// comes straight after it.
+struct LightNode {
+    bounds: LightBounds,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    /// The index of the light.
+    Leaf(usize),
+    /// The index of the second child.
+    Interior(usize),
+}
+
+/// A tree of lights, walked from the root by picking between the children
+/// of each node in proportion to how much light they could give the
+/// shading point. Nearby lights facing the point get picked far more often
+/// than ones that are far away or facing away, so adding more lights
+/// elsewhere doesn't take samples away from the ones that matter.
+///
+/// Lights infinitely far away can't go in the tree, so they're picked
+/// uniformly, with the tree as a whole getting the same chance as one of
+/// them.
+pub struct BvhLightSampler {
+    nodes: Vec<LightNode>,
+    /// For each light in the tree, the turns down to it from the root, one
+    /// bit per level, lowest first, with one for taking the second child.
+    trails: Vec<Option<u64>>,
+    infinite: Vec<usize>,
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: &[Box<dyn Light + '_>]) -> Self {
+        let mut bounded = Vec::new();
+        let mut infinite = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => infinite.push(index),
+            }
+        }
+        let mut sampler = Self {
+            nodes: Vec::new(),
+            trails: vec![None; lights.len()],
+            infinite,
+        };
+        if !bounded.is_empty() {
+            sampler.build(&mut bounded, 0, 0);
+        }
+        sampler
+    }
+
+    /// Adds the nodes for `lights`, reached from the root by `trail` over
+    /// `depth` levels, and returns their combined bounds.
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        if let [(index, bounds)] = *lights {
+            self.trails[index] = Some(trail);
+            self.nodes.push(LightNode {
+                bounds,
+                kind: NodeKind::Leaf(index),
+            });
+            return bounds;
+        }
+        // Split at the median along the axis the lights are most spread out
+        // on.
+        let centroids = lights
+            .iter()
+            .map(|(_, bounds)| bounds.bounds.centroid())
+            .collect::<Vec<_>>();
+        let centroids = Aabb::around(&centroids, 0.0);
+        let extent = centroids.max - centroids.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = lights.len() / 2;
+        lights.select_nth_unstable_by(middle, |(_, a), (_, b)| {
+            a.bounds
+                .centroid()
+                .component(axis)
+                .total_cmp(&b.bounds.centroid().component(axis))
+        });
+        let node = self.nodes.len();
+        self.nodes.push(LightNode {
+            bounds: lights[0].1,
+            kind: NodeKind::Interior(0),
+        });
+        let (first, second) = lights.split_at_mut(middle);
+        let first = self.build(first, trail, depth + 1);
+        let second_node = self.nodes.len();
+        let second = self.build(second, trail | 1 << depth, depth + 1);
+        let bounds = first.union(&second);
+        self.nodes[node] = LightNode {
+            bounds,
+            kind: NodeKind::Interior(second_node),
+        };
+        bounds
+    }
+
+    /// The chance of picking the tree rather than one of the infinite
+    /// lights.
+    fn tree_share(&self) -> f64 {
+        match self.nodes.is_empty() {
+            true => 0.0,
+            false => 1.0 / (self.infinite.len() + 1) as f64,
+        }
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(&self, point: Vec3, normal: Vec3, mut u: f64) -> Option<(usize, f64)> {
+        if self.nodes.is_empty() && self.infinite.is_empty() {
+            return None;
+        }
+        let tree_share = self.tree_share();
+        let infinite_share = 1.0 - tree_share;
+        if u < infinite_share {
+            let count = self.infinite.len();
+            let index = ((u / infinite_share * count as f64) as usize).min(count - 1);
+            return Some((self.infinite[index], infinite_share / count as f64));
+        }
+        u = ((u - infinite_share) / tree_share).min(1.0 - f64::EPSILON);
+        let mut pmf = tree_share;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(index) => {
+                    return (self.nodes[node].bounds.importance(point, normal) > 0.0)
+                        .then_some((index, pmf));
+                }
+                NodeKind::Interior(second) => {
+                    let first = self.nodes[node + 1].bounds.importance(point, normal);
+                    let total = first + self.nodes[second].bounds.importance(point, normal);
+                    if total == 0.0 {
+                        return None;
+                    }
+                    let p_first = first / total;
+                    if u < p_first {
+                        u = (u / p_first).min(1.0 - f64::EPSILON);
+                        pmf *= p_first;
+                        node += 1;
+                    } else {
+                        u = ((u - p_first) / (1.0 - p_first)).min(1.0 - f64::EPSILON);
+                        pmf *= 1.0 - p_first;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, point: Vec3, normal: Vec3, index: usize) -> f64 {
+        let mut trail = match self.trails.get(index) {
+            Some(Some(trail)) => *trail,
+            _ if self.infinite.contains(&index) => {
+                return (1.0 - self.tree_share()) / self.infinite.len() as f64;
+            }
+            _ => return 0.0,
+        };
+        let mut pmf = self.tree_share();
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                NodeKind::Leaf(_) => {
+                    return match self.nodes[node].bounds.importance(point, normal) > 0.0 {
+                        true => pmf,
+                        false => 0.0,
+                    };
+                }
+                NodeKind::Interior(second) => {
+                    let first = self.nodes[node + 1].bounds.importance(point, normal);
+                    let total = first + self.nodes[second].bounds.importance(point, normal);
+                    if total == 0.0 {
+                        return 0.0;
+                    }
+                    if trail & 1 == 0 {
+                        pmf *= first / total;
+                        node += 1;
+                    } else {
+                        pmf *= 1.0 - first / total;
+                        node = second;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::light::PointLight;
+
+    #[test]
+    fn bvh_prefers_nearby_lights_and_agrees_with_its_pmf() {
+        let mut lights: Vec<Box<dyn Light>> = Vec::new();
+        for i in 0..100 {
+            let position = Vec3::new(i as f64 * 10.0, 5.0, (i % 7) as f64);
+            lights.push(Box::new(PointLight::new(
+                position,
+                Vec3::new(1.0, 1.0, 1.0),
+            )));
+        }
+        let sampler = LightSamplerKind::Bvh.build(&lights);
+        let point = Vec3::new(0.0, 0.0, 0.0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+
+        let total = (0..lights.len())
+            .map(|index| sampler.pmf(point, normal, index))
+            .sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+
+        let samples = 10000;
+        let mut nearest = 0;
+        for i in 0..samples {
+            let u = (i as f64 + 0.5) / samples as f64;
+            let (index, pmf) = sampler.sample(point, normal, u).unwrap();
+            assert!((pmf - sampler.pmf(point, normal, index)).abs() < 1e-12);
+            if index == 0 {
+                nearest += 1;
+            }
+        }
+        // Uniform picking would give the nearest light 1% of the samples.
+        assert!(nearest > samples / 4, "{}", nearest);
+    }
+}
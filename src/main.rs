@@ -1,106 +1,497 @@
 #[macro_use]
 extern crate impl_ops;
 
+mod adaptive;
+mod animation;
+mod aov;
+mod bdpt;
+mod bvh;
 mod camera;
+mod color;
+mod debug;
+mod denoise;
+mod disk;
+mod distribution;
+mod film;
+mod filter;
 mod hit;
+mod image;
+mod integrator;
+mod light;
+mod light_sampler;
 mod material;
+mod mesh;
+mod mlt;
+mod normal_map;
+mod options;
+mod photon;
+mod preetham;
+mod principled;
 mod ray;
+mod rect;
+mod sampler;
+mod scene;
+mod shape;
+mod spectrum;
 mod sphere;
+mod subsurface;
+mod texture;
+mod triangle;
 mod vec3;
-use camera::Camera;
+use adaptive::{AdaptiveSampling, PixelStats};
+use animation::Keyframe;
+use aov::AovBuffers;
+use camera::{
+    autofocus, Aperture, Camera, CubemapCamera, EquirectangularCamera, FisheyeCamera,
+    OrthographicCamera, PerspectiveCamera, Projection,
+};
+use color::{Matrix, WorkingSpace};
+use disk::Disk;
+use film::{Film, Splats};
 use hit::Hittables;
-use material::{Dialectric, Lambertian, Material, Metal};
-use rand::{distributions::Uniform, prelude::Distribution, Rng};
-use ray::ray_color;
+use image::Image;
+use integrator::{Integrator, PathLength, SceneView};
+use light::{AreaLight, DirectionalLight, Light, PointLight, SpotLight};
+use material::{Dialectric, DiffuseLight, Lambertian, Material, Metal};
+use mesh::{Displacement, Mesh};
+use mlt::MltSettings;
+use normal_map::{NormalMap, Perturbed};
+use options::Options;
+use photon::PhotonSettings;
+use principled::Principled;
+use rand::Rng;
+use ray::{Radiance, Sky, SkyKind};
+use rect::Rect;
+use scene::{EmitterShape, Scene, SceneLight};
+use shape::Shape;
+use spectrum::{Conductor, Ior};
 use sphere::Sphere;
-use std::{cell::RefCell, error::Error, io::Write, rc::Rc};
+use std::{
+    cell::RefCell,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use subsurface::Subsurface;
+use texture::{Checker, ImageTexture, Texture};
+use triangle::Triangle;
 use vec3::Vec3;
 
 const ASPECT_RATIO: f64 = 3.0 / 2.0;
 const IMAGE_WIDTH: usize = 1200;
-const IMAGE_HEIGHT: usize = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as usize;
-const SAMPLES_PER_PIXEL: usize = 500;
 const MAX_DEPTH: u16 = 50;
+/// Rows of triangles in the sphere a displacement map is applied to.
+const DISPLACEMENT_SUBDIVISIONS: usize = 200;
+
+/// Everything in the scene, shared with the lights that are part of it.
+type World = Hittables<Rc<dyn Shape<'static>>>;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut stdout = std::io::stdout();
-    let mut rng = rand::thread_rng();
-    let between = Uniform::new(0.0, 1.0);
+    let options = Options::from_args()?;
 
     // World
-    let world = random_scene();
+    let mut world = random_scene(&options)?;
+    let sky = match options.sky {
+        SkyKind::Gradient => Sky::new(options.working_space),
+        SkyKind::Daylight => Sky::daylight(
+            options.sun_elevation,
+            options.sun_azimuth,
+            options.turbidity,
+            options.working_space,
+        ),
+    };
+    let length = PathLength {
+        max_depth: MAX_DEPTH,
+        roulette_depth: options.roulette_depth,
+    };
+    let photons = PhotonSettings {
+        photons: options.photons,
+        radius: options.photon_radius,
+        passes: options.photon_passes,
+        final_gather: options.final_gather,
+        seed: options.seed,
+    };
+    let mlt = MltSettings {
+        bootstrap: options.mlt_bootstrap,
+        chains: options.mlt_chains,
+        sigma: options.mlt_sigma,
+        large_step_probability: options.mlt_large_step,
+        seed: options.seed,
+    };
+    let integrator =
+        options
+            .integrator
+            .build(length, options.spectral, options.ao_radius, photons, mlt);
 
     // Camera
-    let look_from = Vec3::new(13.0, 2.0, 3.0);
-    let look_at = Vec3::new(0.0, 0.0, 0.0);
-    let vertical_up = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        vertical_up,
-        20.0,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
+    let defaults = Keyframe {
+        time: 0.0,
+        look_from: Vec3::new(13.0, 2.0, 3.0),
+        look_at: Vec3::new(0.0, 0.0, 0.0),
+        fov: options.fov.unwrap_or(match options.projection {
+            Projection::Fisheye => 180.0,
+            _ => 20.0,
+        }),
+        focus_dist: 10.0,
+    };
+    let mut scene = match &options.scene {
+        Some(path) => Scene::read(path, defaults)?,
+        None => Scene::still(defaults),
+    };
+    if let Some(frames) = options.frames {
+        scene.frames = frames.max(1);
+    }
+    let sequence = scene.frames > 1;
+    check_lights(&options, &scene.lights)?;
+    let mut lights = add_lights(&mut world, &scene.lights, options.working_space);
+    if let Some((direction, irradiance)) = sky.sun() {
+        lights.push(Box::new(DirectionalLight::new(direction, irradiance)));
+    }
+
+    for frame in 0..scene.frames {
+        if sequence {
+            eprintln!("Frame {} of {}", frame + 1, scene.frames);
+        }
+        let view = scene.camera_path.at(scene.frame_time(frame));
+        let camera = build_camera(&options, &world, &view, scene.vertical_up)?;
+        let output = match (&options.output, sequence) {
+            (Some(path), true) => Some(frame_path(path, frame)),
+            (None, true) => Some(frame_path(Path::new("frame_####.ppm"), frame)),
+            (path, false) => path.clone(),
+        };
+        let frame_path = |path: &PathBuf| match sequence {
+            true => frame_path(path, frame),
+            false => path.clone(),
+        };
+        render_frame(
+            &options,
+            &world,
+            &sky,
+            &lights,
+            integrator.as_ref(),
+            camera.as_ref(),
+            FrameOutputs {
+                image: output,
+                aov_dir: options.aov_dir.as_ref().map(frame_path),
+                sample_counts: options.sample_counts.as_ref().map(frame_path),
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// The size of the image for `projection`. Panoramas take in twice the
+/// angle across that they do up and down, so they get an image twice as
+/// wide as it is tall.
+fn image_size(projection: Projection) -> (usize, usize) {
+    let aspect_ratio = match projection {
+        Projection::Equirectangular => 2.0,
+        _ => ASPECT_RATIO,
+    };
+    (IMAGE_WIDTH, (IMAGE_WIDTH as f64 / aspect_ratio) as usize)
+}
+
+/// Where to write a frame. The image goes to stdout if it has no path.
+struct FrameOutputs {
+    image: Option<PathBuf>,
+    aov_dir: Option<PathBuf>,
+    sample_counts: Option<PathBuf>,
+}
+
+fn render_frame(
+    options: &Options,
+    world: &World,
+    sky: &Sky,
+    lights: &[Box<dyn Light>],
+    integrator: &dyn Integrator,
+    camera: &dyn Camera,
+    outputs: FrameOutputs,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = image_size(options.projection);
+    let sampling = AdaptiveSampling::new(
+        options.min_samples,
+        options.max_samples,
+        options.noise_threshold,
     );
+    let mut film = Film::new(width, height, options.filter.build(options.filter_radius));
+    let mut splats = Splats::new(width, height);
+    let light_sampler = options.light_sampler.build(lights);
+    let scene = SceneView {
+        world,
+        sky,
+        lights,
+        light_sampler: light_sampler.as_ref(),
+        camera,
+    };
+    let mut aovs = (outputs.aov_dir.is_some() || options.denoise)
+        .then(|| AovBuffers::new(width, height, options.filter, options.filter_radius));
+    let mut pixel_stats = vec![PixelStats::default(); width * height];
+    let mut sampler = options.sampler.build(sampling.max_samples, options.seed);
+    let exposure = match (options.lens(), options.integrator.is_physical()) {
+        (Some(lens), true) => lens.exposure(),
+        _ => 1.0,
+    };
+
+    // Integrators that work in passes get an even share of the samples in
+    // each.
+    let passes = integrator.passes();
+    let adaptive = !integrator.splats_only();
+    for pass in 0..passes {
+        integrator.start_pass(&scene, pass);
+        let budget = sampling.max_samples * (pass + 1) / passes;
+        for j in (0..height).rev() {
+            eprintln!("Scanlines remaining: {}", j);
+            for i in 0..width {
+                let stats = &mut pixel_stats[j * width + i];
+                while !(adaptive && sampling.done(stats)) && stats.count() < budget {
+                    sampler.start_sample(i, j, stats.count());
+                    let (du, dv) = sampler.next_2d();
+                    let u = (i as f64 + du) / width as f64;
+                    let v = (j as f64 + dv) / height as f64;
+                    let ray = camera.get_ray(u, v, sampler.as_mut());
+                    let radiance = match &ray {
+                        Some(ray) => {
+                            integrator.radiance(ray, &scene, sampler.as_mut(), &mut splats)
+                                * exposure
+                        }
+                        None => Radiance::default(),
+                    };
+                    let (x, y) = (i as f64 + du, j as f64 + dv);
+                    film.add_sample(x, y, radiance.total());
+                    if let Some(aovs) = aovs.as_mut() {
+                        aovs.add_sample(x, y, ray.as_ref(), world, &radiance);
+                    }
+                    stats.add(radiance.total());
+                }
+            }
+        }
+    }
+
+    let samples = pixel_stats.iter().map(PixelStats::count).sum();
+    let mut image = film.pixels();
+    let splats = splats
+        .pixels(samples)
+        .into_iter()
+        .map(|splat| splat * exposure)
+        .collect::<Vec<_>>();
+    for (pixel, splat) in image.iter_mut().zip(&splats) {
+        *pixel += splat.total();
+    }
+    if let Some(aovs) = aovs.as_mut() {
+        aovs.add_splats(&splats);
+    }
+    if let (true, Some(aovs)) = (options.denoise, &aovs) {
+        let variance = pixel_stats
+            .iter()
+            .map(PixelStats::variance_of_mean)
+            .collect::<Vec<_>>();
+        image = denoise::denoise(width, height, &image, &variance, &aovs.features());
+    }
+
+    write_image(options, outputs.image.as_deref(), &image)?;
+    if let (Some(aovs), Some(directory)) = (&aovs, &outputs.aov_dir) {
+        aovs.write(directory)?;
+    }
+    if let Some(path) = &outputs.sample_counts {
+        write_sample_counts(path, (width, height), &pixel_stats, sampling.max_samples)?;
+    }
+    Ok(())
+}
+
+/// Numbers a path for one frame of a sequence, replacing a run of `#` with
+/// the zero padded frame number, or adding the number before the extension.
+fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let text = path.to_string_lossy();
+    match text.find('#') {
+        Some(start) => {
+            let width = text[start..].chars().take_while(|c| *c == '#').count();
+            let number = format!("{:0width$}", frame, width = width);
+            PathBuf::from(format!(
+                "{}{}{}",
+                &text[..start],
+                number,
+                &text[start + width..]
+            ))
+        }
+        None => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(extension) => {
+                    format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy())
+                }
+                None => format!("{}_{:04}", stem, frame),
+            };
+            path.with_file_name(name)
+        }
+    }
+}
 
-    stdout.write_all(format!("P3\n{} {}\n255\n", IMAGE_WIDTH, IMAGE_HEIGHT).as_bytes())?;
-    for j in (0..IMAGE_HEIGHT).rev() {
-        eprintln!("Scanlines remaining: {}", j);
-        for i in 0..IMAGE_WIDTH {
-            let mut color = Vec3::new(0.0, 0.0, 0.0);
-            for _ in 0..SAMPLES_PER_PIXEL {
-                let u = (i as f64 + between.sample(&mut rng)) / (IMAGE_WIDTH - 1) as f64;
-                let v = (j as f64 + between.sample(&mut rng)) / (IMAGE_HEIGHT - 1) as f64;
-                let ray = camera.get_ray(u, v);
-                color += ray_color(&ray, &world, MAX_DEPTH);
+fn build_camera(
+    options: &Options,
+    world: &World,
+    view: &Keyframe,
+    vertical_up: Vec3,
+) -> Result<Box<dyn Camera>, Box<dyn Error>> {
+    let look_from = view.look_from;
+    let look_at = view.look_at;
+    let (vertical_fov, aperture) = match options.lens() {
+        Some(lens) => (lens.vertical_fov(ASPECT_RATIO), lens.aperture()),
+        None => (view.fov, 0.1),
+    };
+    let dist_to_focus = if options.autofocus {
+        autofocus(world, look_from, look_at).unwrap_or(view.focus_dist)
+    } else {
+        view.focus_dist
+    };
+
+    let camera: Box<dyn Camera> = match options.projection {
+        Projection::Perspective => {
+            let aperture_shape = match (&options.aperture_mask, options.aperture_blades) {
+                (Some(path), _) => Aperture::mask(&Image::read_netpbm(path)?)?,
+                (None, Some(blades)) => Aperture::Polygon {
+                    blades,
+                    rotation: options.aperture_rotation,
+                },
+                (None, None) => Aperture::Circle,
+            };
+            Box::new(
+                PerspectiveCamera::new(
+                    look_from,
+                    look_at,
+                    vertical_up,
+                    vertical_fov,
+                    ASPECT_RATIO,
+                    aperture,
+                    dist_to_focus,
+                )
+                .with_aperture(aperture_shape)
+                .with_cats_eye(options.cats_eye)
+                .with_tilt_shift(options.tilt, options.shift),
+            )
+        }
+        Projection::Orthographic => {
+            // Frame the same view the perspective camera sees at the focus
+            // distance.
+            let fov = vertical_fov.to_radians();
+            Box::new(OrthographicCamera::new(
+                look_from,
+                look_at,
+                vertical_up,
+                2.0 * dist_to_focus * (fov / 2.0).tan(),
+                ASPECT_RATIO,
+            ))
+        }
+        Projection::Fisheye => Box::new(FisheyeCamera::new(
+            look_from,
+            look_at,
+            vertical_up,
+            view.fov,
+            ASPECT_RATIO,
+        )),
+        Projection::Equirectangular => {
+            Box::new(EquirectangularCamera::new(look_from, look_at, vertical_up))
+        }
+        Projection::Cubemap => Box::new(CubemapCamera::new(look_from, look_at, vertical_up)),
+    };
+    Ok(camera)
+}
+
+/// Writes the image in the output color space, as PNG or EXR if the path
+/// asks for it and as a plain PPM otherwise.
+fn write_image(
+    options: &Options,
+    path: Option<&Path>,
+    image: &[Vec3],
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = image_size(options.projection);
+    let space = options.output_space;
+    let conversion = Matrix::conversion(options.working_space.primaries(), space.primaries());
+    // Formats want the top row first, film rows start at the bottom.
+    let linear = image
+        .chunks(width)
+        .rev()
+        .flatten()
+        .map(|color| conversion * *color)
+        .collect::<Vec<_>>();
+    let encoded = || {
+        linear
+            .iter()
+            .map(|color| {
+                // Filters with negative lobes can ring below zero around
+                // edges, which encode clamps.
+                [color.x, color.y, color.z]
+                    .map(|value| (255.0 * options.encoding.encode(space, value)).round() as u8)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let extension = path
+        .and_then(Path::extension)
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let mut writer: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match extension.as_deref() {
+        Some("png") => image::write_png(
+            &mut writer,
+            width,
+            height,
+            &encoded(),
+            space,
+            options.encoding,
+        )?,
+        Some("exr") => image::write_exr(&mut writer, width, height, &linear, space.primaries())?,
+        _ => {
+            writer.write_all(format!("P3\n{} {}\n255\n", width, height).as_bytes())?;
+            for [r, g, b] in encoded() {
+                writer.write_all(format!("{} {} {}\n", r, g, b).as_bytes())?;
             }
-            write_color(&stdout, color, SAMPLES_PER_PIXEL)?;
+            writer.flush()?;
         }
     }
-    stdout.flush()?;
     Ok(())
 }
 
-fn write_color(
-    mut stdout: &std::io::Stdout,
-    color: Vec3,
-    samples_per_pixel: usize,
+/// Writes a greyscale image where brighter pixels took more samples.
+fn write_sample_counts(
+    path: &Path,
+    (width, height): (usize, usize),
+    pixel_stats: &[PixelStats],
+    max_samples: usize,
 ) -> Result<(), Box<dyn Error>> {
-    let mut r = color.x;
-    let mut g = color.y;
-    let mut b = color.z;
-    let scale = 1.0 / samples_per_pixel as f64;
-    r = (scale * r).sqrt();
-    g = (scale * g).sqrt();
-    b = (scale * b).sqrt();
-
-    let c = 256.0;
-    stdout.write_all(
-        &format!(
-            "{} {} {}\n",
-            (c * r.clamp(0.0, 0.999)) as usize,
-            (c * g.clamp(0.0, 0.999)) as usize,
-            (c * b.clamp(0.0, 0.999)) as usize,
-        )
-        .as_bytes(),
-    )?;
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(format!("P3\n{} {}\n255\n", width, height).as_bytes())?;
+    for row in pixel_stats.chunks(width).rev() {
+        for stats in row {
+            let level = (255.0 * stats.count() as f64 / max_samples as f64).min(255.0) as usize;
+            file.write_all(format!("{} {} {}\n", level, level, level).as_bytes())?;
+        }
+    }
+    file.flush()?;
     Ok(())
 }
 
-fn random_scene() -> Hittables<Sphere<'static>> {
+/// The book's cover scene: three big spheres among many small random ones.
+/// With `--showcase` the small ones and the glass sphere show off the
+/// measured metals, dispersive glass, wax and principled materials too,
+/// some of them cut away into a lattice. The big matte sphere is the only
+/// one the normal, bump and displacement maps are applied to.
+fn random_scene(options: &Options) -> Result<World, Box<dyn Error>> {
+    let space = options.working_space;
     let mut rng = rand::thread_rng();
-    let mut world = Hittables::new();
-    let material_ground = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    let mut world: World = Hittables::new();
+    let material_ground = Box::new(Lambertian::new(
+        space.convert_from_srgb(Vec3::new(0.5, 0.5, 0.5)),
+    ));
     let material_ground = package_material(material_ground);
-    world.add(Sphere::new(
+    world.add(Rc::new(Sphere::new(
         Vec3::new(0.0, -1000.0, 0.0),
         1000.0,
         material_ground,
-    ));
+    )));
     for a in -11..11 {
         for b in -11..11 {
             let choose_mat = rng.gen_range(0.0..1.0);
@@ -110,33 +501,258 @@ fn random_scene() -> Hittables<Sphere<'static>> {
                 b as f64 + 0.9 * rng.gen_range(0.0..1.0),
             );
             if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                let material = if choose_mat < 0.8 {
+                let material = if options.showcase {
+                    showcase_material(choose_mat, &mut rng, space)
+                } else if choose_mat < 0.8 {
                     let albedo = Vec3::random(0.0, 1.0) * Vec3::random(0.0, 1.0);
-                    let material = Lambertian::new(albedo);
+                    let material = Lambertian::new(space.convert_from_srgb(albedo));
                     package_material(Box::new(material))
                 } else if choose_mat < 0.95 {
                     let albedo = Vec3::random(0.5, 1.0);
                     let fuzz = rng.gen_range(0.0..0.5);
-                    let material = Metal::new(albedo, fuzz);
+                    let material = Metal::new(space.convert_from_srgb(albedo), fuzz);
                     package_material(Box::new(material))
                 } else {
                     package_material(Box::new(Dialectric::new(1.5)))
                 };
                 let sphere = Sphere::new(center, 0.2, material);
-                world.add(sphere);
+                world.add(Rc::new(sphere));
             }
         }
     }
-    let material = package_material(Box::new(Dialectric::new(1.5)));
-    let sphere = Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, material);
-    world.add(sphere);
-    let material = package_material(Box::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1))));
-    let sphere = Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, material);
-    world.add(sphere);
-    let material = package_material(Box::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)));
+    let glass = if options.showcase {
+        Dialectric::dispersive(Ior::bk7())
+    } else {
+        Dialectric::new(1.5)
+    };
+    let sphere = Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        package_material(Box::new(glass)),
+    );
+    world.add(Rc::new(sphere));
+    let material = package_material(Box::new(Lambertian::new(
+        space.convert_from_srgb(Vec3::new(0.4, 0.2, 0.1)),
+    )));
+    world.add(mapped_sphere(
+        Vec3::new(-4.0, 1.0, 0.0),
+        1.0,
+        material,
+        options,
+    )?);
+    let material = package_material(Box::new(Metal::new(
+        space.convert_from_srgb(Vec3::new(0.7, 0.6, 0.5)),
+        0.0,
+    )));
     let sphere = Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, material);
-    world.add(sphere);
-    world
+    world.add(Rc::new(sphere));
+    Ok(world)
+}
+
+/// A material for one of the small spheres in the showcase scene, which
+/// adds measured metals, dispersive glass and newer materials to the mix.
+fn showcase_material(
+    choose_mat: f64,
+    rng: &mut impl Rng,
+    space: WorkingSpace,
+) -> Rc<RefCell<Box<dyn Material>>> {
+    if choose_mat < 0.7 {
+        let albedo = Vec3::random(0.0, 1.0) * Vec3::random(0.0, 1.0);
+        package_material(Box::new(Lambertian::new(space.convert_from_srgb(albedo))))
+    } else if choose_mat < 0.75 {
+        package_material(Box::new(random_principled(rng, space)))
+    } else if choose_mat < 0.8 {
+        // Wax, with light going furthest in the channels it reflects most.
+        let albedo = space.convert_from_srgb(Vec3::random(0.5, 1.0));
+        package_material(Box::new(Subsurface::new(albedo, 0.1 * albedo, 1.4)))
+    } else if choose_mat < 0.84 {
+        let conductors = [
+            Conductor::Gold,
+            Conductor::Silver,
+            Conductor::Copper,
+            Conductor::Aluminium,
+        ];
+        let conductor = conductors[rng.gen_range(0..conductors.len())];
+        let fuzz = rng.gen_range(0.0..0.5);
+        package_material(Box::new(Metal::conductor(conductor, fuzz, space)))
+    } else if choose_mat < 0.95 {
+        let albedo = Vec3::random(0.5, 1.0);
+        let fuzz = rng.gen_range(0.0..0.5);
+        package_material(Box::new(Metal::new(space.convert_from_srgb(albedo), fuzz)))
+    } else if choose_mat < 0.975 {
+        package_material(Box::new(Dialectric::new(1.5)))
+    } else {
+        // Dense flint glass, which splits light more than most.
+        let ior = Ior::Cauchy {
+            a: 1.7280,
+            b: 0.01342,
+        };
+        package_material(Box::new(Dialectric::dispersive(ior)))
+    }
+}
+
+/// A sphere with the surface detail from the options' maps: displaced into
+/// a mesh if there's a displacement map, then with its normals bent by the
+/// normal map and bump map in turn.
+fn mapped_sphere(
+    center: Vec3,
+    radius: f64,
+    material: Rc<RefCell<Box<dyn Material>>>,
+    options: &Options,
+) -> Result<Rc<dyn Shape<'static>>, Box<dyn Error>> {
+    let read = |path: &PathBuf| -> Result<Box<dyn Texture>, Box<dyn Error>> {
+        Ok(Box::new(ImageTexture::new(Image::read_netpbm(path)?)?))
+    };
+    let mut shape: Rc<dyn Shape> = match &options.displacement_map {
+        Some(path) => {
+            let displacement = Displacement {
+                height: read(path)?,
+                scale: options.displacement_scale,
+            };
+            Rc::new(Mesh::sphere(
+                center,
+                radius,
+                DISPLACEMENT_SUBDIVISIONS,
+                Some(&displacement),
+                material,
+            ))
+        }
+        None => Rc::new(Sphere::new(center, radius, material)),
+    };
+    if let Some(path) = &options.normal_map {
+        shape = Rc::new(Perturbed::new(shape, NormalMap::TangentSpace(read(path)?)));
+    }
+    if let Some(path) = &options.bump_map {
+        let map = NormalMap::Bump {
+            height: read(path)?,
+            strength: options.bump_strength,
+        };
+        shape = Rc::new(Perturbed::new(shape, map));
+    }
+    Ok(shape)
+}
+
+/// A principled material with a random mix of lobes, sometimes checkered or
+/// cut away.
+fn random_principled(rng: &mut impl Rng, space: WorkingSpace) -> Principled {
+    let color = space.convert_from_srgb(Vec3::random(0.2, 1.0));
+    let base_color: Box<dyn Texture> = if rng.gen_bool(0.3) {
+        Box::new(Checker::new(0.05, color, 0.2 * color))
+    } else {
+        Box::new(color)
+    };
+    let metallic = if rng.gen_bool(0.3) { 1.0 } else { 0.0 };
+    let transmission = if rng.gen_bool(0.2) { 1.0 } else { 0.0 };
+    // Some are cut into a lattice, to show what's behind them.
+    let (alpha, alpha_cutoff): (Box<dyn Texture>, _) = if rng.gen_bool(0.2) {
+        (Box::new(Checker::new(0.07, 1.0, 0.0)), Some(0.5))
+    } else {
+        (Box::new(1.0), None)
+    };
+    Principled {
+        metallic: Box::new(metallic),
+        roughness: Box::new(rng.gen_range(0.0..0.6)),
+        clearcoat: Box::new(if rng.gen_bool(0.3) { 1.0 } else { 0.0 }),
+        sheen: Box::new(rng.gen_range(0.0..0.5)),
+        transmission: Box::new(transmission),
+        alpha,
+        alpha_cutoff,
+        ..Principled::new(base_color)
+    }
+}
+
+/// Rejects scene file lights the integrator would leave dark. Point, spot
+/// and sun lights are delta lights, which only shadow rays can reach, and
+/// glowing shapes are only seen by paths that add what they hit.
+fn check_lights(options: &Options, lights: &[SceneLight]) -> Result<(), Box<dyn Error>> {
+    let integrator = options.integrator;
+    if !integrator.is_physical() {
+        return Ok(());
+    }
+    let area = lights
+        .iter()
+        .any(|light| matches!(light, SceneLight::Area { .. }));
+    if area && !integrator.hits_emitters() {
+        return Err(
+            "sphere, rect, triangle and disk lights need the path or mlt integrator".into(),
+        );
+    }
+    let delta = lights
+        .iter()
+        .any(|light| !matches!(light, SceneLight::Area { .. }));
+    if delta && !integrator.samples_lights(options.spectral) {
+        return Err(
+            "point, spot and sun lights need the path or mlt integrator without --spectral".into(),
+        );
+    }
+    Ok(())
+}
+
+/// Builds the scene file's lights, adding the ones with a shape to the
+/// world too.
+fn add_lights(
+    world: &mut World,
+    lights: &[SceneLight],
+    space: WorkingSpace,
+) -> Vec<Box<dyn Light>> {
+    lights
+        .iter()
+        .enumerate()
+        .map(|(index, light)| -> Box<dyn Light> {
+            match *light {
+                SceneLight::Area { shape, radiance } => {
+                    let material = Box::new(DiffuseLight::new(space.convert_from_srgb(radiance)));
+                    let material = package_material(material);
+                    let shape: Rc<dyn Shape> = match shape {
+                        EmitterShape::Sphere { center, radius } => {
+                            Rc::new(Sphere::new(center, radius, material))
+                        }
+                        EmitterShape::Rect { corner, u, v } => {
+                            Rc::new(Rect::new(corner, u, v, material))
+                        }
+                        EmitterShape::Triangle { vertices } => {
+                            Rc::new(Triangle::new(vertices, material))
+                        }
+                        EmitterShape::Disk {
+                            center,
+                            normal,
+                            radius,
+                        } => Rc::new(Disk::new(center, normal, radius, material)),
+                    };
+                    let light = Rc::new(AreaLight::new(shape, index));
+                    world.add(light.clone());
+                    Box::new(light)
+                }
+                SceneLight::Point {
+                    position,
+                    intensity,
+                } => Box::new(PointLight::new(
+                    position,
+                    space.convert_from_srgb(intensity),
+                )),
+                SceneLight::Spot {
+                    position,
+                    direction,
+                    cone,
+                    falloff_start,
+                    intensity,
+                } => Box::new(SpotLight::new(
+                    position,
+                    direction,
+                    cone,
+                    falloff_start,
+                    space.convert_from_srgb(intensity),
+                )),
+                SceneLight::Sun {
+                    direction,
+                    irradiance,
+                } => Box::new(DirectionalLight::new(
+                    direction,
+                    space.convert_from_srgb(irradiance),
+                )),
+            }
+        })
+        .collect()
 }
 
 fn package_material(material: Box<dyn Material>) -> Rc<RefCell<Box<dyn Material>>> {
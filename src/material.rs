@@ -1,9 +1,12 @@
 use crate::{
+    color::WorkingSpace,
     hit::HitRecord,
     ray::Ray,
-    vec3::{dot, random_in_unit_sphere, random_unit_vector, reflect, unit_vector, Vec3},
+    sampler::{sample_in_unit_sphere, sample_unit_vector, Sampler},
+    spectrum::{Conductor, Ior, Spectrum, Wavelengths, WAVELENGTHS},
+    vec3::{dot, reflect, unit_vector, Vec3},
 };
-use rand::Rng;
+use std::f64::consts::PI;
 
 pub trait Material {
     fn scatter(
@@ -12,7 +15,66 @@ pub trait Material {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool;
+
+    /// Scatters a path carrying `wavelengths`. Materials whose response
+    /// isn't spectral can rely on this, which upsamples their RGB
+    /// attenuation.
+    fn scatter_spectral(
+        &mut self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        wavelengths: &mut Wavelengths,
+        attenuation: &mut Spectrum,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let mut rgb = Vec3::default();
+        let scatters = self.scatter(ray_in, record, &mut rgb, scattered, sampler);
+        *attenuation = Spectrum::from_rgb(rgb, wavelengths);
+        scatters
+    }
+
+    /// How much of the surface is there at the hit, from zero where it's
+    /// cut away to one where it's solid. Rays go straight through the
+    /// missing part, shadow rays included.
+    ///
+    /// Only `Hittables` looks at alpha, as it traces rays through the scene.
+    /// Shapes hit on their own, and `Shape::pdf` and `Shape::sample` on area
+    /// lights, see the whole surface, so cut away emitters aren't supported.
+    fn alpha(&self, _record: &HitRecord) -> f64 {
+        1.0
+    }
+
+    /// The overall color of the surface, as written to the albedo AOV.
+    fn albedo(&self, record: &HitRecord) -> Vec3;
+
+    /// Whether `scatter` only picks mirror-like directions, which can be
+    /// followed but never connected to from elsewhere. Materials that don't
+    /// evaluate `bsdf` and `pdf` count as specular, fuzzy metals included.
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    /// The fraction of light arriving from `wi` that leaves towards `wo`,
+    /// per unit solid angle and without the cosine term. Both directions
+    /// point away from the surface.
+    fn bsdf(&self, _record: &HitRecord, _wo: Vec3, _wi: Vec3) -> Vec3 {
+        Vec3::default()
+    }
+
+    /// The density, per unit solid angle, with which `scatter` picks `wi`
+    /// for light leaving towards `wo`.
+    fn pdf(&self, _record: &HitRecord, _wo: Vec3, _wi: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Light the surface gives off at the hit, back along the ray that
+    /// found it.
+    fn emitted(&self, _record: &HitRecord) -> Vec3 {
+        Vec3::default()
+    }
 }
 
 pub struct Lambertian {
@@ -32,8 +94,9 @@ impl Material for Lambertian {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
-        let mut scatter_direction = record.normal + random_unit_vector();
+        let mut scatter_direction = record.normal + sample_unit_vector(sampler.next_2d());
         if scatter_direction.near_zero() {
             scatter_direction = record.normal;
         }
@@ -41,17 +104,51 @@ impl Material for Lambertian {
         *attenuation = self.albedo;
         true
     }
+
+    fn albedo(&self, _: &HitRecord) -> Vec3 {
+        self.albedo
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn bsdf(&self, record: &HitRecord, wo: Vec3, wi: Vec3) -> Vec3 {
+        if dot(wo, record.normal) > 0.0 && dot(wi, record.normal) > 0.0 {
+            self.albedo / PI
+        } else {
+            Vec3::default()
+        }
+    }
+
+    fn pdf(&self, record: &HitRecord, _: Vec3, wi: Vec3) -> f64 {
+        dot(unit_vector(wi), record.normal).max(0.0) / PI
+    }
 }
 
 pub struct Metal {
     albedo: Vec3,
     fuzz: f64,
+    conductor: Option<Conductor>,
 }
 
 impl Metal {
     pub fn new(albedo: Vec3, fuzz: f64) -> Self {
         let fuzz = if fuzz < 1.0 { fuzz } else { 1.0 };
-        Self { albedo, fuzz }
+        Self {
+            albedo,
+            fuzz,
+            conductor: None,
+        }
+    }
+
+    /// A metal with a measured index of refraction. Spectral renders use
+    /// the full Fresnel equations, RGB ones its color at normal incidence.
+    pub fn conductor(conductor: Conductor, fuzz: f64, space: WorkingSpace) -> Self {
+        Self {
+            conductor: Some(conductor),
+            ..Self::new(space.convert_from_srgb(conductor.rgb()), fuzz)
+        }
     }
 }
 
@@ -62,24 +159,60 @@ impl Material for Metal {
         record: &HitRecord,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
     ) -> bool {
         let reflected = reflect(unit_vector(ray_in.direction), record.normal);
         *scattered = Ray::new(
             record.point,
-            reflected + self.fuzz * random_in_unit_sphere(),
+            reflected + self.fuzz * sample_in_unit_sphere(sampler.next_2d(), sampler.next_1d()),
         );
         *attenuation = self.albedo;
         dot(scattered.direction, record.normal) > 0.0
     }
+
+    fn scatter_spectral(
+        &mut self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        wavelengths: &mut Wavelengths,
+        attenuation: &mut Spectrum,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let mut rgb = Vec3::default();
+        let scatters = self.scatter(ray_in, record, &mut rgb, scattered, sampler);
+        *attenuation = match self.conductor {
+            Some(conductor) => {
+                let cos_theta = dot(-unit_vector(ray_in.direction), record.normal);
+                let mut reflectance = [0.0; WAVELENGTHS];
+                for (value, lambda) in reflectance.iter_mut().zip(wavelengths.lambda.iter()) {
+                    *value = conductor.reflectance(cos_theta, *lambda);
+                }
+                Spectrum(reflectance)
+            }
+            None => Spectrum::from_rgb(rgb, wavelengths),
+        };
+        scatters
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Vec3 {
+        self.albedo
+    }
 }
 
 pub struct Dialectric {
-    refraction_index: f64,
+    ior: Ior,
 }
 
 impl Dialectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::dispersive(Ior::Constant(refraction_index))
+    }
+
+    /// Glass whose index varies with wavelength. RGB renders use its index
+    /// at the d line.
+    pub fn dispersive(ior: Ior) -> Self {
+        Self { ior }
     }
 
     pub fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -88,38 +221,110 @@ impl Dialectric {
         r0 = r0.powi(2);
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
-}
 
-impl Material for Dialectric {
-    fn scatter(
-        &mut self,
+    fn bend(
         ray_in: &Ray,
         record: &HitRecord,
-        attenuation: &mut Vec3,
-        scattered: &mut Ray,
-    ) -> bool {
-        // TODO prob not great to get this each call
-        let mut rng = rand::thread_rng();
-        *attenuation = Vec3::new(1.0, 1.0, 1.0);
+        refraction_index: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Ray {
         let refraction_ratio = if record.front_face {
-            1.0 / self.refraction_index
+            1.0 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
         let unit_direction = unit_vector(ray_in.direction);
         let cos_theta = f64::min(dot(-unit_direction, record.normal), 1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction = if cannot_refract
-            || Dialectric::reflectance(cos_theta, refraction_ratio) > rng.gen_range(0.0..1.0)
+            || Dialectric::reflectance(cos_theta, refraction_ratio) > sampler.next_1d()
         {
             reflect(unit_direction, record.normal)
         } else {
             refract(unit_direction, record.normal, refraction_ratio)
         };
-        *scattered = Ray::new(record.point, direction);
+        Ray::new(record.point, direction)
+    }
+}
+
+impl Material for Dialectric {
+    fn scatter(
+        &mut self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        *attenuation = Vec3::new(1.0, 1.0, 1.0);
+        *scattered = Dialectric::bend(ray_in, record, self.ior.nominal(), sampler);
+        true
+    }
+
+    fn scatter_spectral(
+        &mut self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        wavelengths: &mut Wavelengths,
+        attenuation: &mut Spectrum,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        // Each wavelength refracts its own way, so only the hero can carry on.
+        if self.ior.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+        *attenuation = Spectrum::constant(1.0);
+        let refraction_index = self.ior.at(wavelengths.hero());
+        *scattered = Dialectric::bend(ray_in, record, refraction_index, sampler);
         true
     }
+
+    fn albedo(&self, _: &HitRecord) -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+}
+
+/// A surface that glows evenly in every direction from its front, and
+/// reflects nothing.
+pub struct DiffuseLight {
+    radiance: Vec3,
+}
+
+impl DiffuseLight {
+    pub fn new(radiance: Vec3) -> Self {
+        Self { radiance }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &mut self,
+        _: &Ray,
+        _: &HitRecord,
+        _: &mut Vec3,
+        _: &mut Ray,
+        _: &mut dyn Sampler,
+    ) -> bool {
+        false
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Vec3 {
+        Vec3::default()
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn emitted(&self, record: &HitRecord) -> Vec3 {
+        if record.front_face {
+            self.radiance
+        } else {
+            Vec3::default()
+        }
+    }
 }
 
 pub fn refract(uv: Vec3, normal: Vec3, etai_over_etat: f64) -> Vec3 {
@@ -0,0 +1,246 @@
+use crate::{
+    bvh::Aabb,
+    distribution::Distribution1D,
+    hit::{tangent_frame, Hit, HitRecord, Hittables},
+    material::Material,
+    ray::Ray,
+    shape::{Shape, ShapeSample},
+    texture::Texture,
+    triangle::Triangle,
+    vec3::{cross, unit_vector, Vec3},
+};
+use std::{cell::RefCell, f64::consts::PI, rc::Rc};
+
+/// Heights that push a surface out along its normals before it's cut into
+/// triangles, so that the detail changes the outline and casts shadows.
+pub struct Displacement {
+    pub height: Box<dyn Texture>,
+    /// How far out a height of one pushes the surface.
+    pub scale: f64,
+}
+
+/// A surface made of many small triangles, shaded smoothly across them.
+pub struct Mesh<'material> {
+    triangles: Vec<Rc<Triangle<'material>>>,
+    hittables: Hittables<Rc<Triangle<'material>>>,
+    /// For picking triangles by area.
+    areas: Distribution1D,
+    area: f64,
+}
+
+impl<'material> Mesh<'material> {
+    /// A sphere cut into `subdivisions` rows of twice as many triangle
+    /// pairs each, going round it the way a `Sphere`'s `uv` does, pushed
+    /// out by `displacement`.
+    pub fn sphere(
+        center: Vec3,
+        radius: f64,
+        subdivisions: usize,
+        displacement: Option<&Displacement>,
+        material: Rc<RefCell<Box<dyn Material + 'material>>>,
+    ) -> Self {
+        let (rows, columns) = (subdivisions.max(2), 2 * subdivisions.max(2));
+        let mut points = Vec::with_capacity((rows + 1) * (columns + 1));
+        let mut uvs = Vec::with_capacity(points.capacity());
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let uv = (column as f64 / columns as f64, row as f64 / rows as f64);
+                let (theta, phi) = (uv.1 * PI, uv.0 * 2.0 * PI);
+                let normal = Vec3::new(
+                    -theta.sin() * phi.cos(),
+                    -theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let height = displacement.map_or(0.0, |displacement| {
+                    // Every vertex at a pole is the same point, so they
+                    // all take the height at the middle of the image.
+                    let at = match row == 0 || row == rows {
+                        true => (0.5, uv.1),
+                        false => uv,
+                    };
+                    let (tangent, bitangent) = tangent_frame(
+                        normal,
+                        Vec3::new(normal.z, 0.0, -normal.x),
+                        Vec3::new(0.0, 1.0, 0.0),
+                    );
+                    let record = HitRecord {
+                        point: center + radius * normal,
+                        normal,
+                        t: 0.0,
+                        front_face: true,
+                        uv: at,
+                        barycentric: None,
+                        material: material.clone(),
+                        light: None,
+                        tangent,
+                        bitangent,
+                    };
+                    displacement.scale * displacement.height.scalar(&record)
+                });
+                points.push(center + (radius + height) * normal);
+                uvs.push(uv);
+            }
+        }
+
+        let index = |row: usize, column: usize| row * (columns + 1) + column;
+        // Normals across the pushed out surface, from the neighbouring
+        // points, going round the seam and using the undisplaced normal at
+        // the poles where the neighbours meet.
+        let normals = (0..=rows)
+            .flat_map(|row| (0..=columns).map(move |column| (row, column)))
+            .map(|(row, column)| {
+                let point = points[index(row, column)];
+                if row == 0 || row == rows {
+                    return unit_vector(point - center);
+                }
+                let previous = if column == 0 { columns - 1 } else { column - 1 };
+                let next = if column == columns { 1 } else { column + 1 };
+                let across = points[index(row, next)] - points[index(row, previous)];
+                let up = points[index(row + 1, column)] - points[index(row - 1, column)];
+                let normal = cross(across, up);
+                if normal.near_zero() {
+                    unit_vector(point - center)
+                } else {
+                    unit_vector(normal)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut triangles = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let corners = [
+                    index(row, column),
+                    index(row, column + 1),
+                    index(row + 1, column + 1),
+                    index(row + 1, column),
+                ];
+                for [a, b, c] in [
+                    [corners[0], corners[1], corners[3]],
+                    [corners[1], corners[2], corners[3]],
+                ] {
+                    let vertices = [points[a], points[b], points[c]];
+                    // The triangles touching the poles are half squashed
+                    // flat.
+                    if cross(vertices[1] - vertices[0], vertices[2] - vertices[0]).near_zero() {
+                        continue;
+                    }
+                    triangles.push(Rc::new(Triangle::smooth(
+                        vertices,
+                        [normals[a], normals[b], normals[c]],
+                        [uvs[a], uvs[b], uvs[c]],
+                        material.clone(),
+                    )));
+                }
+            }
+        }
+        Self::new(triangles)
+    }
+
+    fn new(triangles: Vec<Rc<Triangle<'material>>>) -> Self {
+        let mut hittables = Hittables::new();
+        for triangle in &triangles {
+            hittables.add(triangle.clone());
+        }
+        let areas = triangles
+            .iter()
+            .map(|triangle| triangle.area())
+            .collect::<Vec<_>>();
+        Self {
+            area: areas.iter().sum(),
+            areas: Distribution1D::new(areas),
+            triangles,
+            hittables,
+        }
+    }
+}
+
+impl<'material> Hit<'material> for Mesh<'material> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        self.hittables.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.hittables.bounding_box()
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        self.hittables.specular_bounds()
+    }
+
+    fn hit_counting(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<HitRecord<'material>> {
+        self.hittables.hit_counting(ray, t_min, t_max, tests)
+    }
+}
+
+impl<'material> Shape<'material> for Mesh<'material> {
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        // Reuse the part of the first number left over after picking the
+        // triangle.
+        let count = self.triangles.len();
+        let (x, _) = self.areas.sample_continuous(u.0);
+        let index = ((x * count as f64) as usize).min(count - 1);
+        let sample = self.triangles[index].sample_area((x * count as f64 - index as f64, u.1));
+        ShapeSample {
+            pdf: 1.0 / self.area,
+            ..sample
+        }
+    }
+
+    /// Uses the true normal of the triangle hit, which the smooth shading
+    /// hides.
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        let ray = Ray::new(reference, unit_vector(direction));
+        match self.hittables.hit_object(&ray, 0.001, f64::INFINITY) {
+            Some((index, record)) => {
+                self.triangles[index].density(record.t, ray.direction, self.area)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn sphere_meshes_follow_the_sphere_pushed_out_by_the_displacement() {
+        let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let material = Rc::new(RefCell::new(material));
+        let displacement = Displacement {
+            height: Box::new(1.0),
+            scale: 0.5,
+        };
+        let plain = Mesh::sphere(Vec3::default(), 1.0, 32, None, material.clone());
+        let pushed = Mesh::sphere(Vec3::default(), 1.0, 32, Some(&displacement), material);
+        assert!((plain.area() - 4.0 * PI).abs() < 0.05, "{}", plain.area());
+
+        for direction in [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.3, 0.9, -0.2),
+            Vec3::new(0.0, -1.0, 0.0),
+        ] {
+            let direction = unit_vector(direction);
+            let ray = Ray::new(Vec3::default(), direction);
+            for (mesh, radius) in [(&plain, 1.0), (&pushed, 1.5)] {
+                let record = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+                assert!((record.t - radius).abs() < 0.01 * radius, "{}", record.t);
+                // Hit from inside, facing back towards the middle.
+                assert!(!record.front_face);
+                assert!((record.normal + direction).length() < 0.01);
+            }
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use crate::{
+    distribution::Distribution1D,
+    film::Splats,
+    integrator::{Integrator, PathTracer, SceneView},
+    ray::{Radiance, Ray},
+    sampler::{hash, IndependentSampler, Sampler},
+};
+use std::{cell::RefCell, f64::consts::PI};
+
+/// A coordinate of primary sample space, with what it was before the
+/// current mutation in case that's rejected.
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    /// The iteration that last changed the value.
+    modified: usize,
+    backup: f64,
+    backup_modified: usize,
+}
+
+/// A sampler whose random numbers are a point in primary sample space that
+/// can be mutated, following Kelemen et al.
+///
+/// Each iteration either makes a large step, replacing every coordinate
+/// with a fresh random number, or a small step, nudging each one by a
+/// normally distributed amount. Coordinates are only mutated when they are
+/// used, catching up on any small steps they missed.
+pub struct MltSampler {
+    rng: IndependentSampler,
+    samples: Vec<PrimarySample>,
+    index: usize,
+    iteration: usize,
+    large_step: bool,
+    last_large_step: usize,
+    sigma: f64,
+    large_step_probability: f64,
+}
+
+impl MltSampler {
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: IndependentSampler::new(seed),
+            samples: Vec::new(),
+            index: 0,
+            iteration: 0,
+            // The first point is picked at random.
+            large_step: true,
+            last_large_step: 0,
+            sigma,
+            large_step_probability,
+        }
+    }
+
+    /// Starts a new mutation of the point.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_1d() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    /// Keeps the mutated point.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Goes back to the point as it was before the mutation.
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// A uniform random number that isn't part of the point, for deciding
+    /// whether to accept a mutation.
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.next_1d()
+    }
+
+    /// Brings coordinate `index` up to date with the current iteration.
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let mut sample = self.samples[index];
+        // Anything not touched since the last accepted large step would
+        // have been replaced by it.
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.next_1d();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.next_1d();
+        } else {
+            // Small steps add up to one normal step with the combined
+            // variance.
+            let steps = (self.iteration - sample.modified) as f64;
+            let normal = standard_normal(self.rng.next_2d());
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.modified = self.iteration;
+        self.samples[index] = sample;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn start_sample(&mut self, _: usize, _: usize, _: usize) {}
+
+    fn next_1d(&mut self) -> f64 {
+        self.ensure_ready(self.index);
+        self.index += 1;
+        self.samples[self.index - 1].value
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+/// Box-Muller: a normally distributed number with zero mean and unit
+/// variance.
+fn standard_normal(u: (f64, f64)) -> f64 {
+    (-2.0 * (1.0 - u.0).ln()).sqrt() * (2.0 * PI * u.1).cos()
+}
+
+/// How the Markov chains are set up and mutated.
+#[derive(Debug, Clone, Copy)]
+pub struct MltSettings {
+    /// Independent paths traced to estimate the image's brightness and pick
+    /// where the chains start.
+    pub bootstrap: usize,
+    pub chains: usize,
+    /// The standard deviation of small steps.
+    pub sigma: f64,
+    pub large_step_probability: f64,
+    /// The render's seed, which the bootstrap paths and the choice of where
+    /// chains start are drawn from.
+    pub seed: u64,
+}
+
+struct Chain {
+    sampler: MltSampler,
+    light: Radiance,
+    image: (f64, f64),
+}
+
+struct Chains {
+    chains: Vec<Chain>,
+    /// The average luminance of the image, which the chains' samples are
+    /// scaled by.
+    brightness: f64,
+    next: usize,
+}
+
+/// Primary sample space Metropolis light transport.
+///
+/// Paths are traced by a `PathTracer` driven by an `MltSampler`, whose first
+/// two numbers pick the point on the image. Markov chains mutate those
+/// numbers, spending time on paths in proportion to how bright they are, so
+/// hard to find light that a path tracer only stumbles on now and then gets
+/// explored once found.
+///
+/// Each camera sample the renderer asks for advances one chain by one
+/// mutation and splats the result, so the sample count sets how many
+/// mutations are made. The returned radiance is always black.
+pub struct Mlt {
+    tracer: PathTracer,
+    settings: MltSettings,
+    chains: RefCell<Option<Chains>>,
+}
+
+impl Mlt {
+    pub fn new(tracer: PathTracer, settings: MltSettings) -> Self {
+        Self {
+            tracer,
+            settings,
+            chains: RefCell::new(None),
+        }
+    }
+
+    /// The light along the path the sampler's numbers describe, and where
+    /// on the image it lands.
+    fn contribution(&self, scene: &SceneView, sampler: &mut MltSampler) -> (Radiance, (f64, f64)) {
+        let image = sampler.next_2d();
+        let light = match scene.camera.get_ray(image.0, image.1, sampler) {
+            Some(ray) => self.tracer.trace(&ray, scene, sampler).0,
+            None => Radiance::default(),
+        };
+        (light, image)
+    }
+
+    /// The sampler for the `index`th bootstrap path.
+    fn sampler(&self, index: usize) -> MltSampler {
+        MltSampler::new(
+            hash(&[self.settings.seed, index as u64]),
+            self.settings.sigma,
+            self.settings.large_step_probability,
+        )
+    }
+
+    /// Traces independent paths to estimate the image's brightness, then
+    /// starts the chains from a selection of them picked in proportion to
+    /// their luminance, so they start out already distributed like the
+    /// image.
+    fn bootstrap(&self, scene: &SceneView) -> Chains {
+        let weights = (0..self.settings.bootstrap.max(1))
+            .map(|index| {
+                let mut sampler = self.sampler(index);
+                self.contribution(scene, &mut sampler).0.total().luminance()
+            })
+            .collect::<Vec<_>>();
+        let brightness = weights.iter().sum::<f64>() / weights.len() as f64;
+        let distribution = Distribution1D::new(weights);
+
+        let mut rng = IndependentSampler::new(hash(&[self.settings.seed]));
+        let chains = (0..self.settings.chains.max(1))
+            .map(|_| {
+                // Replaying the chosen path's sampler gives back the same
+                // path.
+                let index = distribution.sample_discrete(rng.next_1d());
+                let mut sampler = self.sampler(index);
+                let (light, image) = self.contribution(scene, &mut sampler);
+                Chain {
+                    sampler,
+                    light,
+                    image,
+                }
+            })
+            .collect();
+        Chains {
+            chains,
+            brightness,
+            next: 0,
+        }
+    }
+}
+
+impl Integrator for Mlt {
+    fn start_pass(&self, scene: &SceneView, _: usize) {
+        *self.chains.borrow_mut() = Some(self.bootstrap(scene));
+    }
+
+    fn splats_only(&self) -> bool {
+        true
+    }
+
+    fn radiance(
+        &self,
+        _: &Ray,
+        scene: &SceneView,
+        _: &mut dyn Sampler,
+        splats: &mut Splats,
+    ) -> Radiance {
+        if self.chains.borrow().is_none() {
+            self.start_pass(scene, 0);
+        }
+        let mut chains = self.chains.borrow_mut();
+        let chains = chains.as_mut().unwrap();
+        let brightness = chains.brightness;
+        let index = chains.next;
+        chains.next = (chains.next + 1) % chains.chains.len();
+        let chain = &mut chains.chains[index];
+
+        chain.sampler.start_iteration();
+        let (light, image) = self.contribution(scene, &mut chain.sampler);
+        let proposed = light.total().luminance();
+        let current = chain.light.total().luminance();
+        let accept = if current > 0.0 {
+            (proposed / current).min(1.0)
+        } else {
+            1.0
+        };
+        // Splat both the proposal and the current state, weighted by how
+        // likely each is to be where the chain ends up, rather than only
+        // whichever is picked.
+        if proposed > 0.0 {
+            splats.add(image, light * (accept * brightness / proposed));
+        }
+        if current > 0.0 {
+            splats.add(
+                chain.image,
+                chain.light * ((1.0 - accept) * brightness / current),
+            );
+        }
+        if chain.sampler.uniform() < accept {
+            chain.light = light;
+            chain.image = image;
+            chain.sampler.accept();
+        } else {
+            chain.sampler.reject();
+        }
+        Radiance::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        integrator::{
+            test::{test_scene, HEIGHT, WIDTH},
+            PathLength,
+        },
+        sampler::SamplerKind,
+    };
+
+    /// The luminance of each pixel, then of the whole image, with the light
+    /// returned for each camera sample splatted where the sample was taken.
+    fn image(
+        integrator: &dyn Integrator,
+        scene: &SceneView,
+        samples: usize,
+        seed: u64,
+    ) -> Vec<f64> {
+        let mut sampler = SamplerKind::Independent.build(samples, seed);
+        let mut splats = Splats::new(WIDTH, HEIGHT);
+        for index in 0..samples {
+            sampler.start_sample(0, 0, index);
+            let (s, t) = sampler.next_2d();
+            if let Some(ray) = scene.camera.get_ray(s, t, sampler.as_mut()) {
+                let radiance = integrator.radiance(&ray, scene, sampler.as_mut(), &mut splats);
+                splats.add((s, t), radiance);
+            }
+        }
+        let mut pixels = splats
+            .pixels(samples)
+            .iter()
+            .map(|pixel| pixel.total().luminance())
+            .collect::<Vec<_>>();
+        pixels.push(pixels.iter().sum::<f64>() / pixels.len() as f64);
+        pixels
+    }
+
+    /// The mean and standard error of each of `image`'s values over
+    /// independent batches, as samples within a Markov chain aren't
+    /// independent.
+    fn batches(
+        make: impl Fn(u64) -> Box<dyn Integrator>,
+        scene: &SceneView,
+        samples: usize,
+    ) -> Vec<(f64, f64)> {
+        let count = 8;
+        let images = (0..count)
+            .map(|batch| image(make(batch).as_ref(), scene, samples, 100 + batch))
+            .collect::<Vec<_>>();
+        (0..=WIDTH * HEIGHT)
+            .map(|pixel| {
+                let mean = images.iter().map(|image| image[pixel]).sum::<f64>() / count as f64;
+                let variance = images
+                    .iter()
+                    .map(|image| (image[pixel] - mean).powi(2))
+                    .sum::<f64>()
+                    / (count - 1) as f64;
+                (mean, (variance / count as f64).sqrt())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mlt_converges_to_the_path_traced_image() {
+        let fixture = test_scene();
+        let scene = fixture.view();
+
+        let length = PathLength {
+            max_depth: 20,
+            roulette_depth: u16::MAX,
+        };
+        let expected = batches(|_| Box::new(PathTracer::new(length)), &scene, 20000);
+        let mlt = batches(
+            |seed| {
+                let settings = MltSettings {
+                    bootstrap: 5000,
+                    chains: 1000,
+                    sigma: 0.01,
+                    large_step_probability: 0.3,
+                    seed,
+                };
+                Box::new(Mlt::new(PathTracer::new(length), settings))
+            },
+            &scene,
+            20000,
+        );
+        // The whole image's brightness comes from the bootstrap paths alone,
+        // so is pinned down much more tightly than each pixel.
+        for ((expected, expected_error), (mlt, mlt_error)) in expected.into_iter().zip(mlt) {
+            let error = (expected_error.powi(2) + mlt_error.powi(2)).sqrt();
+            assert!(
+                (expected - mlt).abs() < 4.0 * error,
+                "{} ± {} against {} ± {}",
+                expected,
+                expected_error,
+                mlt,
+                mlt_error
+            );
+        }
+    }
+
+    #[test]
+    fn rejecting_a_mutation_restores_the_point() {
+        let mut sampler = MltSampler::new(3, 0.01, 0.3);
+        let first = (0..8).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+        sampler.accept();
+        for _ in 0..10 {
+            sampler.start_iteration();
+            let mutated = (0..8).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+            assert_ne!(mutated, first);
+            assert!(mutated.iter().all(|value| (0.0..1.0).contains(value)));
+            sampler.reject();
+        }
+        // A mutation that never looks at a coordinate leaves it alone.
+        sampler.start_iteration();
+        sampler.next_1d();
+        sampler.reject();
+        sampler.start_iteration();
+        sampler.large_step = false;
+        sampler.sigma = 0.0;
+        let replayed = (0..8).map(|_| sampler.next_1d()).collect::<Vec<_>>();
+        assert_eq!(replayed, first);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    bvh::Aabb,
+    hit::{tangent_frame, Hit, HitRecord},
+    ray::Ray,
+    shape::{Shape, ShapeSample},
+    texture::Texture,
+    vec3::{dot, unit_vector, Vec3},
+};
+
+/// How far apart in `uv` bump map heights are compared to find their
+/// slope.
+const BUMP_DELTA: f64 = 0.0005;
+
+/// A way of bending a surface's shading normals to add detail that isn't
+/// in its geometry.
+pub enum NormalMap {
+    /// Normals in the surface's tangent frame, with the red, green and blue
+    /// channels giving how far they lean along the tangent, bitangent and
+    /// outward normal, mapped from `[-1, 1]` to `[0, 1]`.
+    TangentSpace(Box<dyn Texture>),
+    /// Heights, whose slope across the surface tilts the normal.
+    /// `strength` is the rise a height of one stands for, over a change of
+    /// one in `uv`.
+    Bump {
+        height: Box<dyn Texture>,
+        strength: f64,
+    },
+}
+
+impl NormalMap {
+    /// The bent outward normal at `record`, whose outward normal is
+    /// `normal`.
+    fn apply(&self, record: &HitRecord, normal: Vec3) -> Vec3 {
+        let bent = match self {
+            NormalMap::TangentSpace(texture) => {
+                let lean = 2.0 * texture.value(record) - Vec3::new(1.0, 1.0, 1.0);
+                lean.x * record.tangent + lean.y * record.bitangent + lean.z * normal
+            }
+            NormalMap::Bump { height, strength } => {
+                let at = |uv| {
+                    height.scalar(&HitRecord {
+                        uv,
+                        ..record.clone()
+                    })
+                };
+                let (u, v) = record.uv;
+                let here = at((u, v));
+                let slope_u = (at((u + BUMP_DELTA, v)) - here) / BUMP_DELTA;
+                let slope_v = (at((u, v + BUMP_DELTA)) - here) / BUMP_DELTA;
+                normal - *strength * (slope_u * record.tangent + slope_v * record.bitangent)
+            }
+        };
+        // Normals bent past the surface would shade it from behind.
+        if bent.near_zero() || dot(bent, normal) <= 0.0 {
+            normal
+        } else {
+            unit_vector(bent)
+        }
+    }
+}
+
+/// An object whose shading normals are bent by a normal map. Only the
+/// shading changes: rays hit the object where they would anyway, and
+/// lights on it are sampled by its true shape.
+pub struct Perturbed<T> {
+    object: T,
+    map: NormalMap,
+}
+
+impl<T> Perturbed<T> {
+    pub fn new(object: T, map: NormalMap) -> Self {
+        Self { object, map }
+    }
+
+    fn perturb<'material>(&self, record: HitRecord<'material>) -> HitRecord<'material> {
+        let outward = if record.front_face {
+            record.normal
+        } else {
+            -record.normal
+        };
+        let bent = self.map.apply(&record, outward);
+        let (tangent, bitangent) = tangent_frame(bent, record.tangent, record.bitangent);
+        HitRecord {
+            normal: if record.front_face { bent } else { -bent },
+            tangent,
+            bitangent,
+            ..record
+        }
+    }
+}
+
+impl<'material, T> Hit<'material> for Perturbed<T>
+where
+    T: Hit<'material>,
+{
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        let record = self.object.hit(ray, t_min, t_max)?;
+        Some(self.perturb(record))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        self.object.specular_bounds()
+    }
+
+    fn hit_counting(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        tests: &mut usize,
+    ) -> Option<HitRecord<'material>> {
+        let record = self.object.hit_counting(ray, t_min, t_max, tests)?;
+        Some(self.perturb(record))
+    }
+}
+
+impl<'material, T> Shape<'material> for Perturbed<T>
+where
+    T: Shape<'material>,
+{
+    fn area(&self) -> f64 {
+        self.object.area()
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        self.object.sample_area(u)
+    }
+
+    fn sample(&self, reference: Vec3, u: (f64, f64)) -> Option<ShapeSample> {
+        self.object.sample(reference, u)
+    }
+
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        self.object.pdf(reference, direction)
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        self.object.normal_cone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        hit::Hittables,
+        material::{Lambertian, Material},
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn flat_maps_keep_the_normal_and_slopes_tilt_it() {
+        let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let material = Rc::new(RefCell::new(material));
+        let sphere = || Sphere::new(Vec3::default(), 1.0, material.clone());
+        // Onto the side of the sphere facing +x, where u grows towards -z,
+        // from outside and from inside.
+        let rays = [
+            Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)),
+            Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+        ];
+
+        let flat = Perturbed::new(
+            sphere(),
+            NormalMap::TangentSpace(Box::new(Vec3::new(0.5, 0.5, 1.0))),
+        );
+        for ray in &rays {
+            let expected = sphere().hit(ray, 0.001, f64::INFINITY).unwrap();
+            let record = flat.hit(ray, 0.001, f64::INFINITY).unwrap();
+            assert!((record.normal - expected.normal).length() < 1e-9);
+            assert!(dot(record.tangent, record.normal).abs() < 1e-9);
+            assert!(dot(record.bitangent, record.normal).abs() < 1e-9);
+        }
+
+        // Heights rising with u lean the normal back along the tangent,
+        // staying on the side the ray hit.
+        struct Ramp;
+        impl Texture for Ramp {
+            fn value(&self, record: &HitRecord) -> Vec3 {
+                Vec3::new(record.uv.0, record.uv.0, record.uv.0)
+            }
+        }
+        let bumped = Perturbed::new(
+            sphere(),
+            NormalMap::Bump {
+                height: Box::new(Ramp),
+                strength: 1.0,
+            },
+        );
+        for ray in &rays {
+            let record = bumped.hit(ray, 0.001, f64::INFINITY).unwrap();
+            let outward = if record.front_face {
+                record.normal
+            } else {
+                -record.normal
+            };
+            let expected = unit_vector(Vec3::new(1.0, 0.0, 1.0));
+            assert!((outward - expected).length() < 1e-6, "{:?}", outward);
+            assert!(dot(record.normal, ray.direction) < 0.0);
+        }
+
+        // Counting intersection tests looks through to what's underneath,
+        // and still bends the normal.
+        let mut spheres = Hittables::new();
+        spheres.add(sphere());
+        spheres.add(Sphere::new(Vec3::new(0.0, 3.0, 0.0), 1.0, material.clone()));
+        let mut expected = 0;
+        spheres.hit_counting(&rays[0], 0.001, f64::INFINITY, &mut expected);
+        let bumped = Perturbed::new(
+            spheres,
+            NormalMap::Bump {
+                height: Box::new(Ramp),
+                strength: 1.0,
+            },
+        );
+        let mut tests = 0;
+        let record = bumped
+            .hit_counting(&rays[0], 0.001, f64::INFINITY, &mut tests)
+            .unwrap();
+        assert!(expected > 1);
+        assert_eq!(tests, expected);
+        assert_eq!(
+            record.normal,
+            bumped.hit(&rays[0], 0.001, f64::INFINITY).unwrap().normal
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{
+    camera::{PhysicalLens, Projection},
+    color::{Encoding, OutputSpace, WorkingSpace},
+    filter::FilterKind,
+    integrator::IntegratorKind,
+    light_sampler::LightSamplerKind,
+    ray::SkyKind,
+    sampler::SamplerKind,
+};
+use std::{error::Error, fmt, path::PathBuf, str::FromStr};
+
+pub struct Options {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    pub noise_threshold: f64,
+    pub sample_counts: Option<PathBuf>,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub filter: FilterKind,
+    pub filter_radius: Option<f64>,
+    pub aov_dir: Option<PathBuf>,
+    pub denoise: bool,
+    pub integrator: IntegratorKind,
+    pub ao_radius: f64,
+    pub photons: usize,
+    pub photon_radius: f64,
+    pub photon_passes: usize,
+    pub final_gather: usize,
+    pub mlt_bootstrap: usize,
+    pub mlt_chains: usize,
+    pub mlt_sigma: f64,
+    pub mlt_large_step: f64,
+    pub sky: SkyKind,
+    pub sun_elevation: f64,
+    pub sun_azimuth: f64,
+    pub turbidity: f64,
+    pub light_sampler: LightSamplerKind,
+    /// The surface detail maps only go on the big matte sphere of the random
+    /// scene, at (-4, 1, 0), whatever camera or lights a scene file gives.
+    pub normal_map: Option<PathBuf>,
+    pub bump_map: Option<PathBuf>,
+    pub bump_strength: f64,
+    pub displacement_map: Option<PathBuf>,
+    pub displacement_scale: f64,
+    pub spectral: bool,
+    pub showcase: bool,
+    pub roulette_depth: u16,
+    pub working_space: WorkingSpace,
+    pub output_space: OutputSpace,
+    pub encoding: Encoding,
+    pub projection: Projection,
+    pub fov: Option<f64>,
+    pub sensor: (f64, f64),
+    pub focal_length: Option<f64>,
+    pub f_number: f64,
+    pub shutter: f64,
+    pub iso: f64,
+    pub autofocus: bool,
+    pub aperture_blades: Option<usize>,
+    pub aperture_rotation: f64,
+    pub aperture_mask: Option<PathBuf>,
+    pub cats_eye: f64,
+    pub tilt: f64,
+    pub shift: (f64, f64),
+    pub scene: Option<PathBuf>,
+    pub frames: Option<usize>,
+    pub output: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            max_samples: 500,
+            noise_threshold: 0.005,
+            sample_counts: None,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            filter: FilterKind::Box,
+            filter_radius: None,
+            aov_dir: None,
+            denoise: false,
+            integrator: IntegratorKind::Path,
+            ao_radius: 1.0,
+            photons: 100_000,
+            photon_radius: 0.05,
+            photon_passes: 1,
+            final_gather: 0,
+            mlt_bootstrap: 100_000,
+            mlt_chains: 1000,
+            mlt_sigma: 0.01,
+            mlt_large_step: 0.3,
+            sky: SkyKind::Gradient,
+            sun_elevation: 30.0,
+            sun_azimuth: 60.0,
+            turbidity: 3.0,
+            light_sampler: LightSamplerKind::Bvh,
+            normal_map: None,
+            bump_map: None,
+            bump_strength: 0.01,
+            displacement_map: None,
+            displacement_scale: 0.1,
+            spectral: false,
+            showcase: false,
+            roulette_depth: 3,
+            working_space: WorkingSpace::LinearSrgb,
+            output_space: OutputSpace::Srgb,
+            encoding: Encoding::Gamma2,
+            projection: Projection::Perspective,
+            fov: None,
+            sensor: (36.0, 24.0),
+            focal_length: None,
+            f_number: 16.0,
+            shutter: 1.0 / 100.0,
+            iso: 100.0,
+            autofocus: false,
+            aperture_blades: None,
+            aperture_rotation: 0.0,
+            aperture_mask: None,
+            cats_eye: 0.0,
+            tilt: 0.0,
+            shift: (0.0, 0.0),
+            scene: None,
+            frames: None,
+            output: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--min-samples" => options.min_samples = value(&mut args, &arg)?,
+                "--max-samples" => options.max_samples = value(&mut args, &arg)?,
+                "--noise-threshold" => options.noise_threshold = value(&mut args, &arg)?,
+                "--sample-counts" => options.sample_counts = Some(value(&mut args, &arg)?),
+                "--sampler" => options.sampler = value(&mut args, &arg)?,
+                "--seed" => options.seed = value(&mut args, &arg)?,
+                "--filter" => options.filter = value(&mut args, &arg)?,
+                "--filter-radius" => options.filter_radius = Some(positive(&mut args, &arg)?),
+                "--aov-dir" => options.aov_dir = Some(value(&mut args, &arg)?),
+                "--denoise" => options.denoise = true,
+                "--integrator" => options.integrator = value(&mut args, &arg)?,
+                "--ao-radius" => options.ao_radius = value(&mut args, &arg)?,
+                "--photons" => options.photons = value(&mut args, &arg)?,
+                "--photon-radius" => options.photon_radius = positive(&mut args, &arg)?,
+                "--photon-passes" => options.photon_passes = value(&mut args, &arg)?,
+                "--final-gather" => options.final_gather = value(&mut args, &arg)?,
+                "--mlt-bootstrap" => options.mlt_bootstrap = value(&mut args, &arg)?,
+                "--mlt-chains" => options.mlt_chains = value(&mut args, &arg)?,
+                "--mlt-sigma" => options.mlt_sigma = value(&mut args, &arg)?,
+                "--mlt-large-step" => options.mlt_large_step = value(&mut args, &arg)?,
+                "--sky" => options.sky = value(&mut args, &arg)?,
+                "--sun-elevation" => options.sun_elevation = value(&mut args, &arg)?,
+                "--sun-azimuth" => options.sun_azimuth = value(&mut args, &arg)?,
+                "--turbidity" => options.turbidity = value(&mut args, &arg)?,
+                "--light-sampler" => options.light_sampler = value(&mut args, &arg)?,
+                "--normal-map" => options.normal_map = Some(value(&mut args, &arg)?),
+                "--bump-map" => options.bump_map = Some(value(&mut args, &arg)?),
+                "--bump-strength" => options.bump_strength = value(&mut args, &arg)?,
+                "--displacement-map" => options.displacement_map = Some(value(&mut args, &arg)?),
+                "--displacement-scale" => options.displacement_scale = value(&mut args, &arg)?,
+                "--spectral" => options.spectral = true,
+                "--showcase" => options.showcase = true,
+                "--roulette-depth" => options.roulette_depth = value(&mut args, &arg)?,
+                "--working-space" => options.working_space = value(&mut args, &arg)?,
+                "--output-space" => options.output_space = value(&mut args, &arg)?,
+                "--encoding" => options.encoding = value(&mut args, &arg)?,
+                "--projection" => options.projection = value(&mut args, &arg)?,
+                "--fov" => options.fov = Some(value(&mut args, &arg)?),
+                "--sensor" => options.sensor = sensor(&value::<String>(&mut args, &arg)?)?,
+                "--focal-length" => options.focal_length = Some(value(&mut args, &arg)?),
+                "--f-number" => options.f_number = value(&mut args, &arg)?,
+                "--shutter" => options.shutter = seconds(&value::<String>(&mut args, &arg)?)?,
+                "--iso" => options.iso = value(&mut args, &arg)?,
+                "--autofocus" => options.autofocus = true,
+                "--aperture-blades" => options.aperture_blades = Some(value(&mut args, &arg)?),
+                "--aperture-rotation" => options.aperture_rotation = value(&mut args, &arg)?,
+                "--aperture-mask" => options.aperture_mask = Some(value(&mut args, &arg)?),
+                "--cats-eye" => options.cats_eye = value(&mut args, &arg)?,
+                "--tilt" => options.tilt = value(&mut args, &arg)?,
+                "--shift" => options.shift = pair(&value::<String>(&mut args, &arg)?)?,
+                "--scene" => options.scene = Some(value(&mut args, &arg)?),
+                "--frames" => options.frames = Some(value(&mut args, &arg)?),
+                "--output" => options.output = Some(value(&mut args, &arg)?),
+                _ => return Err(format!("unknown argument {}", arg).into()),
+            }
+        }
+        // Spectral renders convert their spectra back to linear sRGB.
+        if options.spectral && options.working_space != WorkingSpace::LinearSrgb {
+            return Err("--spectral renders in linear sRGB and takes no --working-space".into());
+        }
+        // The daylight sky's sun is a delta light, which only shadow rays
+        // can reach.
+        if options.sky == SkyKind::Daylight
+            && options.integrator.is_physical()
+            && !options.integrator.samples_lights(options.spectral)
+        {
+            return Err(
+                "--sky daylight needs the path or mlt integrator without --spectral".into(),
+            );
+        }
+        Ok(options)
+    }
+
+    /// The physical lens, if a focal length was given.
+    pub fn lens(&self) -> Option<PhysicalLens> {
+        self.focal_length.map(|focal_length| PhysicalLens {
+            sensor_width: self.sensor.0,
+            sensor_height: self.sensor.1,
+            focal_length,
+            f_number: self.f_number,
+            shutter: self.shutter,
+            iso: self.iso,
+        })
+    }
+}
+
+/// Parses a sensor size in millimetres such as `36x24`.
+fn sensor(value: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| ParseOptionError::new("sensor size", value))?;
+    Ok((width.parse()?, height.parse()?))
+}
+
+/// Parses a pair of numbers separated by a comma, such as `0.1,-0.2`.
+fn pair(value: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let (x, y) = value
+        .split_once(',')
+        .ok_or_else(|| ParseOptionError::new("pair", value))?;
+    Ok((x.parse()?, y.parse()?))
+}
+
+/// Parses an exposure time given either in seconds or as a fraction of a
+/// second such as `1/125`.
+fn seconds(value: &str) -> Result<f64, Box<dyn Error>> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            Ok(numerator.parse::<f64>()? / denominator.parse::<f64>()?)
+        }
+        None => Ok(value.parse()?),
+    }
+}
+
+fn value<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    let value = args
+        .next()
+        .ok_or_else(|| format!("missing value for {}", flag))?;
+    Ok(value.parse()?)
+}
+
+/// Reads the value after `flag` as a distance or size, which has to be
+/// above zero.
+fn positive(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<f64, Box<dyn Error>> {
+    let value: f64 = value(args, flag)?;
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} must be above zero, not {}", flag, value).into())
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseOptionError {
+    option: &'static str,
+    value: String,
+}
+
+impl ParseOptionError {
+    pub fn new(option: &'static str, value: &str) -> Self {
+        Self {
+            option,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown {} \"{}\"", self.option, self.value)
+    }
+}
+
+impl Error for ParseOptionError {}
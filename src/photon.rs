@@ -0,0 +1,712 @@
+use crate::{
+    film::Splats,
+    hit::HitRecord,
+    integrator::{Integrator, SceneView},
+    ray::{Radiance, Ray},
+    sampler::{hash, sample_unit_disk, IndependentSampler, Sampler},
+    vec3::{dot, perpendicular, unit_vector, Vec3},
+};
+use std::{cell::RefCell, collections::BinaryHeap, f64::consts::PI};
+
+/// How many of the nearest photons the global map's estimates use.
+const GLOBAL_LOOKUP: usize = 50;
+
+/// How fast the caustic radius shrinks: pass `i` gathers from an area
+/// `(i + alpha) / (i + 1)` times the last one's. Between zero and one,
+/// smaller values shrink it faster, trading bias for noise.
+const RADIUS_ALPHA: f64 = 2.0 / 3.0;
+
+/// Light arriving at a diffuse surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Vec3,
+    /// The direction back the way the photon came.
+    pub incoming: Vec3,
+    pub power: Vec3,
+}
+
+/// A kd-tree over photons.
+///
+/// The tree is implicit in the order of the photons: each range of them is
+/// split by the photon at its middle, with the ones below it on the
+/// splitting axis before it and the ones above after.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    /// Calls `f` with every photon within `radius` of `point`.
+    pub fn within(&self, point: Vec3, radius: f64, mut f: impl FnMut(&Photon)) {
+        self.search(0, self.photons.len(), point, radius * radius, &mut f);
+    }
+
+    fn search(
+        &self,
+        start: usize,
+        end: usize,
+        point: Vec3,
+        radius_squared: f64,
+        f: &mut impl FnMut(&Photon),
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        if (photon.position - point).length_squared() <= radius_squared {
+            f(photon);
+        }
+        let axis = self.axes[middle];
+        let offset = point.component(axis) - photon.position.component(axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search(near.0, near.1, point, radius_squared, f);
+        if offset * offset <= radius_squared {
+            self.search(far.0, far.1, point, radius_squared, f);
+        }
+    }
+
+    /// The `count` photons nearest to `point`, and the squared distance to
+    /// the furthest of them.
+    pub fn nearest(&self, point: Vec3, count: usize) -> (Vec<&Photon>, f64) {
+        let mut heap = BinaryHeap::new();
+        self.search_nearest(0, self.photons.len(), point, count, &mut heap);
+        let radius_squared = heap
+            .peek()
+            .map_or(0.0, |nearby: &Nearby| nearby.distance_squared);
+        let photons = heap
+            .into_iter()
+            .map(|nearby| &self.photons[nearby.index])
+            .collect();
+        (photons, radius_squared)
+    }
+
+    fn search_nearest(
+        &self,
+        start: usize,
+        end: usize,
+        point: Vec3,
+        count: usize,
+        heap: &mut BinaryHeap<Nearby>,
+    ) {
+        if start >= end || count == 0 {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let distance_squared = (self.photons[middle].position - point).length_squared();
+        if heap.len() < count {
+            heap.push(Nearby {
+                distance_squared,
+                index: middle,
+            });
+        } else if distance_squared < heap.peek().map_or(f64::INFINITY, |n| n.distance_squared) {
+            heap.pop();
+            heap.push(Nearby {
+                distance_squared,
+                index: middle,
+            });
+        }
+        let axis = self.axes[middle];
+        let offset = point.component(axis) - self.photons[middle].position.component(axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, middle), (middle + 1, end))
+        } else {
+            ((middle + 1, end), (start, middle))
+        };
+        self.search_nearest(near.0, near.1, point, count, heap);
+        let furthest = heap.peek().map_or(f64::INFINITY, |n| n.distance_squared);
+        if heap.len() < count || offset * offset < furthest {
+            self.search_nearest(far.0, far.1, point, count, heap);
+        }
+    }
+}
+
+/// A photon found by a nearest neighbour search, ordered by distance so the
+/// heap keeps the furthest on top.
+struct Nearby {
+    distance_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Nearby {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance_squared == other.distance_squared
+    }
+}
+
+impl Eq for Nearby {}
+
+impl PartialOrd for Nearby {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nearby {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+/// Splits the photons at the median along the longest axis of their
+/// bounds, then does the same to each half.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), photon| {
+            let p = photon.position;
+            (
+                Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        a.position
+            .component(axis)
+            .total_cmp(&b.position.component(axis))
+    });
+    axes[middle] = axis;
+    let (lower, upper) = photons.split_at_mut(middle);
+    let (lower_axes, upper_axes) = axes.split_at_mut(middle);
+    build(lower, lower_axes);
+    build(&mut upper[1..], &mut upper_axes[1..]);
+}
+
+/// How photons are traced and gathered.
+#[derive(Debug, Clone, Copy)]
+pub struct PhotonSettings {
+    /// Photons shot into each of the global and caustic maps per pass.
+    pub photons: usize,
+    /// The radius caustic photons are gathered from in the first pass.
+    pub radius: f64,
+    /// How many passes to split the samples over, each with freshly traced
+    /// photons and a smaller radius, so caustics converge as passes are
+    /// added.
+    pub passes: usize,
+    /// How many rays to gather the global map through from the first
+    /// diffuse surface a camera ray hits. With none the global map is
+    /// looked up on the surface itself, which is faster but blotchier.
+    pub final_gather: usize,
+    /// The render's seed, which each pass's photons are traced from along
+    /// with the pass number.
+    pub seed: u64,
+}
+
+struct Maps {
+    /// Photons that reached a diffuse surface, used for indirect light.
+    global: PhotonMap,
+    /// Photons that reached a diffuse surface through specular surfaces
+    /// alone.
+    caustic: PhotonMap,
+    radius: f64,
+}
+
+/// A photon mapper with progressive passes.
+///
+/// Photons are shot from the sky into a global map, and aimed at specular
+/// objects to make a denser caustic map. Camera rays follow specular
+/// surfaces to the first diffuse one, which takes direct light from the
+/// sky, caustics from the caustic map, and indirect light from the global
+/// map. Area lights in the scene are left dark, as the sky is the only
+/// light photons come from.
+///
+/// Each pass traces new photons and gathers caustics from a smaller radius
+/// than the last, following the probabilistic formulation of stochastic
+/// progressive photon mapping by Knaus and Zwicker, so that averaging the
+/// passes converges to the right answer.
+pub struct PhotonMapper {
+    max_depth: u16,
+    settings: PhotonSettings,
+    maps: RefCell<Option<Maps>>,
+}
+
+impl PhotonMapper {
+    pub fn new(max_depth: u16, settings: PhotonSettings) -> Self {
+        Self {
+            max_depth,
+            settings,
+            maps: RefCell::new(None),
+        }
+    }
+
+    fn trace_photons(&self, scene: &SceneView, pass: usize) -> Maps {
+        let mut sampler = IndependentSampler::new(hash(&[self.settings.seed, pass as u64]));
+        let count = self.settings.photons;
+        let bounds = scene.world.bounding_box();
+        let (center, radius) = (bounds.centroid(), (bounds.max - bounds.centroid()).length());
+
+        let mut global = Vec::new();
+        for _ in 0..count {
+            let (direction, pdf) = self.sample_direction(scene, &mut sampler);
+            let origin = disk_point(center, radius, direction, sampler.next_2d());
+            let pdf_position = 1.0 / (PI * radius * radius);
+            let power = self.emitted(scene, direction) / (pdf * pdf_position * count as f64);
+            let ray = Ray::new(origin, direction);
+            self.trace_photon(scene, ray, power, false, &mut sampler, &mut global);
+        }
+
+        // Caustic photons are aimed at the bounding spheres of specular
+        // objects, each picked with equal probability.
+        let casters = scene
+            .world
+            .specular_bounds()
+            .iter()
+            .map(|bounds| (bounds.centroid(), (bounds.max - bounds.centroid()).length()))
+            .collect::<Vec<_>>();
+        let mut caustic = Vec::new();
+        let caustic_count = if casters.is_empty() { 0 } else { count };
+        for _ in 0..caustic_count {
+            let choice =
+                ((sampler.next_1d() * casters.len() as f64) as usize).min(casters.len() - 1);
+            let (caster, caster_radius) = casters[choice];
+            let (direction, pdf) = self.sample_direction(scene, &mut sampler);
+            let on_disk = disk_point(caster, caster_radius, direction, sampler.next_2d());
+            // Start from outside the scene, so nothing in front of the
+            // caster is skipped.
+            let closest = on_disk + direction * dot(center - on_disk, direction);
+            let origin = closest - radius * direction;
+            // The same line could have been aimed at any caster it passes
+            // through.
+            let pdf_position = casters
+                .iter()
+                .filter(|(c, r)| {
+                    let offset = *c - origin;
+                    (offset - direction * dot(offset, direction)).length_squared() < r * r
+                })
+                .map(|(_, r)| 1.0 / (PI * r * r))
+                .sum::<f64>()
+                / casters.len() as f64;
+            let power = self.emitted(scene, direction) / (pdf * pdf_position * count as f64);
+            let ray = Ray::new(origin, direction);
+            self.trace_photon(scene, ray, power, true, &mut sampler, &mut caustic);
+        }
+
+        let shrink = (1..=pass)
+            .map(|i| (i as f64 + RADIUS_ALPHA) / (i as f64 + 1.0))
+            .product::<f64>();
+        Maps {
+            global: PhotonMap::new(global),
+            caustic: PhotonMap::new(caustic),
+            radius: self.settings.radius * shrink.sqrt(),
+        }
+    }
+
+    /// A direction for light to leave the sky in, and its density.
+    fn sample_direction(&self, scene: &SceneView, sampler: &mut dyn Sampler) -> (Vec3, f64) {
+        let (towards_sky, pdf) = scene.sky.sample_direction(sampler.next_2d());
+        (-towards_sky, pdf)
+    }
+
+    /// The sky's radiance along `direction`.
+    fn emitted(&self, scene: &SceneView, direction: Vec3) -> Vec3 {
+        scene.sky.color(&Ray::new(Vec3::default(), -direction))
+    }
+
+    /// Follows a photon through the scene, storing it on diffuse surfaces.
+    /// Caustic photons are only stored if they got there through specular
+    /// surfaces alone, and stop there. Global photons that came straight
+    /// from the sky are only needed for final gathering, as direct light is
+    /// sampled separately otherwise.
+    fn trace_photon(
+        &self,
+        scene: &SceneView,
+        mut ray: Ray,
+        mut power: Vec3,
+        caustic: bool,
+        sampler: &mut dyn Sampler,
+        photons: &mut Vec<Photon>,
+    ) {
+        let mut diffuse_before = false;
+        for bounce in 0..self.max_depth {
+            let record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(record) => record,
+                None => return,
+            };
+            let specular = record.material.borrow().is_specular();
+            if !specular {
+                let store = if caustic {
+                    bounce > 0 && !diffuse_before
+                } else {
+                    self.settings.final_gather > 0 || diffuse_before
+                };
+                if store {
+                    photons.push(Photon {
+                        position: record.point,
+                        incoming: -unit_vector(ray.direction),
+                        power,
+                    });
+                }
+                if caustic {
+                    return;
+                }
+            }
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Vec3::default();
+            let scatters = record.material.borrow_mut().scatter(
+                &ray,
+                &record,
+                &mut attenuation,
+                &mut scattered,
+                sampler,
+            );
+            if !scatters {
+                return;
+            }
+            // Russian roulette keeps the photons' power about the same.
+            let survival = attenuation.x.max(attenuation.y).max(attenuation.z).min(1.0);
+            if sampler.next_1d() >= survival {
+                return;
+            }
+            power *= attenuation / survival;
+            diffuse_before |= !specular;
+            ray = scattered;
+        }
+    }
+
+    /// Caustic radiance leaving `record` towards `wo`, from the photons
+    /// within the pass's radius.
+    fn caustics(&self, maps: &Maps, record: &HitRecord, wo: Vec3) -> Vec3 {
+        let material = record.material.borrow();
+        let mut sum = Vec3::default();
+        maps.caustic.within(record.point, maps.radius, |photon| {
+            sum += material.bsdf(record, wo, photon.incoming) * photon.power;
+        });
+        sum / (PI * maps.radius * maps.radius)
+    }
+
+    /// Radiance leaving `record` towards `wo` from the global map's nearest
+    /// photons.
+    fn global(&self, maps: &Maps, record: &HitRecord, wo: Vec3) -> Vec3 {
+        let (photons, radius_squared) = maps.global.nearest(record.point, GLOBAL_LOOKUP);
+        if photons.is_empty() || radius_squared == 0.0 {
+            return Vec3::default();
+        }
+        let material = record.material.borrow();
+        let sum = photons.iter().fold(Vec3::default(), |sum, photon| {
+            sum + material.bsdf(record, wo, photon.incoming) * photon.power
+        });
+        sum / (PI * radius_squared)
+    }
+
+    /// Direct light from the sky leaving the diffuse surface at `record`,
+    /// from one scattered ray.
+    fn direct(
+        &self,
+        scene: &SceneView,
+        ray_in: &Ray,
+        record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let mut scattered = Ray::default();
+        let mut attenuation = Vec3::default();
+        let scatters = record.material.borrow_mut().scatter(
+            ray_in,
+            record,
+            &mut attenuation,
+            &mut scattered,
+            sampler,
+        );
+        match scatters && scene.world.hit(&scattered, 0.001, f64::INFINITY).is_none() {
+            true => attenuation * scene.sky.color(&scattered),
+            false => Vec3::default(),
+        }
+    }
+
+    /// Direct and indirect light reaching the diffuse surface at `record`
+    /// from `rays` scattered rays. Rays that escape bring direct light,
+    /// unless they passed through specular surfaces first, as the caustic
+    /// map covers that. Rays that land on a diffuse surface bring the
+    /// global map's estimate there.
+    fn gather<'material>(
+        &self,
+        maps: &Maps,
+        scene: &SceneView<'_, 'material>,
+        ray_in: &Ray,
+        record: &HitRecord<'material>,
+        rays: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec3, Vec3) {
+        let (mut direct, mut indirect) = (Vec3::default(), Vec3::default());
+        for _ in 0..rays {
+            let mut ray = *ray_in;
+            let mut record = record.clone();
+            let mut weight = Vec3::new(1.0, 1.0, 1.0);
+            for bounce in 0..self.max_depth {
+                let mut scattered = Ray::default();
+                let mut attenuation = Vec3::default();
+                let scatters = record.material.borrow_mut().scatter(
+                    &ray,
+                    &record,
+                    &mut attenuation,
+                    &mut scattered,
+                    sampler,
+                );
+                if !scatters {
+                    break;
+                }
+                weight *= attenuation;
+                ray = scattered;
+                record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                    Some(next) => next,
+                    None => {
+                        if bounce == 0 {
+                            direct += weight * scene.sky.color(&ray);
+                        }
+                        break;
+                    }
+                };
+                if !record.material.borrow().is_specular() {
+                    indirect += weight * self.global(maps, &record, -unit_vector(ray.direction));
+                    break;
+                }
+            }
+        }
+        (direct / rays as f64, indirect / rays as f64)
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn passes(&self) -> usize {
+        self.settings.passes.max(1)
+    }
+
+    fn start_pass(&self, scene: &SceneView, pass: usize) {
+        *self.maps.borrow_mut() = Some(self.trace_photons(scene, pass));
+    }
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &SceneView,
+        sampler: &mut dyn Sampler,
+        _: &mut Splats,
+    ) -> Radiance {
+        if self.maps.borrow().is_none() {
+            self.start_pass(scene, 0);
+        }
+        let maps = self.maps.borrow();
+        let maps = maps.as_ref().unwrap();
+
+        let mut radiance = Radiance::default();
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        for bounce in 0..self.max_depth {
+            let record = match scene.world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(record) => record,
+                None => {
+                    radiance.add(bounce, throughput * scene.sky.color(&ray));
+                    break;
+                }
+            };
+            if !record.material.borrow().is_specular() {
+                let wo = -unit_vector(ray.direction);
+                radiance.add(bounce + 2, throughput * self.caustics(maps, &record, wo));
+                let (direct, indirect) = match self.settings.final_gather {
+                    0 => (
+                        self.direct(scene, &ray, &record, sampler),
+                        self.global(maps, &record, wo),
+                    ),
+                    rays => self.gather(maps, scene, &ray, &record, rays, sampler),
+                };
+                radiance.add(bounce + 1, throughput * direct);
+                radiance.add(bounce + 2, throughput * indirect);
+                break;
+            }
+            let mut scattered = Ray::default();
+            let mut attenuation = Vec3::default();
+            let scatters = record.material.borrow_mut().scatter(
+                &ray,
+                &record,
+                &mut attenuation,
+                &mut scattered,
+                sampler,
+            );
+            if !scatters {
+                break;
+            }
+            throughput *= attenuation;
+            ray = scattered;
+        }
+        radiance
+    }
+}
+
+/// A point on the disk of the given radius facing `direction`, set back
+/// from `center` by the radius.
+fn disk_point(center: Vec3, radius: f64, direction: Vec3, u: (f64, f64)) -> Vec3 {
+    let (tangent, bitangent) = perpendicular(direction);
+    let disk = radius * sample_unit_disk(u);
+    center - radius * direction + disk.x * tangent + disk.y * bitangent
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        integrator::{
+            test::{estimate, test_scene, HEIGHT, WIDTH},
+            PathLength, PathTracer,
+        },
+        material::{Dialectric, Material},
+        sampler::SamplerKind,
+        sphere::Sphere,
+    };
+    use std::rc::Rc;
+
+    /// Checks that the passes of a photon mapper average out to the path
+    /// traced image, handing `check` the maps each pass traces.
+    fn assert_converges(scene: &SceneView, check: impl Fn(&Maps)) {
+        let path = PathTracer::new(PathLength {
+            max_depth: 20,
+            roulette_depth: u16::MAX,
+        });
+        let (expected, expected_error) = estimate(&path, scene, 1);
+        // Each pass traces its own maps, whose noise every sample in the
+        // pass shares, so the error comes from the spread between passes.
+        let passes = 8;
+        let photons = PhotonMapper::new(
+            20,
+            PhotonSettings {
+                photons: 20000,
+                radius: 0.05,
+                passes,
+                final_gather: 0,
+                seed: 2,
+            },
+        );
+        let batches = (0..passes)
+            .map(|pass| {
+                photons.start_pass(scene, pass);
+                check(photons.maps.borrow().as_ref().unwrap());
+                estimate(&photons, scene, 3 + pass as u64).0
+            })
+            .collect::<Vec<_>>();
+        let photon = batches.iter().sum::<f64>() / passes as f64;
+        let variance = batches
+            .iter()
+            .map(|batch| (batch - photon).powi(2))
+            .sum::<f64>()
+            / (passes - 1) as f64;
+        let photon_error = (variance / passes as f64).sqrt();
+        let error = (expected_error.powi(2) + photon_error.powi(2)).sqrt();
+        assert!(
+            (expected - photon).abs() < 4.0 * error,
+            "{} ± {} against {} ± {}",
+            expected,
+            expected_error,
+            photon,
+            photon_error
+        );
+    }
+
+    #[test]
+    fn photon_mapping_converges_to_the_path_traced_image() {
+        let fixture = test_scene();
+        assert_converges(&fixture.view(), |_| {});
+    }
+
+    #[test]
+    fn caustics_converge_to_the_path_traced_image() {
+        // A glass ball floating just above the ground, which focuses the
+        // sky beneath it. The camera looks in under the ball, so the whole
+        // view is the caustic and the ball refracting it.
+        let mut fixture = test_scene();
+        let material: Box<dyn Material> = Box::new(Dialectric::new(1.5));
+        fixture.world.add(Sphere::new(
+            Vec3::new(1.5, 0.8, 1.5),
+            0.5,
+            Rc::new(RefCell::new(material)),
+        ));
+        fixture.camera = PerspectiveCamera::new(
+            Vec3::new(1.5, 0.6, 4.0),
+            Vec3::new(1.5, 0.0, 1.5),
+            Vec3::new(0.0, 1.0, 0.0),
+            10.0,
+            WIDTH as f64 / HEIGHT as f64,
+            0.0,
+            2.6,
+        );
+        assert_converges(&fixture.view(), |maps| {
+            assert!(!maps.caustic.photons.is_empty())
+        });
+    }
+
+    #[test]
+    fn kd_tree_finds_the_same_photons_as_a_linear_search() {
+        let mut sampler = SamplerKind::Independent.build(1, 7);
+        let photons = (0..500)
+            .map(|_| {
+                let (x, y) = sampler.next_2d();
+                Photon {
+                    position: Vec3::new(x, y, sampler.next_1d()),
+                    incoming: Vec3::new(0.0, 1.0, 0.0),
+                    power: Vec3::new(1.0, 1.0, 1.0),
+                }
+            })
+            .collect::<Vec<_>>();
+        let map = PhotonMap::new(photons.clone());
+        for _ in 0..20 {
+            let (x, y) = sampler.next_2d();
+            let point = Vec3::new(x, y, 0.5);
+            let distances = |mut d: Vec<f64>| {
+                d.sort_by(f64::total_cmp);
+                d
+            };
+
+            let mut found = Vec::new();
+            map.within(point, 0.2, |photon| {
+                found.push((photon.position - point).length_squared())
+            });
+            let expected = photons
+                .iter()
+                .map(|photon| (photon.position - point).length_squared())
+                .filter(|d| *d <= 0.04)
+                .collect();
+            assert_eq!(distances(found), distances(expected));
+
+            let (nearest, radius_squared) = map.nearest(point, 10);
+            let all = distances(
+                photons
+                    .iter()
+                    .map(|photon| (photon.position - point).length_squared())
+                    .collect(),
+            );
+            let nearest = distances(
+                nearest
+                    .iter()
+                    .map(|photon| (photon.position - point).length_squared())
+                    .collect(),
+            );
+            assert_eq!(nearest, all[..10]);
+            assert_eq!(radius_squared, all[9]);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    color::WorkingSpace,
+    vec3::{dot, Vec3},
+};
+use std::f64::consts::PI;
+
+/// Sky luminance, in kcd/m², that comes out as a radiance of one. Picked so
+/// a sunlit scene comes out about as bright as it did under the old
+/// gradient sky, without having to set up an exposure.
+const LUMINANCE_UNIT: f64 = 20.0;
+
+/// Illuminance from the sun above the atmosphere, in klux.
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+/// The coefficients of the Perez sky distribution for the luminance and the
+/// two chromaticity coordinates, as linear functions of turbidity.
+const PEREZ: [[(f64, f64); 5]; 3] = [
+    [
+        (0.1787, -1.4630),
+        (-0.3554, 0.4275),
+        (-0.0227, 5.3251),
+        (0.1206, -2.5771),
+        (-0.0670, 0.3703),
+    ],
+    [
+        (-0.0193, -0.2592),
+        (-0.0665, 0.0008),
+        (-0.0004, 0.2125),
+        (-0.0641, -0.8989),
+        (-0.0033, 0.0452),
+    ],
+    [
+        (-0.0167, -0.2608),
+        (-0.0950, 0.0092),
+        (-0.0079, 0.2102),
+        (-0.0441, -1.6537),
+        (-0.0109, 0.0529),
+    ],
+];
+
+/// Preetham, Shirley and Smits' analytic model of a clear daylight sky.
+///
+/// The sky's luminance and chromaticity at the zenith come from fits to the
+/// sun's elevation and the turbidity, the haziness of the air from about 2
+/// on a clear day to 10 in thick haze. The Perez distribution then spreads
+/// them out over the sky, brightening around the sun and towards the
+/// horizon. Below the horizon the sky keeps its horizon color.
+pub struct Preetham {
+    sun: Vec3,
+    turbidity: f64,
+    /// Luminance and chromaticity at the zenith.
+    zenith: [f64; 3],
+    coefficients: [[f64; 5]; 3],
+    /// The Perez distribution at the zenith, which the rest of the sky is
+    /// relative to.
+    zenith_perez: [f64; 3],
+    space: WorkingSpace,
+}
+
+impl Preetham {
+    pub fn new(sun: Vec3, turbidity: f64, space: WorkingSpace) -> Self {
+        let theta_sun = sun.y.clamp(-1.0, 1.0).acos();
+        let coefficients = PEREZ.map(|row| row.map(|(slope, offset)| slope * turbidity + offset));
+        let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * theta_sun);
+        let luminance = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+        let chromaticity = |c: [[f64; 4]; 3]| {
+            let t = [turbidity.powi(2), turbidity, 1.0];
+            (0..3)
+                .map(|row| {
+                    t[row]
+                        * (c[row][0] * theta_sun.powi(3)
+                            + c[row][1] * theta_sun.powi(2)
+                            + c[row][2] * theta_sun
+                            + c[row][3])
+                })
+                .sum::<f64>()
+        };
+        let x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith_perez = coefficients.map(|c| perez(c, 1.0, theta_sun));
+        Self {
+            sun,
+            turbidity,
+            zenith: [luminance.max(0.0), x, y],
+            coefficients,
+            zenith_perez,
+            space,
+        }
+    }
+
+    /// The unit direction towards the sun.
+    pub fn sun(&self) -> Vec3 {
+        self.sun
+    }
+
+    /// The sky's radiance looking along the unit vector `direction`.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let cos_theta = direction.y.max(1e-3);
+        let gamma = dot(direction, self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez(self.coefficients[i], cos_theta, gamma) / self.zenith_perez[i]
+        });
+        let xyz = luminance / LUMINANCE_UNIT * Vec3::new(x / y, 1.0, (1.0 - x - y) / y);
+        self.space.convert_from_xyz(xyz)
+    }
+
+    /// The irradiance the sun gives a surface facing it, after passing
+    /// through the air. Light has further to go through the air when the
+    /// sun is low, and blue light is scattered away more than red, so the
+    /// sun dims and reddens towards the horizon.
+    pub fn sun_irradiance(&self) -> Vec3 {
+        if self.sun.y <= 0.0 {
+            return Vec3::default();
+        }
+        // Kasten and Young's relative air mass.
+        let zenith_degrees = self.sun.y.acos().to_degrees();
+        let air_mass = 1.0 / (self.sun.y + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+        // Rayleigh scattering by the air and Ångström's haze extinction,
+        // at wavelengths, in micrometres, standing in for red, green and
+        // blue.
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let depth = 0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3);
+            (-depth * air_mass).exp()
+        };
+        let rgb = Vec3::new(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.45),
+        );
+        self.space
+            .convert_from_srgb(SOLAR_ILLUMINANCE / LUMINANCE_UNIT * rgb)
+    }
+}
+
+/// The Perez distribution, for a direction at an angle with cosine
+/// `cos_theta` from the zenith and `gamma` from the sun.
+fn perez(c: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vec3::unit_vector;
+
+    #[test]
+    fn sky_is_brightest_around_the_sun_and_dims_at_sunset() {
+        let sun = unit_vector(Vec3::new(1.0, 1.0, 0.0));
+        let sky = Preetham::new(sun, 3.0, WorkingSpace::LinearSrgb);
+        let near_sun = sky.radiance(unit_vector(Vec3::new(1.0, 1.1, 0.0)));
+        let opposite = sky.radiance(unit_vector(Vec3::new(-1.0, 1.0, 0.0)));
+        assert!(near_sun.luminance() > 2.0 * opposite.luminance());
+        // Away from the sun, a clear sky is blue.
+        assert!(opposite.z > opposite.x, "{:?}", opposite);
+
+        let sunset = Preetham::new(
+            unit_vector(Vec3::new(1.0, 0.05, 0.0)),
+            3.0,
+            WorkingSpace::LinearSrgb,
+        );
+        let (noon, evening) = (sky.sun_irradiance(), sunset.sun_irradiance());
+        assert!(evening.luminance() < 0.5 * noon.luminance());
+        assert!(evening.x / evening.z > noon.x / noon.z);
+    }
+}
@@ -0,0 +1,584 @@
+use crate::{
+    hit::HitRecord,
+    material::Material,
+    ray::Ray,
+    sampler::{sample_unit_vector, Sampler},
+    texture::Texture,
+    vec3::{cross, dot, perpendicular, reflect, unit_vector, Vec3},
+};
+use std::f64::consts::PI;
+
+/// How rough the clearcoat is, as a GGX width.
+const CLEARCOAT_ROUGHNESS: f64 = 0.05;
+
+/// One material covering most surfaces, after Burley's Disney BRDF and
+/// OpenPBR. Every parameter is a texture, and all but the colors are in
+/// `[0, 1]`.
+///
+/// It blends a diffuse base with a sheen over it for cloth, a GGX specular
+/// layer that's tinted by the base color as it becomes metal, and rough
+/// glass that takes over from the diffuse base with `transmission`. A
+/// clearcoat goes over all of them. Each scattered ray comes from one of
+/// the lobes, picked in proportion to how much it reflects, and is weighted
+/// by the density of all of them together.
+///
+/// `alpha` is how much of the surface is there, for cutouts like leaves
+/// and fences. With an `alpha_cutoff` the surface is solid where alpha
+/// reaches the cutoff and missing elsewhere. Without one, partly there
+/// surfaces let light through in proportion.
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    /// The strength of the dielectric specular layer, with 0.5 giving the
+    /// 4% reflectance of most materials at normal incidence.
+    pub specular: Box<dyn Texture>,
+    pub clearcoat: Box<dyn Texture>,
+    pub sheen: Box<dyn Texture>,
+    pub transmission: Box<dyn Texture>,
+    /// The index of refraction of the glass `transmission` blends in.
+    pub ior: f64,
+    pub emission: Box<dyn Texture>,
+    pub alpha: Box<dyn Texture>,
+    pub alpha_cutoff: Option<f64>,
+}
+
+impl Principled {
+    /// A rough plastic of the given color, to change the other parameters
+    /// of.
+    pub fn new(base_color: Box<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: Box::new(0.0),
+            roughness: Box::new(0.5),
+            specular: Box::new(0.5),
+            clearcoat: Box::new(0.0),
+            sheen: Box::new(0.0),
+            transmission: Box::new(0.0),
+            ior: 1.5,
+            emission: Box::new(0.0),
+            alpha: Box::new(1.0),
+            alpha_cutoff: None,
+        }
+    }
+
+    fn lobes(&self, record: &HitRecord, wo: Vec3) -> Lobes {
+        let base_color = self.base_color.value(record);
+        let metallic = self.metallic.scalar(record).clamp(0.0, 1.0);
+        let roughness = self.roughness.scalar(record).clamp(0.0, 1.0);
+        let transmission = self.transmission.scalar(record).clamp(0.0, 1.0);
+        let dielectric = 1.0 - metallic;
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let specular = 0.08 * self.specular.scalar(record).max(0.0) * white;
+        let frame = Frame::new(record.normal);
+        let cos_o = dot(wo, record.normal).max(1e-6);
+        let lobes = Lobes {
+            frame,
+            base_color,
+            roughness,
+            alpha: roughness.powi(2).max(1e-3),
+            f0: specular * dielectric + base_color * metallic,
+            diffuse: dielectric * (1.0 - transmission),
+            sheen: self.sheen.scalar(record).max(0.0),
+            glass: dielectric * transmission,
+            clearcoat: self.clearcoat.scalar(record).clamp(0.0, 1.0),
+            eta: if record.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+            weights: [0.0; 4],
+        };
+        // Pick lobes by roughly how much light each sends back towards
+        // `wo`.
+        let specular = 1.0 - lobes.glass;
+        let weights = [
+            lobes.diffuse * (base_color.luminance() + lobes.sheen).max(1e-3),
+            specular * schlick(lobes.f0, cos_o).luminance(),
+            lobes.glass,
+            lobes.clearcoat * schlick(0.04 * white, cos_o).x,
+        ];
+        let total = weights.iter().sum::<f64>();
+        Lobes {
+            weights: match total > 0.0 {
+                true => weights.map(|weight| weight / total),
+                false => [1.0, 0.0, 0.0, 0.0],
+            },
+            ..lobes
+        }
+    }
+}
+
+/// The parameters of a `Principled` material at one hit.
+struct Lobes {
+    frame: Frame,
+    base_color: Vec3,
+    roughness: f64,
+    alpha: f64,
+    /// Specular reflectance at normal incidence.
+    f0: Vec3,
+    /// How much of each lobe there is.
+    diffuse: f64,
+    sheen: f64,
+    glass: f64,
+    clearcoat: f64,
+    /// The index of refraction on the far side of the surface over the
+    /// one on the near side.
+    eta: f64,
+    /// The chance of sampling each of the diffuse, specular, glass and
+    /// clearcoat lobes.
+    weights: [f64; 4],
+}
+
+impl Lobes {
+    /// Samples a direction `wi` from one of the lobes, for light leaving
+    /// towards `wo`.
+    fn sample(&self, wo: Vec3, u: f64, u2: (f64, f64)) -> Option<Vec3> {
+        let n = self.frame.normal;
+        let (mut lobe, mut u) = (0, u);
+        while lobe < 3 && u >= self.weights[lobe] {
+            u -= self.weights[lobe];
+            lobe += 1;
+        }
+        // What's left of `u` picks between reflection and refraction.
+        let u = (u / self.weights[lobe].max(1e-12)).clamp(0.0, 1.0);
+        let wi = match lobe {
+            0 => {
+                let direction = n + sample_unit_vector(u2);
+                if direction.near_zero() {
+                    n
+                } else {
+                    unit_vector(direction)
+                }
+            }
+            1 => reflect(-wo, self.frame.sample_ggx(wo, self.alpha, u2)),
+            2 => {
+                // Reflect or refract about the microfacet in proportion to
+                // its Fresnel reflectance.
+                let h = self.frame.sample_ggx(wo, self.alpha, u2);
+                let reflectance = fresnel_dielectric(dot(wo, h), self.eta);
+                if u < reflectance {
+                    reflect(-wo, h)
+                } else {
+                    refract(wo, h, self.eta)?
+                }
+            }
+            _ => reflect(-wo, self.frame.sample_ggx(wo, CLEARCOAT_ROUGHNESS, u2)),
+        };
+        Some(unit_vector(wi))
+    }
+
+    fn bsdf(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let n = self.frame.normal;
+        let (cos_o, cos_i) = (dot(wo, n), dot(wi, n));
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Vec3::default();
+        }
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut value = Vec3::default();
+        if cos_i > 0.0 {
+            let h = unit_vector(wo + wi);
+            let cos_d = dot(wi, h);
+            if self.diffuse > 0.0 {
+                // Burley's diffuse, which darkens at grazing angles on
+                // smooth surfaces and brightens on rough ones.
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d.powi(2);
+                let fd = |cos: f64| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+                let sheen = self.sheen * (1.0 - cos_d).powi(5);
+                value +=
+                    self.diffuse * (self.base_color / PI * fd(cos_i) * fd(cos_o) + sheen * white);
+            }
+            let specular = 1.0 - self.glass;
+            if specular > 0.0 {
+                let fresnel = schlick(self.f0, cos_d);
+                value += specular * fresnel * self.microfacet(wo, wi, h, self.alpha);
+            }
+        }
+        if self.glass > 0.0 {
+            value += self.glass * self.dielectric(wo, wi) * self.base_color;
+        }
+        if self.clearcoat > 0.0 {
+            // The coat takes the light it reflects away from the layers
+            // beneath.
+            let coat = self.clearcoat * schlick(0.04 * white, cos_o).x;
+            value *= 1.0 - coat;
+            if cos_i > 0.0 {
+                let h = unit_vector(wo + wi);
+                let fresnel = schlick(0.04 * white, dot(wi, h)).x;
+                value += self.clearcoat
+                    * fresnel
+                    * self.microfacet(wo, wi, h, CLEARCOAT_ROUGHNESS)
+                    * white;
+            }
+        }
+        value
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let n = self.frame.normal;
+        let (cos_o, cos_i) = (dot(wo, n), dot(wi, n));
+        if cos_o <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        if cos_i > 0.0 {
+            let h = unit_vector(wo + wi);
+            pdf += self.weights[0] * cos_i / PI;
+            pdf += self.weights[1] * self.frame.reflection_pdf(wo, h, self.alpha);
+            pdf += self.weights[3] * self.frame.reflection_pdf(wo, h, CLEARCOAT_ROUGHNESS);
+        }
+        if self.weights[2] > 0.0 {
+            pdf += self.weights[2] * self.dielectric_pdf(wo, wi);
+        }
+        pdf
+    }
+
+    /// The Torrance-Sparrow reflection off GGX microfacets, without the
+    /// Fresnel term.
+    fn microfacet(&self, wo: Vec3, wi: Vec3, h: Vec3, alpha: f64) -> f64 {
+        let n = self.frame.normal;
+        let (cos_o, cos_i) = (dot(wo, n), dot(wi, n));
+        ggx(dot(h, n), alpha) * smith(wo, wi, n, alpha) / (4.0 * cos_o * cos_i)
+    }
+
+    /// The generalized half vector between `wo` and `wi` for reflection or
+    /// refraction, facing the same way as the normal, with the relative
+    /// index of refraction used for it. `None` if the microfacet would face
+    /// away from either direction.
+    fn glass_half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f64)> {
+        let n = self.frame.normal;
+        let eta = match dot(wi, n) > 0.0 {
+            true => 1.0,
+            false => self.eta,
+        };
+        let mut h = wi * eta + wo;
+        if h.near_zero() {
+            return None;
+        }
+        h = unit_vector(h);
+        if dot(h, n) < 0.0 {
+            h = -h;
+        }
+        if dot(h, wi) * dot(wi, n) < 0.0 || dot(h, wo) * dot(wo, n) < 0.0 {
+            return None;
+        }
+        Some((h, eta))
+    }
+
+    /// Walter et al.'s rough dielectric, reflecting and refracting through
+    /// GGX microfacets.
+    fn dielectric(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let n = self.frame.normal;
+        let (h, eta) = match self.glass_half_vector(wo, wi) {
+            Some(half) => half,
+            None => return Vec3::default(),
+        };
+        let (cos_o, cos_i) = (dot(wo, n), dot(wi, n));
+        let reflectance = fresnel_dielectric(dot(wo, h), self.eta);
+        let (d, g) = (ggx(dot(h, n), self.alpha), smith(wo, wi, n, self.alpha));
+        let value = if eta == 1.0 {
+            d * g * reflectance / (4.0 * cos_o * cos_i).abs()
+        } else {
+            let denominator = (dot(wi, h) + dot(wo, h) / eta).powi(2);
+            // Light squeezes into a smaller cone going into the denser
+            // side, which raises its radiance.
+            d * g
+                * (1.0 - reflectance)
+                * (dot(wi, h) * dot(wo, h) / (cos_i * cos_o * denominator)).abs()
+                / eta.powi(2)
+        };
+        Vec3::new(value, value, value)
+    }
+
+    fn dielectric_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let (h, eta) = match self.glass_half_vector(wo, wi) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+        let reflectance = fresnel_dielectric(dot(wo, h), self.eta);
+        let visible = self.frame.visible_pdf(wo, h, self.alpha);
+        if eta == 1.0 {
+            visible / (4.0 * dot(wo, h).abs()) * reflectance
+        } else {
+            let denominator = (dot(wi, h) + dot(wo, h) / eta).powi(2);
+            visible * dot(wi, h).abs() / denominator * (1.0 - reflectance)
+        }
+    }
+}
+
+/// An orthonormal basis around a surface normal.
+struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    fn new(normal: Vec3) -> Self {
+        let (tangent, bitangent) = perpendicular(normal);
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            dot(v, self.tangent),
+            dot(v, self.bitangent),
+            dot(v, self.normal),
+        )
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+
+    /// Samples a GGX microfacet normal among those visible from `wo`,
+    /// following Heitz.
+    fn sample_ggx(&self, wo: Vec3, alpha: f64, u: (f64, f64)) -> Vec3 {
+        let wo = self.to_local(wo);
+        let v = unit_vector(Vec3::new(alpha * wo.x, alpha * wo.y, wo.z));
+        let length_squared = v.x.powi(2) + v.y.powi(2);
+        let t1 = match length_squared > 0.0 {
+            true => Vec3::new(-v.y, v.x, 0.0) / length_squared.sqrt(),
+            false => Vec3::new(1.0, 0.0, 0.0),
+        };
+        let t2 = cross(v, t1);
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z);
+        let p2 = (1.0 - s) * (1.0 - p1.powi(2)).max(0.0).sqrt() + s * r * phi.sin();
+        let h = p1 * t1 + p2 * t2 + (1.0 - p1.powi(2) - p2.powi(2)).max(0.0).sqrt() * v;
+        self.to_world(unit_vector(Vec3::new(
+            alpha * h.x,
+            alpha * h.y,
+            h.z.max(1e-6),
+        )))
+    }
+
+    /// The density of `sample_ggx` picking `h`.
+    fn visible_pdf(&self, wo: Vec3, h: Vec3, alpha: f64) -> f64 {
+        let cos_o = dot(wo, self.normal);
+        smith_g1(wo, self.normal, alpha) * dot(wo, h).max(0.0) * ggx(dot(h, self.normal), alpha)
+            / cos_o
+    }
+
+    /// The density of reflecting `wo` about a microfacet from `sample_ggx`
+    /// to get the direction halfway from `wo` to which is `h`.
+    fn reflection_pdf(&self, wo: Vec3, h: Vec3, alpha: f64) -> f64 {
+        let cos = dot(wo, h);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        self.visible_pdf(wo, h, alpha) / (4.0 * cos)
+    }
+}
+
+/// The GGX distribution of microfacet normals, for one with cosine
+/// `cos_h` to the surface normal.
+fn ggx(cos_h: f64, alpha: f64) -> f64 {
+    if cos_h <= 0.0 {
+        return 0.0;
+    }
+    let alpha2 = alpha.powi(2);
+    alpha2 / (PI * (cos_h.powi(2) * (alpha2 - 1.0) + 1.0).powi(2))
+}
+
+/// Smith's auxiliary function for GGX.
+fn lambda(w: Vec3, n: Vec3, alpha: f64) -> f64 {
+    let cos2 = dot(w, n).powi(2);
+    if cos2 == 0.0 {
+        return f64::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha.powi(2) * tan2).sqrt() - 1.0)
+}
+
+/// The fraction of microfacets visible from `w`.
+fn smith_g1(w: Vec3, n: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + lambda(w, n, alpha))
+}
+
+/// The fraction of microfacets visible from both `wo` and `wi`.
+fn smith(wo: Vec3, wi: Vec3, n: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + lambda(wo, n, alpha) + lambda(wi, n, alpha))
+}
+
+fn schlick(f0: Vec3, cosine: f64) -> Vec3 {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    f0 + (white - f0) * (1.0 - cosine.clamp(0.0, 1.0)).powi(5)
+}
+
+/// The Fresnel reflectance of unpolarized light arriving at an angle with
+/// cosine `cos_i`, going into a medium `eta` times as dense.
+fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(-1.0, 1.0);
+    let sin2_t = (1.0 - cos_i.powi(2)) / eta.powi(2);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let cos_i = cos_i.abs();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel.powi(2) + perpendicular.powi(2))
+}
+
+/// Refracts `wo` through a microfacet `h` into a medium `eta` times as
+/// dense, or `None` for total internal reflection.
+fn refract(wo: Vec3, h: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = dot(wo, h);
+    let sin2_t = (1.0 - cos_i.powi(2)).max(0.0) / eta.powi(2);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * h)
+}
+
+impl Material for Principled {
+    fn scatter(
+        &mut self,
+        ray_in: &Ray,
+        record: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+        sampler: &mut dyn Sampler,
+    ) -> bool {
+        let wo = -unit_vector(ray_in.direction);
+        let lobes = self.lobes(record, wo);
+        let wi = match lobes.sample(wo, sampler.next_1d(), sampler.next_2d()) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        let pdf = lobes.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+        *attenuation = lobes.bsdf(wo, wi) * dot(wi, record.normal).abs() / pdf;
+        *scattered = Ray::new(record.point, wi);
+        true
+    }
+
+    fn alpha(&self, record: &HitRecord) -> f64 {
+        let alpha = self.alpha.scalar(record).clamp(0.0, 1.0);
+        match self.alpha_cutoff {
+            Some(cutoff) if alpha >= cutoff => 1.0,
+            Some(_) => 0.0,
+            None => alpha,
+        }
+    }
+
+    fn albedo(&self, record: &HitRecord) -> Vec3 {
+        self.base_color.value(record)
+    }
+
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn bsdf(&self, record: &HitRecord, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (wo, wi) = (unit_vector(wo), unit_vector(wi));
+        self.lobes(record, wo).bsdf(wo, wi)
+    }
+
+    fn pdf(&self, record: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        let (wo, wi) = (unit_vector(wo), unit_vector(wi));
+        self.lobes(record, wo).pdf(wo, wi)
+    }
+
+    fn emitted(&self, record: &HitRecord) -> Vec3 {
+        if record.front_face {
+            self.emission.value(record)
+        } else {
+            Vec3::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{hit::Hit, sampler::SamplerKind, sphere::Sphere};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Checks that the mean weight of sampled rays matches integrating the
+    /// `bsdf` over the sphere, and that neither it nor the `pdf` adds up to
+    /// more than one. The integral takes directions stratified over the
+    /// sphere to keep the noise from peaky lobes down, and the tolerances
+    /// come from how much separate batches disagree, so that they hold
+    /// whatever numbers the sampler gives.
+    fn check(material: Principled) {
+        let material: Box<dyn Material> = Box::new(material);
+        let sphere = Sphere::new(Vec3::default(), 1.0, Rc::new(RefCell::new(material)));
+        let ray = Ray::new(Vec3::new(0.0, 3.0, 2.0), Vec3::new(0.0, -3.0, -2.0));
+        let record = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let wo = -unit_vector(ray.direction);
+        let mut sampler = SamplerKind::Independent.build(1, 1);
+        let (batches, side) = (20, 100);
+        let mut batch_means = Vec::new();
+        for _ in 0..batches {
+            let (mut sampled, mut integrated, mut density) = (0.0, 0.0, 0.0);
+            for i in 0..side * side {
+                let mut attenuation = Vec3::default();
+                let mut scattered = Ray::default();
+                let mut material = record.material.borrow_mut();
+                if material.scatter(
+                    &ray,
+                    &record,
+                    &mut attenuation,
+                    &mut scattered,
+                    sampler.as_mut(),
+                ) {
+                    sampled += attenuation.luminance();
+                }
+                let (column, row) = ((i % side) as f64, (i / side) as f64);
+                let (x, y) = sampler.next_2d();
+                let wi = sample_unit_vector(((column + x) / side as f64, (row + y) / side as f64));
+                let cosine = dot(wi, record.normal).abs();
+                integrated += material.bsdf(&record, wo, wi).luminance() * cosine * 4.0 * PI;
+                density += material.pdf(&record, wo, wi) * 4.0 * PI;
+            }
+            let count = (side * side) as f64;
+            batch_means.push([sampled / count, integrated / count, density / count]);
+        }
+        // The mean over the batches, and five standard errors of it.
+        let estimate = |value: fn(&[f64; 3]) -> f64| {
+            let values = batch_means.iter().map(value).collect::<Vec<_>>();
+            let count = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / count;
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1.0);
+            (mean, 5.0 * (variance / count).sqrt())
+        };
+        let (difference, tolerance) = estimate(|means| means[0] - means[1]);
+        assert!(
+            difference.abs() < tolerance + 1e-3,
+            "{} {}",
+            difference,
+            tolerance
+        );
+        let (integrated, tolerance) = estimate(|means| means[1]);
+        assert!(integrated - tolerance <= 1.02, "{}", integrated);
+        let (density, tolerance) = estimate(|means| means[2]);
+        assert!(density - tolerance <= 1.02, "{}", density);
+    }
+
+    #[test]
+    fn sampling_matches_the_bsdf() {
+        check(Principled::new(Box::new(Vec3::new(0.8, 0.4, 0.2))));
+        check(Principled {
+            metallic: Box::new(1.0),
+            roughness: Box::new(0.3),
+            clearcoat: Box::new(1.0),
+            ..Principled::new(Box::new(Vec3::new(0.9, 0.7, 0.3)))
+        });
+        check(Principled {
+            roughness: Box::new(0.4),
+            transmission: Box::new(1.0),
+            ..Principled::new(Box::new(Vec3::new(1.0, 1.0, 1.0)))
+        });
+    }
+}
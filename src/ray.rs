@@ -1,9 +1,13 @@
 use crate::{
-    hit::{Hit, Hittables},
+    color::WorkingSpace,
+    options::ParseOptionError,
+    preetham::Preetham,
+    sampler::sample_unit_vector,
     vec3::{unit_vector, Vec3},
 };
+use std::{f64::consts::PI, str::FromStr};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
@@ -19,30 +23,132 @@ impl Ray {
     }
 }
 
-pub fn ray_color<'material, T>(ray: &Ray, world: &Hittables<T>, depth: u16) -> Vec3
-where
-    T: Hit<'material>,
-{
-    if depth == 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
-    }
-    if let Some(record) = world.hit(ray, 0.001, f64::INFINITY) {
-        let mut scattered = Ray::default();
-        let mut attenuation = Vec3::default();
-        let maybe_attenuation = {
-            let material_ref = &mut *record.material.borrow_mut();
-            material_ref
-                .scatter(ray, &record, &mut attenuation, &mut scattered)
-                .then(|| attenuation)
-        };
-        if let Some(attenuation) = maybe_attenuation {
-            attenuation * ray_color(&scattered, world, depth - 1)
+/// Radiance arriving along a camera ray.
+///
+/// `direct` is light that reached the camera straight from the sky or after
+/// a single scatter, `indirect` is everything that scattered more than once.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Radiance {
+    pub direct: Vec3,
+    pub indirect: Vec3,
+}
+
+impl Radiance {
+    pub fn total(&self) -> Vec3 {
+        self.direct + self.indirect
+    }
+
+    /// Adds light reaching the camera after `bounces` scattering events.
+    pub fn add(&mut self, bounces: u16, light: Vec3) {
+        if bounces <= 1 {
+            self.direct += light;
         } else {
-            Vec3::default()
+            self.indirect += light;
+        }
+    }
+}
+
+impl std::ops::AddAssign for Radiance {
+    fn add_assign(&mut self, other: Radiance) {
+        self.direct += other.direct;
+        self.indirect += other.indirect;
+    }
+}
+
+impl std::ops::Mul<f64> for Radiance {
+    type Output = Radiance;
+
+    fn mul(self, scale: f64) -> Self::Output {
+        Radiance {
+            direct: self.direct * scale,
+            indirect: self.indirect * scale,
+        }
+    }
+}
+
+/// The skies that can be picked at runtime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkyKind {
+    /// White at the horizon to blue overhead.
+    Gradient,
+    /// Preetham's clear sky, with a sun.
+    Daylight,
+}
+
+impl FromStr for SkyKind {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gradient" => Ok(SkyKind::Gradient),
+            "daylight" => Ok(SkyKind::Daylight),
+            _ => Err(ParseOptionError::new("sky", s)),
         }
-    } else {
+    }
+}
+
+/// The sky lighting the scene from every direction rays escape in.
+pub enum Sky {
+    Gradient {
+        horizon: Vec3,
+        zenith: Vec3,
+    },
+    /// A daylight sky. The sun itself isn't part of the sky, but a light of
+    /// its own.
+    Daylight(Preetham),
+}
+
+impl Sky {
+    /// The gradient sky.
+    pub fn new(space: WorkingSpace) -> Self {
+        Sky::Gradient {
+            horizon: space.convert_from_srgb(Vec3::new(1.0, 1.0, 1.0)),
+            zenith: space.convert_from_srgb(Vec3::new(0.5, 0.7, 1.0)),
+        }
+    }
+
+    /// A daylight sky with the sun `elevation` degrees above the horizon,
+    /// and `azimuth` degrees round from +x towards +z.
+    pub fn daylight(elevation: f64, azimuth: f64, turbidity: f64, space: WorkingSpace) -> Self {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun = Vec3::new(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            elevation.cos() * azimuth.sin(),
+        );
+        Sky::Daylight(Preetham::new(sun, turbidity, space))
+    }
+
+    pub fn color(&self, ray: &Ray) -> Vec3 {
         let unit_direction = unit_vector(ray.direction);
-        let t = 0.5 * (unit_direction.y + 1.0);
-        (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+        match self {
+            Sky::Gradient { horizon, zenith } => {
+                let t = 0.5 * (unit_direction.y + 1.0);
+                (1.0 - t) * *horizon + t * *zenith
+            }
+            Sky::Daylight(model) => model.radiance(unit_direction),
+        }
+    }
+
+    /// The direction towards the sun and the irradiance it gives, for skies
+    /// that have one.
+    pub fn sun(&self) -> Option<(Vec3, Vec3)> {
+        match self {
+            Sky::Gradient { .. } => None,
+            Sky::Daylight(model) => Some((model.sun(), model.sun_irradiance())),
+        }
+    }
+
+    /// Picks a direction to look at the sky in, for paths that start from
+    /// it or connect to it. Returns the direction and its density per unit
+    /// solid angle.
+    pub fn sample_direction(&self, u: (f64, f64)) -> (Vec3, f64) {
+        let direction = sample_unit_vector(u);
+        (direction, self.pdf(direction))
+    }
+
+    /// The density with which `sample_direction` picks `direction`.
+    pub fn pdf(&self, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
     }
 }
@@ -0,0 +1,113 @@
+use crate::{
+    bvh::Aabb,
+    hit::{face_normal, tangent_frame, Hit, HitRecord},
+    material::Material,
+    ray::Ray,
+    shape::{Shape, ShapeSample},
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// A parallelogram spanned by two edges from a corner. Its front is the side
+/// `cross(u, v)` points to.
+pub struct Rect<'material> {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    /// Maps a point on the plane, relative to the corner, to its
+    /// coordinates along the edges.
+    w: Vec3,
+    area: f64,
+    material: Rc<RefCell<Box<dyn Material + 'material>>>,
+}
+
+impl<'material> Rect<'material> {
+    pub fn new(
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+        material: Rc<RefCell<Box<dyn Material + 'material>>>,
+    ) -> Self {
+        let n = cross(u, v);
+        Self {
+            corner,
+            u,
+            v,
+            normal: unit_vector(n),
+            w: n / dot(n, n),
+            area: n.length(),
+            material,
+        }
+    }
+}
+
+impl<'material> Hit<'material> for Rect<'material> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        let denominator = dot(self.normal, ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = dot(self.normal, self.corner - ray.origin) / denominator;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let point = ray.at(t);
+        let planar = point - self.corner;
+        let alpha = dot(self.w, cross(planar, self.v));
+        let beta = dot(self.w, cross(self.u, planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        let (front_face, normal) = face_normal(ray, self.normal);
+        let (tangent, bitangent) = tangent_frame(self.normal, self.u, self.v);
+        Some(HitRecord {
+            point,
+            normal,
+            t,
+            front_face,
+            uv: (alpha, beta),
+            barycentric: None,
+            material: self.material.clone(),
+            light: None,
+            tangent,
+            bitangent,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let corners = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        Aabb::around(&corners, 1e-4)
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        if self.material.borrow().is_specular() {
+            vec![self.bounding_box()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl<'material> Shape<'material> for Rect<'material> {
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        ShapeSample {
+            point: self.corner + u.0 * self.u + u.1 * self.v,
+            normal: self.normal,
+            pdf: 1.0 / self.area,
+        }
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        (self.normal, 1.0)
+    }
+}
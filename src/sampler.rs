@@ -0,0 +1,523 @@
+use crate::{options::ParseOptionError, vec3::Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{f64::consts::PI, str::FromStr};
+
+/// Source of the random numbers used while tracing a single sample.
+///
+/// Every consumer (pixel jitter, the lens, material scattering and light
+/// sampling) asks for the next one or two dimensions in turn. Samplers that
+/// know which pixel and sample they are on can then hand out well
+/// distributed values for each dimension instead of independent ones.
+pub trait Sampler {
+    /// Moves on to sample `index` of pixel `(x, y)`, starting again from the
+    /// first dimension.
+    fn start_sample(&mut self, x: usize, y: usize, index: usize);
+
+    fn next_1d(&mut self) -> f64;
+
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn build(&self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = ParseOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(ParseOptionError::new("sampler", s)),
+        }
+    }
+}
+
+/// Uniform random numbers with no relationship between samples.
+pub struct IndependentSampler {
+    rng: StdRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _: usize, _: usize, _: usize) {}
+
+    fn next_1d(&mut self) -> f64 {
+        self.rng.gen_range(0.0..1.0)
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen_range(0.0..1.0), self.rng.gen_range(0.0..1.0))
+    }
+}
+
+/// Jittered stratified sampling.
+///
+/// Each dimension is split into one stratum per sample, and each pair of
+/// dimensions into a square grid, with every sample of a pixel landing in a
+/// different stratum. The order the strata are visited in is shuffled per
+/// pixel and per dimension so dimensions don't correlate with each other.
+pub struct StratifiedSampler {
+    samples_per_pixel: usize,
+    grid_size: usize,
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: u64,
+    rng: StdRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let grid_size = (samples_per_pixel as f64).sqrt().ceil() as usize;
+        Self {
+            samples_per_pixel,
+            grid_size,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn stratum(&mut self, count: usize) -> usize {
+        let permutation = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        permutation_element(
+            (self.index % count) as u32,
+            count as u32,
+            permutation as u32,
+        ) as usize
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = (x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let stratum = self.stratum(self.samples_per_pixel);
+        (stratum as f64 + self.rng.gen_range(0.0..1.0)) / self.samples_per_pixel as f64
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let n = self.grid_size;
+        let stratum = self.stratum(n * n);
+        let x = (stratum % n) as f64 + self.rng.gen_range(0.0..1.0);
+        let y = (stratum / n) as f64 + self.rng.gen_range(0.0..1.0);
+        (x / n as f64, y / n as f64)
+    }
+}
+
+/// The Halton sequence, using the next prime as the base for each dimension.
+///
+/// Every pixel walks the same sequence, shifted by a per-pixel random
+/// (Cranley-Patterson) rotation so neighbouring pixels don't share a pattern.
+/// Dimensions past the prime table fall back to hashed random values.
+pub struct HaltonSampler {
+    primes: Vec<u64>,
+    seed: u64,
+    pixel: (usize, usize),
+    index: u64,
+    dimension: usize,
+}
+
+const HALTON_DIMENSIONS: usize = 128;
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().all(|prime| candidate % prime != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        Self {
+            primes,
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        let rotation = to_unit(hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+            self.seed,
+        ]));
+        let value = match self.primes.get(self.dimension) {
+            Some(&base) => radical_inverse(self.index, base),
+            None => to_unit(hash(&[
+                self.index,
+                self.dimension as u64,
+                rotation.to_bits(),
+            ])),
+        };
+        self.dimension += 1;
+        (value + rotation).fract()
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = (x, y);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        self.next()
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        (self.next(), self.next())
+    }
+}
+
+fn radical_inverse(mut index: u64, base: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_n = 1.0;
+    let mut reversed = 0;
+    while index > 0 {
+        let next = index / base;
+        reversed = reversed * base + (index - next * base);
+        inverse_base_n *= inverse_base;
+        index = next;
+    }
+    (reversed as f64 * inverse_base_n).min(ONE_MINUS_EPSILON)
+}
+
+/// Owen-scrambled Sobol points, following Burley's "Practical Hash-based
+/// Owen Scrambling".
+///
+/// Each request gets the first one or two Sobol dimensions, with the sample
+/// index shuffled and the result scrambled by a hash of the pixel and the
+/// request number. This pads out as many dimensions as a path needs while
+/// keeping every pair well stratified.
+pub struct SobolSampler {
+    directions: [[u32; 32]; 2],
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            directions: [sobol_directions(0), sobol_directions(1)],
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        let seed = hash(&[
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension,
+            self.seed,
+        ]);
+        self.dimension += 1;
+        seed as u32
+    }
+
+    fn sample(&self, index: u32, dimension: usize, seed: u32) -> f64 {
+        let mut result = 0;
+        let mut index = index;
+        let mut bit = 0;
+        while index != 0 {
+            if index & 1 == 1 {
+                result ^= self.directions[dimension][bit];
+            }
+            index >>= 1;
+            bit += 1;
+        }
+        let scrambled = nested_uniform_scramble(result, hash_u32(seed, dimension as u32));
+        (scrambled as f64 / 4_294_967_296.0).min(ONE_MINUS_EPSILON)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = (x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.index, seed);
+        self.sample(index, 0, seed)
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let seed = self.next_seed();
+        let index = nested_uniform_scramble(self.index, seed);
+        (self.sample(index, 0, seed), self.sample(index, 1, seed))
+    }
+}
+
+/// Direction numbers for the first two Sobol dimensions.
+///
+/// The first is the van der Corput sequence, the second uses the primitive
+/// polynomial `x + 1` with initial direction number 1 (Joe and Kuo).
+fn sobol_directions(dimension: usize) -> [u32; 32] {
+    let mut directions = [0u32; 32];
+    directions[0] = 1 << 31;
+    for k in 1..32 {
+        directions[k] = match dimension {
+            0 => 1 << (31 - k),
+            _ => directions[k - 1] ^ (directions[k - 1] >> 1),
+        };
+    }
+    directions
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Kensler's hashed permutation: the `i`th element of a random permutation
+/// of `0..length` chosen by `seed`, without building the permutation.
+fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let p = seed;
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % length
+}
+
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Mixes `values` into one well spread number, for seeds and permutations
+/// that have to come out the same each time.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |state, value| {
+        let mut z = state ^ value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// A number in `[0, 1)` that comes out the same whenever it's made from
+/// the same values, for random choices that have to agree each time
+/// they're made.
+pub fn hash_unit(values: &[u64]) -> f64 {
+    to_unit(hash(values))
+}
+
+fn hash_u32(a: u32, b: u32) -> u32 {
+    hash(&[a as u64, b as u64]) as u32
+}
+
+fn to_unit(value: u64) -> f64 {
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Maps a square sample onto the unit disk, keeping strata compact
+/// (Shirley and Chiu's concentric mapping).
+pub fn sample_unit_disk(u: (f64, f64)) -> Vec3 {
+    let x = 2.0 * u.0 - 1.0;
+    let y = 2.0 * u.1 - 1.0;
+    if x == 0.0 && y == 0.0 {
+        return Vec3::default();
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Maps a square sample uniformly onto the surface of the unit sphere.
+pub fn sample_unit_vector(u: (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps a square sample and a radius sample uniformly into the unit ball.
+pub fn sample_in_unit_sphere(u: (f64, f64), radius: f64) -> Vec3 {
+    sample_unit_vector(u) * radius.cbrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        radical_inverse, sobol_directions, HaltonSampler, Sampler, SobolSampler, StratifiedSampler,
+    };
+
+    #[test]
+    fn sobol_directions_should_match_reference_values() {
+        let directions = sobol_directions(1);
+        assert_eq!(
+            directions[..8],
+            [
+                0x8000_0000,
+                0xc000_0000,
+                0xa000_0000,
+                0xf000_0000,
+                0x8800_0000,
+                0xcc00_0000,
+                0xaa00_0000,
+                0xff00_0000
+            ]
+        );
+        assert_eq!(directions[31], 0xffff_ffff);
+    }
+
+    #[test]
+    fn stratified_samples_should_cover_every_stratum() {
+        let samples = 16;
+        let mut sampler = StratifiedSampler::new(samples, 7);
+        let mut hits_1d = vec![0; samples];
+        let mut hits_2d = vec![0; samples];
+        for index in 0..samples {
+            sampler.start_sample(3, 5, index);
+            let u = sampler.next_1d();
+            hits_1d[(u * samples as f64) as usize] += 1;
+            let (x, y) = sampler.next_2d();
+            hits_2d[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert!(hits_1d.iter().all(|hits| *hits == 1), "{:?}", hits_1d);
+        assert!(hits_2d.iter().all(|hits| *hits == 1), "{:?}", hits_2d);
+    }
+
+    #[test]
+    fn sobol_pairs_should_be_stratified() {
+        let samples = 16;
+        let mut sampler = SobolSampler::new(11);
+        for dimension in 0..4 {
+            let mut hits = vec![0; samples];
+            for index in 0..samples {
+                sampler.start_sample(1, 2, index);
+                for _ in 0..dimension {
+                    sampler.next_2d();
+                }
+                let (x, y) = sampler.next_2d();
+                hits[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+            }
+            assert!(hits.iter().all(|hits| *hits == 1), "{:?}", hits);
+        }
+    }
+
+    #[test]
+    fn halton_samples_should_follow_the_radical_inverse() {
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!((radical_inverse(5, 3) - 7.0 / 9.0).abs() < 1e-12);
+
+        // Each pixel's rotation shifts the whole sequence, so every sample
+        // sits the radical inverse of its index past the first.
+        let mut sampler = HaltonSampler::new(5);
+        let mut points = Vec::new();
+        for index in 0..32 {
+            sampler.start_sample(4, 9, index);
+            let point = sampler.next_2d();
+            assert!((0.0..1.0).contains(&point.0) && (0.0..1.0).contains(&point.1));
+            points.push(point);
+        }
+        let (x0, y0) = points[0];
+        for (index, (x, y)) in points.iter().enumerate() {
+            let dx = (x - x0).rem_euclid(1.0);
+            let dy = (y - y0).rem_euclid(1.0);
+            assert!(
+                (dx - radical_inverse(index as u64, 2)).abs() < 1e-9,
+                "{}",
+                index
+            );
+            assert!(
+                (dy - radical_inverse(index as u64, 3)).abs() < 1e-9,
+                "{}",
+                index
+            );
+        }
+
+        // Neighbouring pixels are rotated differently.
+        sampler.start_sample(5, 9, 0);
+        assert_ne!(sampler.next_2d(), (x0, y0));
+        sampler.start_sample(4, 10, 0);
+        assert_ne!(sampler.next_2d(), (x0, y0));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{
+    animation::{CameraPath, Interpolation, Keyframe},
+    vec3::Vec3,
+};
+use std::{error::Error, fs, path::Path};
+
+/// Camera settings read from a scene file.
+///
+/// The file is a list of lines, each a directive followed by its values, with
+/// `#` starting a comment:
+///
+/// ```text
+/// up 0 1 0
+/// interpolation spline
+/// frames 48
+/// keyframe time 0 look_from 13 2 3 look_at 0 0 0 fov 20 focus 10
+/// keyframe time 2 look_from -3 2 13
+/// light sphere 0 6 0  1  10 10 10
+/// light rect -1 4 -1  2 0 0  0 0 2  8 8 8
+/// ```
+///
+/// Anything a keyframe leaves out is carried over from the keyframe before
+/// it, or from the defaults for the first one.
+///
+/// Each `light` is given by its kind and parameters followed by its color
+/// in sRGB. Glowing shapes are added to the world, with the color giving
+/// their radiance:
+///
+/// - `sphere` center radius
+/// - `rect` corner edge edge, lit on the side `cross(edge, edge)` faces
+/// - `triangle` three corners, lit on the side they go anticlockwise round
+/// - `disk` center normal radius
+///
+/// The rest are delta lights, which only light things through shadow rays
+/// and can't be seen:
+///
+/// - `point` position, with the color giving its intensity
+/// - `spot` position direction cone falloff, with the angles in degrees
+///   from the middle of the cone to its edge and to where it starts to fade
+/// - `sun` direction towards it, with the color giving its irradiance
+pub struct Scene {
+    pub vertical_up: Vec3,
+    pub camera_path: CameraPath,
+    pub frames: usize,
+    pub lights: Vec<SceneLight>,
+}
+
+/// A light from a `light` directive. Colors are in sRGB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneLight {
+    Area {
+        shape: EmitterShape,
+        radiance: Vec3,
+    },
+    Point {
+        position: Vec3,
+        intensity: Vec3,
+    },
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        cone: f64,
+        falloff_start: f64,
+        intensity: Vec3,
+    },
+    Sun {
+        direction: Vec3,
+        irradiance: Vec3,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitterShape {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+    },
+    Rect {
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+    },
+    Triangle {
+        vertices: [Vec3; 3],
+    },
+    Disk {
+        center: Vec3,
+        normal: Vec3,
+        radius: f64,
+    },
+}
+
+impl Scene {
+    /// A single frame with the camera held at `defaults`.
+    pub fn still(defaults: Keyframe) -> Self {
+        Self {
+            vertical_up: Vec3::new(0.0, 1.0, 0.0),
+            camera_path: CameraPath::new(vec![defaults], Interpolation::Linear),
+            frames: 1,
+            lights: Vec::new(),
+        }
+    }
+
+    pub fn read(path: &Path, defaults: Keyframe) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text, defaults)
+            .map_err(|error| format!("{}: {}", path.display(), error).into())
+    }
+
+    pub fn parse(text: &str, defaults: Keyframe) -> Result<Self, Box<dyn Error>> {
+        let mut vertical_up = Vec3::new(0.0, 1.0, 0.0);
+        let mut interpolation = Interpolation::Linear;
+        let mut frames = 1;
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        let mut lights = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let directive = match tokens.next() {
+                Some(directive) => directive,
+                None => continue,
+            };
+            let mut parse_line = || -> Result<(), Box<dyn Error>> {
+                match directive {
+                    "up" => vertical_up = vector(&mut tokens)?,
+                    "interpolation" => interpolation = word(&mut tokens)?.parse()?,
+                    "frames" => frames = word(&mut tokens)?.parse()?,
+                    "keyframe" => {
+                        let previous = keyframes.last().copied().unwrap_or(defaults);
+                        keyframes.push(keyframe(&mut tokens, previous)?);
+                    }
+                    "light" => lights.push(light(&mut tokens)?),
+                    _ => return Err(format!("unknown directive {}", directive).into()),
+                }
+                match tokens.next() {
+                    Some(extra) => Err(format!("unexpected {}", extra).into()),
+                    None => Ok(()),
+                }
+            };
+            parse_line().map_err(|error| format!("line {}: {}", number + 1, error))?;
+        }
+        if keyframes.is_empty() {
+            keyframes.push(defaults);
+        }
+        Ok(Self {
+            vertical_up,
+            camera_path: CameraPath::new(keyframes, interpolation),
+            frames: frames.max(1),
+            lights,
+        })
+    }
+
+    /// The time of each frame, spread evenly from the first keyframe to the
+    /// last.
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        let (start, end) = (self.camera_path.start(), self.camera_path.end());
+        if self.frames == 1 {
+            return start;
+        }
+        start + (end - start) * frame as f64 / (self.frames - 1) as f64
+    }
+}
+
+fn keyframe<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    previous: Keyframe,
+) -> Result<Keyframe, Box<dyn Error>> {
+    let mut keyframe = previous;
+    while let Some(property) = tokens.next() {
+        match property {
+            "time" => keyframe.time = word(tokens)?.parse()?,
+            "look_from" => keyframe.look_from = vector(tokens)?,
+            "look_at" => keyframe.look_at = vector(tokens)?,
+            "fov" => keyframe.fov = word(tokens)?.parse()?,
+            "focus" => keyframe.focus_dist = word(tokens)?.parse()?,
+            _ => return Err(format!("unknown keyframe property {}", property).into()),
+        }
+    }
+    Ok(keyframe)
+}
+
+fn light<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<SceneLight, Box<dyn Error>> {
+    let shape = match word(tokens)? {
+        "sphere" => EmitterShape::Sphere {
+            center: vector(tokens)?,
+            radius: word(tokens)?.parse()?,
+        },
+        "rect" => EmitterShape::Rect {
+            corner: vector(tokens)?,
+            u: vector(tokens)?,
+            v: vector(tokens)?,
+        },
+        "triangle" => EmitterShape::Triangle {
+            vertices: [vector(tokens)?, vector(tokens)?, vector(tokens)?],
+        },
+        "disk" => EmitterShape::Disk {
+            center: vector(tokens)?,
+            normal: vector(tokens)?,
+            radius: word(tokens)?.parse()?,
+        },
+        "point" => {
+            return Ok(SceneLight::Point {
+                position: vector(tokens)?,
+                intensity: vector(tokens)?,
+            })
+        }
+        "spot" => {
+            return Ok(SceneLight::Spot {
+                position: vector(tokens)?,
+                direction: vector(tokens)?,
+                cone: word(tokens)?.parse()?,
+                falloff_start: word(tokens)?.parse()?,
+                intensity: vector(tokens)?,
+            })
+        }
+        "sun" => {
+            return Ok(SceneLight::Sun {
+                direction: vector(tokens)?,
+                irradiance: vector(tokens)?,
+            })
+        }
+        kind => return Err(format!("unknown light {}", kind).into()),
+    };
+    Ok(SceneLight::Area {
+        shape,
+        radiance: vector(tokens)?,
+    })
+}
+
+fn word<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, Box<dyn Error>> {
+    Ok(tokens.next().ok_or("missing value")?)
+}
+
+fn vector<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Vec3, Box<dyn Error>> {
+    Ok(Vec3::new(
+        word(tokens)?.parse()?,
+        word(tokens)?.parse()?,
+        word(tokens)?.parse()?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn defaults() -> Keyframe {
+        Keyframe {
+            time: 0.0,
+            look_from: Vec3::new(13.0, 2.0, 3.0),
+            look_at: Vec3::default(),
+            fov: 20.0,
+            focus_dist: 10.0,
+        }
+    }
+
+    #[test]
+    fn keyframes_carry_over_what_they_leave_out() {
+        let text = "
+            # A camera swinging round while it zooms in.
+            up 0 0 1
+            interpolation linear
+            frames 5
+            keyframe time 0 look_from 10 0 0 fov 40
+            keyframe time 2 look_from 0 10 0   # keeps the wide lens
+            keyframe time 4 fov 20
+        ";
+        let scene = Scene::parse(text, defaults()).unwrap();
+        assert_eq!(scene.vertical_up, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.frames, 5);
+        assert!(scene.lights.is_empty());
+        let times = (0..5).map(|frame| scene.frame_time(frame));
+        assert!(times.eq(vec![0.0, 1.0, 2.0, 3.0, 4.0]));
+
+        let start = scene.camera_path.at(0.0);
+        assert_eq!(start.look_from, Vec3::new(10.0, 0.0, 0.0));
+        assert_eq!(start.look_at, Vec3::default());
+        assert_eq!(start.focus_dist, 10.0);
+        let middle = scene.camera_path.at(1.0);
+        assert_eq!(middle.look_from, Vec3::new(5.0, 5.0, 0.0));
+        assert_eq!(middle.fov, 40.0);
+        let zooming = scene.camera_path.at(3.0);
+        assert_eq!(zooming.look_from, Vec3::new(0.0, 10.0, 0.0));
+        assert_eq!(zooming.fov, 30.0);
+
+        // With no keyframes the camera holds still at the defaults.
+        let still = Scene::parse("frames 0\n", defaults()).unwrap();
+        assert_eq!(still.frames, 1);
+        assert_eq!(still.frame_time(0), 0.0);
+        assert_eq!(still.camera_path.at(3.0).look_from, defaults().look_from);
+    }
+
+    #[test]
+    fn lights_are_read_with_their_parameters() {
+        let text = "
+            light sphere 0 6 0  1  10 10 10
+            light rect -1 4 -1  2 0 0  0 0 2  8 8 8
+            light triangle 0 0 0  1 0 0  0 1 0  1 2 3
+            light disk 0 5 0  0 -1 0  0.5  4 4 4
+            light point 1 2 3  5 5 5
+            light spot 0 4 0  0 -1 0  30 20  6 6 6
+            light sun 1 1 0  2 2 2
+        ";
+        let scene = Scene::parse(text, defaults()).unwrap();
+        let gray = |value| Vec3::new(value, value, value);
+        assert_eq!(
+            scene.lights,
+            vec![
+                SceneLight::Area {
+                    shape: EmitterShape::Sphere {
+                        center: Vec3::new(0.0, 6.0, 0.0),
+                        radius: 1.0,
+                    },
+                    radiance: gray(10.0),
+                },
+                SceneLight::Area {
+                    shape: EmitterShape::Rect {
+                        corner: Vec3::new(-1.0, 4.0, -1.0),
+                        u: Vec3::new(2.0, 0.0, 0.0),
+                        v: Vec3::new(0.0, 0.0, 2.0),
+                    },
+                    radiance: gray(8.0),
+                },
+                SceneLight::Area {
+                    shape: EmitterShape::Triangle {
+                        vertices: [
+                            Vec3::default(),
+                            Vec3::new(1.0, 0.0, 0.0),
+                            Vec3::new(0.0, 1.0, 0.0),
+                        ],
+                    },
+                    radiance: Vec3::new(1.0, 2.0, 3.0),
+                },
+                SceneLight::Area {
+                    shape: EmitterShape::Disk {
+                        center: Vec3::new(0.0, 5.0, 0.0),
+                        normal: Vec3::new(0.0, -1.0, 0.0),
+                        radius: 0.5,
+                    },
+                    radiance: gray(4.0),
+                },
+                SceneLight::Point {
+                    position: Vec3::new(1.0, 2.0, 3.0),
+                    intensity: gray(5.0),
+                },
+                SceneLight::Spot {
+                    position: Vec3::new(0.0, 4.0, 0.0),
+                    direction: Vec3::new(0.0, -1.0, 0.0),
+                    cone: 30.0,
+                    falloff_start: 20.0,
+                    intensity: gray(6.0),
+                },
+                SceneLight::Sun {
+                    direction: Vec3::new(1.0, 1.0, 0.0),
+                    irradiance: gray(2.0),
+                },
+            ]
+        );
+
+        for text in &["light lamp 0 0 0\n", "light sphere 0 6 0 1\n"] {
+            assert!(Scene::parse(text, defaults()).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        for (text, expected) in &[
+            ("up 0 1\n", "line 1: missing value"),
+            (
+                "\nkeyframe zoom 2\n",
+                "line 2: unknown keyframe property zoom",
+            ),
+            ("frames 2 3\n", "line 1: unexpected 3"),
+            ("interpolation bezier\n", "line 1: "),
+            ("camera 1\n", "line 1: unknown directive camera"),
+        ] {
+            let error = Scene::parse(text, defaults()).err().unwrap().to_string();
+            assert!(error.starts_with(expected), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{
+    hit::Hit,
+    ray::Ray,
+    vec3::{dot, unit_vector, Vec3},
+};
+use std::rc::Rc;
+
+/// A point picked on the surface of a shape.
+#[derive(Debug, Clone, Copy)]
+pub struct ShapeSample {
+    pub point: Vec3,
+    /// The outward normal at `point`.
+    pub normal: Vec3,
+    /// The density with which the point was picked: per unit area from
+    /// `sample_area`, per unit solid angle seen from the reference point
+    /// from `sample`.
+    pub pdf: f64,
+}
+
+/// A surface points can be picked on, so that it can be used as an area
+/// light when its material gives off light.
+pub trait Shape<'material>: Hit<'material> {
+    fn area(&self) -> f64;
+
+    /// Picks a point uniformly by area.
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample;
+
+    /// Picks a point to light `reference` from. Shapes that can do better
+    /// than picking by area, and converting that to solid angle, override
+    /// this along with `pdf`.
+    fn sample(&self, reference: Vec3, u: (f64, f64)) -> Option<ShapeSample> {
+        to_solid_angle(self.sample_area(u), reference)
+    }
+
+    /// The density, per unit solid angle, with which `sample` picks the
+    /// point seen from `reference` in `direction`.
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        pdf_by_area(self, reference, direction)
+    }
+
+    /// A cone holding every outward normal on the surface, as its axis and
+    /// the cosine of its half angle. By default it takes in every
+    /// direction.
+    fn normal_cone(&self) -> (Vec3, f64) {
+        (Vec3::new(0.0, 0.0, 1.0), -1.0)
+    }
+}
+
+/// Lets shapes be shared, and wrapped without knowing their type.
+impl<'material, T> Shape<'material> for Rc<T>
+where
+    T: Shape<'material> + ?Sized,
+{
+    fn area(&self) -> f64 {
+        (**self).area()
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        (**self).sample_area(u)
+    }
+
+    fn sample(&self, reference: Vec3, u: (f64, f64)) -> Option<ShapeSample> {
+        (**self).sample(reference, u)
+    }
+
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        (**self).pdf(reference, direction)
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        (**self).normal_cone()
+    }
+}
+
+/// Converts the density of a point picked by area to the density of the
+/// direction it's seen in from `reference`.
+pub fn to_solid_angle(sample: ShapeSample, reference: Vec3) -> Option<ShapeSample> {
+    let offset = sample.point - reference;
+    let cosine = dot(sample.normal, unit_vector(offset)).abs();
+    if cosine == 0.0 || !cosine.is_finite() {
+        return None;
+    }
+    Some(ShapeSample {
+        pdf: sample.pdf * offset.length_squared() / cosine,
+        ..sample
+    })
+}
+
+/// The density per unit solid angle of seeing the shape from `reference`
+/// in `direction`, when points on it are picked by area.
+pub fn pdf_by_area<'material, S>(shape: &S, reference: Vec3, direction: Vec3) -> f64
+where
+    S: Shape<'material> + ?Sized,
+{
+    let ray = Ray::new(reference, unit_vector(direction));
+    match shape.hit(&ray, 0.001, f64::INFINITY) {
+        Some(record) => {
+            let cosine = dot(record.normal, ray.direction).abs();
+            if cosine == 0.0 {
+                0.0
+            } else {
+                record.t.powi(2) / (cosine * shape.area())
+            }
+        }
+        None => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        disk::Disk,
+        material::{Lambertian, Material},
+        rect::Rect,
+        sampler::{sample_unit_vector, IndependentSampler, Sampler},
+        sphere::Sphere,
+        triangle::Triangle,
+    };
+    use std::{cell::RefCell, f64::consts::PI, rc::Rc};
+
+    #[test]
+    fn samples_cover_the_solid_angle_the_shape_takes_up() {
+        let material: Box<dyn Material> = Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let material = Rc::new(RefCell::new(material));
+        let shapes: Vec<Box<dyn Shape>> = vec![
+            Box::new(Sphere::new(Vec3::new(0.0, 3.0, 0.0), 1.0, material.clone())),
+            Box::new(Rect::new(
+                Vec3::new(-1.0, 2.0, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 2.0),
+                material.clone(),
+            )),
+            Box::new(Triangle::new(
+                [
+                    Vec3::new(-1.0, 2.0, 0.0),
+                    Vec3::new(1.0, 2.5, 0.0),
+                    Vec3::new(0.0, 3.0, 1.5),
+                ],
+                material.clone(),
+            )),
+            Box::new(Disk::new(
+                Vec3::new(0.5, 2.0, 0.0),
+                Vec3::new(0.0, -1.0, 1.0),
+                1.5,
+                material,
+            )),
+        ];
+        let reference = Vec3::default();
+        let mut sampler = IndependentSampler::new(11);
+        let count = 100_000;
+        for shape in &shapes {
+            // Averaging the inverse density of the samples estimates the
+            // solid angle, as does counting uniformly picked directions that
+            // hit the shape.
+            let mut sampled = 0.0;
+            for _ in 0..count {
+                let sample = shape.sample(reference, sampler.next_2d()).unwrap();
+                let pdf = shape.pdf(reference, sample.point - reference);
+                assert!(
+                    (pdf - sample.pdf).abs() < 1e-6 * pdf,
+                    "{} against {}",
+                    pdf,
+                    sample.pdf
+                );
+                sampled += 1.0 / sample.pdf / count as f64;
+            }
+            let hits = (0..count)
+                .filter(|_| {
+                    let ray = Ray::new(reference, sample_unit_vector(sampler.next_2d()));
+                    shape.hit(&ray, 0.001, f64::INFINITY).is_some()
+                })
+                .count();
+            let counted = 4.0 * PI * hits as f64 / count as f64;
+            assert!(
+                (sampled - counted).abs() < 0.03 * counted,
+                "{} against {}",
+                sampled,
+                counted
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::vec3::Vec3;
+use impl_ops::*;
+use std::ops;
+
+/// Number of wavelengths carried along each path.
+pub const WAVELENGTHS: usize = 4;
+
+/// Shortest and longest wavelength sampled, in nanometres.
+const LAMBDA_MIN: f64 = 360.0;
+const LAMBDA_MAX: f64 = 830.0;
+
+/// Integral of the y colour matching function over the visible range, so
+/// that a constant spectrum of 1 has a luminance of 1.
+const CIE_Y_INTEGRAL: f64 = 106.922;
+
+/// A spectral quantity at each of the wavelengths in a `Wavelengths`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spectrum(pub [f64; WAVELENGTHS]);
+
+impl Spectrum {
+    pub fn constant(value: f64) -> Self {
+        Self([value; WAVELENGTHS])
+    }
+
+    pub fn max(&self) -> f64 {
+        self.0.iter().copied().fold(f64::MIN, f64::max)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.0.iter().all(|value| *value == 0.0)
+    }
+
+    /// Upsamples an RGB reflectance with Smits' method: the colour is built
+    /// from white plus the primary and secondary spectra that best cover it.
+    pub fn from_rgb(rgb: Vec3, wavelengths: &Wavelengths) -> Self {
+        let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+        let weights: [(f64, &[f64; 10]); 3] = if r <= g && r <= b {
+            if g <= b {
+                [(r, &WHITE), (g - r, &CYAN), (b - g, &BLUE)]
+            } else {
+                [(r, &WHITE), (b - r, &CYAN), (g - b, &GREEN)]
+            }
+        } else if g <= r && g <= b {
+            if r <= b {
+                [(g, &WHITE), (r - g, &MAGENTA), (b - r, &BLUE)]
+            } else {
+                [(g, &WHITE), (b - g, &MAGENTA), (r - b, &RED)]
+            }
+        } else if r <= g {
+            [(b, &WHITE), (r - b, &YELLOW), (g - r, &GREEN)]
+        } else {
+            [(b, &WHITE), (g - b, &YELLOW), (r - g, &RED)]
+        };
+        let mut spectrum = [0.0; WAVELENGTHS];
+        for (value, lambda) in spectrum.iter_mut().zip(wavelengths.lambda.iter()) {
+            let bin = (((lambda - 380.0) / 34.0).max(0.0) as usize).min(9);
+            *value = weights
+                .iter()
+                .map(|(weight, basis)| weight * basis[bin])
+                .sum::<f64>()
+                .max(0.0);
+        }
+        Self(spectrum)
+    }
+
+    /// Converts radiance estimated at `wavelengths` back to linear sRGB.
+    pub fn to_rgb(self, wavelengths: &Wavelengths) -> Vec3 {
+        let mut xyz = Vec3::default();
+        let mut count = 0.0;
+        for ((value, lambda), pdf) in self
+            .0
+            .iter()
+            .zip(wavelengths.lambda.iter())
+            .zip(wavelengths.pdf.iter())
+        {
+            count += 1.0;
+            if *pdf == 0.0 {
+                continue;
+            }
+            xyz += value / pdf * Vec3::new(cie_x(*lambda), cie_y(*lambda), cie_z(*lambda));
+        }
+        xyz_to_linear_srgb(xyz / (count * CIE_Y_INTEGRAL))
+    }
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self::constant(0.0)
+    }
+}
+
+impl_op_ex!(+|a: &Spectrum, b: &Spectrum| -> Spectrum {
+    let mut sum = a.0;
+    sum.iter_mut().zip(b.0.iter()).for_each(|(a, b)| *a += b);
+    Spectrum(sum)
+});
+impl_op_ex!(*|a: &Spectrum, b: &Spectrum| -> Spectrum {
+    let mut product = a.0;
+    product
+        .iter_mut()
+        .zip(b.0.iter())
+        .for_each(|(a, b)| *a *= b);
+    Spectrum(product)
+});
+impl_op_ex!(*|a: &Spectrum, b: f64| -> Spectrum { Spectrum(a.0.map(|a| a * b)) });
+
+/// The wavelengths sampled for one path, in nanometres.
+///
+/// The first is the hero wavelength; the rest are spread evenly through
+/// the sampling distribution from it, so one random number picks all four.
+/// When a path hits something dispersive only the hero can follow the
+/// refracted direction, so the others get dropped by zeroing their pdf.
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; WAVELENGTHS],
+    pdf: [f64; WAVELENGTHS],
+}
+
+impl Wavelengths {
+    /// Samples wavelengths in proportion to how visible they are, which
+    /// keeps most samples away from the dim ends of the spectrum.
+    pub fn sample_visible(u: f64) -> Self {
+        let mut lambda = [0.0; WAVELENGTHS];
+        let mut pdf = [0.0; WAVELENGTHS];
+        for i in 0..WAVELENGTHS {
+            let u = (u + i as f64 / WAVELENGTHS as f64).fract();
+            lambda[i] = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+            pdf[i] = visible_pdf(lambda[i]);
+        }
+        Self { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|pdf| *pdf == 0.0) {
+            return;
+        }
+        self.pdf[1..].iter_mut().for_each(|pdf| *pdf = 0.0);
+        // The hero now stands in for all four.
+        self.pdf[0] /= WAVELENGTHS as f64;
+    }
+}
+
+fn visible_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+/// Index of refraction as a function of wavelength.
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f64),
+    /// `a + b / λ²`, with λ in micrometres.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// The Sellmeier equation, with `c` in square micrometres.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Ior {
+    /// Schott N-BK7, a common borosilicate crown glass.
+    pub fn bk7() -> Self {
+        Ior::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    pub fn at(&self, lambda: f64) -> f64 {
+        let micrometres = lambda / 1000.0;
+        let squared = micrometres * micrometres;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / squared,
+            Ior::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * squared / (squared - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    /// The index at the helium d line, as used for RGB rendering.
+    pub fn nominal(&self) -> f64 {
+        self.at(587.6)
+    }
+}
+
+/// Metals with measured complex indices of refraction.
+#[derive(Debug, Clone, Copy)]
+pub enum Conductor {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+}
+
+impl Conductor {
+    /// `(n, k)` every 50nm from 400nm to 750nm, after Johnson and Christy
+    /// for the noble metals and Rakić for aluminium.
+    fn table(&self) -> &'static [(f64, f64); 8] {
+        match self {
+            Conductor::Gold => &[
+                (1.66, 1.96),
+                (1.45, 1.95),
+                (0.97, 1.87),
+                (0.35, 2.73),
+                (0.20, 3.09),
+                (0.14, 3.70),
+                (0.13, 4.10),
+                (0.14, 4.54),
+            ],
+            Conductor::Silver => &[
+                (0.05, 2.10),
+                (0.04, 2.65),
+                (0.05, 3.10),
+                (0.06, 3.60),
+                (0.06, 4.00),
+                (0.05, 4.50),
+                (0.04, 4.90),
+                (0.03, 5.30),
+            ],
+            Conductor::Copper => &[
+                (1.17, 2.40),
+                (1.17, 2.50),
+                (1.12, 2.60),
+                (1.02, 2.58),
+                (0.27, 3.20),
+                (0.21, 3.67),
+                (0.21, 4.05),
+                (0.24, 4.40),
+            ],
+            Conductor::Aluminium => &[
+                (0.49, 4.86),
+                (0.62, 5.47),
+                (0.77, 6.08),
+                (0.96, 6.69),
+                (1.20, 7.26),
+                (1.47, 7.79),
+                (1.83, 8.31),
+                (2.40, 8.62),
+            ],
+        }
+    }
+
+    /// The complex index `(n, k)` at `lambda`, linearly interpolated.
+    fn ior(&self, lambda: f64) -> (f64, f64) {
+        let table = self.table();
+        let x = ((lambda - 400.0) / 50.0).clamp(0.0, (table.len() - 1) as f64);
+        let i = (x as usize).min(table.len() - 2);
+        let t = x - i as f64;
+        let ((n0, k0), (n1, k1)) = (table[i], table[i + 1]);
+        (n0 + t * (n1 - n0), k0 + t * (k1 - k0))
+    }
+
+    /// Unpolarised Fresnel reflectance at `lambda` for light arriving at
+    /// `cos_theta` to the normal.
+    pub fn reflectance(&self, cos_theta: f64, lambda: f64) -> f64 {
+        let (n, k) = self.ior(lambda);
+        fresnel_complex(cos_theta.clamp(0.0, 1.0), Complex::new(n, k))
+    }
+
+    /// Reflectance at normal incidence in linear sRGB, for the RGB renderer.
+    pub fn rgb(&self) -> Vec3 {
+        let steps = 94;
+        let mut xyz = Vec3::default();
+        for step in 0..steps {
+            let lambda =
+                LAMBDA_MIN + (step as f64 + 0.5) * (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+            xyz += self.reflectance(1.0, lambda)
+                * Vec3::new(cie_x(lambda), cie_y(lambda), cie_z(lambda));
+        }
+        let xyz = xyz * (LAMBDA_MAX - LAMBDA_MIN) / (steps as f64 * CIE_Y_INTEGRAL);
+        let rgb = xyz_to_linear_srgb(xyz);
+        Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let length = self.norm().sqrt();
+        let re = ((length + self.re) / 2.0).sqrt();
+        let im = ((length - self.re) / 2.0).sqrt();
+        Self::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl_op_ex!(+|a: &Complex, b: &Complex| -> Complex { Complex::new(a.re + b.re, a.im + b.im) });
+impl_op_ex!(-|a: &Complex, b: &Complex| -> Complex { Complex::new(a.re - b.re, a.im - b.im) });
+impl_op_ex!(*|a: &Complex, b: &Complex| -> Complex {
+    Complex::new(a.re * b.re - a.im * b.im, a.re * b.im + a.im * b.re)
+});
+impl_op_ex!(/|a: &Complex, b: &Complex| -> Complex {
+    let denominator = b.norm();
+    Complex::new(
+        (a.re * b.re + a.im * b.im) / denominator,
+        (a.im * b.re - a.re * b.im) / denominator,
+    )
+});
+impl_op_ex!(*|a: &Complex, b: f64| -> Complex { Complex::new(a.re * b, a.im * b) });
+
+fn fresnel_complex(cos_i: f64, eta: Complex) -> f64 {
+    let one = Complex::new(1.0, 0.0);
+    let sin2_t = Complex::new(1.0 - cos_i * cos_i, 0.0) / (eta * eta);
+    let cos_t = (one - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular =
+        (Complex::new(cos_i, 0.0) - eta * cos_t) / (Complex::new(cos_i, 0.0) + eta * cos_t);
+    (parallel.norm() + perpendicular.norm()) / 2.0
+}
+
+// Wyman, Sloan and Shirley's multi-lobe fit to the CIE 1931 colour
+// matching functions.
+fn lobe(lambda: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let sigma = if lambda < mean { below } else { above };
+    (-0.5 * ((lambda - mean) / sigma).powi(2)).exp()
+}
+
+fn cie_x(lambda: f64) -> f64 {
+    1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2)
+}
+
+fn cie_y(lambda: f64) -> f64 {
+    0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1)
+}
+
+fn cie_z(lambda: f64) -> f64 {
+    1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+}
+
+/// Converts XYZ to linear sRGB. Spectra here are relative to an
+/// equal-energy white, so this includes a Bradford adaptation to D65 to keep
+/// a flat spectrum white.
+fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.146251 * xyz.x - 1.666124 * xyz.y - 0.480127 * xyz.z,
+        -0.995535 * xyz.x + 1.955763 * xyz.y + 0.039772 * xyz.z,
+        0.063598 * xyz.x - 0.214597 * xyz.y + 1.150999 * xyz.z,
+    )
+}
+
+// Smits' basis spectra in ten bins from 380nm to 720nm.
+const WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn white_round_trips_to_white() {
+        let mut rgb = Vec3::default();
+        let samples = 1000;
+        for i in 0..samples {
+            let wavelengths = Wavelengths::sample_visible((i as f64 + 0.5) / samples as f64);
+            rgb += Spectrum::from_rgb(Vec3::new(1.0, 1.0, 1.0), &wavelengths).to_rgb(&wavelengths);
+        }
+        let rgb = rgb / samples as f64;
+        for channel in &[rgb.x, rgb.y, rgb.z] {
+            assert!((channel - 1.0).abs() < 0.02, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn dispersion_bends_blue_more_than_red() {
+        let bk7 = Ior::bk7();
+        assert!((bk7.nominal() - 1.5168).abs() < 1e-3);
+        assert!(bk7.at(450.0) > bk7.at(650.0));
+        assert!(Conductor::Gold.rgb().x > Conductor::Gold.rgb().z);
+    }
+}
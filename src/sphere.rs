@@ -1,10 +1,13 @@
 use crate::{
-    hit::{face_normal, Hit, HitRecord},
+    bvh::Aabb,
+    hit::{face_normal, tangent_frame, Hit, HitRecord},
     material::Material,
     ray::Ray,
-    vec3::{dot, Vec3},
+    sampler::sample_unit_vector,
+    shape::{pdf_by_area, to_solid_angle, Shape, ShapeSample},
+    vec3::{dot, perpendicular, unit_vector, Vec3},
 };
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, f64::consts::PI, rc::Rc};
 
 pub struct Sphere<'material> {
     center: Vec3,
@@ -49,12 +52,116 @@ impl<'material> Hit<'material> for Sphere<'material> {
         let point = ray.at(t);
         let outward_normal = (point - self.center) / self.radius;
         let (front_face, normal) = face_normal(ray, outward_normal);
+        // Round the y axis as u grows and up it as v grows.
+        let (tangent, bitangent) = tangent_frame(
+            outward_normal,
+            Vec3::new(outward_normal.z, 0.0, -outward_normal.x),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
         Some(HitRecord {
             point,
             normal,
             front_face,
             t: root,
+            uv: sphere_uv(outward_normal),
+            barycentric: None,
             material: self.material.clone(),
+            light: None,
+            tangent,
+            bitangent,
         })
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        if self.material.borrow().is_specular() {
+            vec![self.bounding_box()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl<'material> Sphere<'material> {
+    /// The cosine of the half angle of the cone the sphere fills as seen
+    /// from `reference`, and one minus that, worked out so that it stays
+    /// accurate for small, distant spheres. `None` from inside the sphere.
+    fn cone(&self, reference: Vec3) -> Option<(f64, f64)> {
+        let sin2_max = self.radius.powi(2) / (self.center - reference).length_squared();
+        if sin2_max >= 1.0 {
+            return None;
+        }
+        let cos_max = (1.0 - sin2_max).sqrt();
+        Some((cos_max, sin2_max / (1.0 + cos_max)))
+    }
+}
+
+impl<'material> Shape<'material> for Sphere<'material> {
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius.powi(2)
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        let normal = sample_unit_vector(u);
+        ShapeSample {
+            point: self.center + self.radius * normal,
+            normal,
+            pdf: 1.0 / self.area(),
+        }
+    }
+
+    /// Picks a direction uniformly within the cone the sphere fills, which
+    /// unlike picking by area never wastes samples on the far side.
+    fn sample(&self, reference: Vec3, u: (f64, f64)) -> Option<ShapeSample> {
+        let one_minus_cos_max = match self.cone(reference) {
+            Some((_, one_minus_cos_max)) => one_minus_cos_max,
+            None => return to_solid_angle(self.sample_area(u), reference),
+        };
+        let cos_theta = 1.0 - u.0 * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta.powi(2)).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        let offset = self.center - reference;
+        let axis = unit_vector(offset);
+        let (tangent, bitangent) = perpendicular(axis);
+        let direction =
+            sin_theta * (phi.cos() * tangent + phi.sin() * bitangent) + cos_theta * axis;
+        // The near intersection of the direction with the sphere.
+        let distance = offset.length();
+        let along = distance * cos_theta
+            - (self.radius.powi(2) - (distance * sin_theta).powi(2))
+                .max(0.0)
+                .sqrt();
+        let point = reference + along * direction;
+        Some(ShapeSample {
+            point,
+            normal: (point - self.center) / self.radius,
+            pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+        })
+    }
+
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        match self.cone(reference) {
+            Some((cos_max, one_minus_cos_max)) => {
+                let axis = unit_vector(self.center - reference);
+                if dot(unit_vector(direction), axis) < cos_max {
+                    0.0
+                } else {
+                    1.0 / (2.0 * PI * one_minus_cos_max)
+                }
+            }
+            None => pdf_by_area(self, reference, direction),
+        }
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, with `u` going
+/// round from -x and `v` up from the bottom.
+fn sphere_uv(point: Vec3) -> (f64, f64) {
+    let theta = (-point.y).acos();
+    let phi = (-point.z).atan2(point.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
 }
@@ -0,0 +1,140 @@
+use crate::{hit::HitRecord, image::Image, vec3::Vec3};
+use std::error::Error;
+
+/// A value that varies over a surface, for material parameters. Scalar
+/// parameters use the luminance of the value.
+pub trait Texture {
+    fn value(&self, record: &HitRecord) -> Vec3;
+
+    fn scalar(&self, record: &HitRecord) -> f64 {
+        self.value(record).luminance()
+    }
+}
+
+/// The same value everywhere.
+impl Texture for f64 {
+    fn value(&self, _: &HitRecord) -> Vec3 {
+        Vec3::new(*self, *self, *self)
+    }
+
+    fn scalar(&self, _: &HitRecord) -> f64 {
+        *self
+    }
+}
+
+/// The same color everywhere.
+impl Texture for Vec3 {
+    fn value(&self, _: &HitRecord) -> Vec3 {
+        *self
+    }
+}
+
+/// Cubes of two alternating textures filling space, `size` across.
+pub struct Checker<Even, Odd> {
+    size: f64,
+    even: Even,
+    odd: Odd,
+}
+
+impl<Even, Odd> Checker<Even, Odd> {
+    pub fn new(size: f64, even: Even, odd: Odd) -> Self {
+        Self { size, even, odd }
+    }
+}
+
+impl<Even, Odd> Texture for Checker<Even, Odd>
+where
+    Even: Texture,
+    Odd: Texture,
+{
+    fn value(&self, record: &HitRecord) -> Vec3 {
+        let cell = |coordinate: f64| (coordinate / self.size).floor() as i64;
+        let point = record.point;
+        if (cell(point.x) + cell(point.y) + cell(point.z)).rem_euclid(2) == 0 {
+            self.even.value(record)
+        } else {
+            self.odd.value(record)
+        }
+    }
+}
+
+/// An image wrapped round a surface by its `uv` coordinates, with `v`
+/// going up the image and both repeating. Values are used as stored, as
+/// suits normal, bump and displacement maps.
+pub struct ImageTexture {
+    image: Image,
+}
+
+impl ImageTexture {
+    /// Fails if the image has no pixels to wrap round.
+    pub fn new(image: Image) -> Result<Self, Box<dyn Error>> {
+        if image.width == 0 || image.height == 0 {
+            return Err("an image texture needs at least one pixel".into());
+        }
+        Ok(Self { image })
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.image.width as i64) as usize;
+        let y = y.rem_euclid(self.image.height as i64) as usize;
+        self.image.pixels[y * self.image.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Blends the four pixels around the point.
+    fn value(&self, record: &HitRecord) -> Vec3 {
+        let x = record.uv.0 * self.image.width as f64 - 0.5;
+        let y = (1.0 - record.uv.1) * self.image.height as f64 - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (fx, fy) = (x - left, y - top);
+        let (left, top) = (left as i64, top as i64);
+        (1.0 - fy) * ((1.0 - fx) * self.pixel(left, top) + fx * self.pixel(left + 1, top))
+            + fy * ((1.0 - fx) * self.pixel(left, top + 1) + fx * self.pixel(left + 1, top + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        hit::Hit,
+        material::{Lambertian, Material},
+        ray::Ray,
+        sphere::Sphere,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn image_textures_blend_and_wrap_pixels() {
+        let (black, white) = (Vec3::default(), Vec3::new(1.0, 1.0, 1.0));
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![black, white],
+        };
+        let texture = ImageTexture::new(image).unwrap();
+        let material: Box<dyn Material> = Box::new(Lambertian::new(white));
+        let sphere = Sphere::new(Vec3::default(), 1.0, Rc::new(RefCell::new(material)));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let at = |u| {
+            texture.value(&HitRecord {
+                uv: (u, 0.5),
+                ..record.clone()
+            })
+        };
+        assert_eq!(at(0.25), black);
+        assert_eq!(at(0.5), 0.5 * white);
+        assert_eq!(at(0.75), white);
+        // Past the right edge the image starts again from the left.
+        assert_eq!(at(1.0), 0.5 * white);
+
+        let empty = Image {
+            width: 3,
+            height: 0,
+            pixels: Vec::new(),
+        };
+        assert!(ImageTexture::new(empty).is_err());
+    }
+}
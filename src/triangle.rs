@@ -0,0 +1,192 @@
+use crate::{
+    bvh::Aabb,
+    hit::{face_normal, tangent_frame, Hit, HitRecord},
+    material::Material,
+    ray::Ray,
+    shape::{Shape, ShapeSample},
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// A single triangle. Its front is the side its vertices go round
+/// anticlockwise on.
+pub struct Triangle<'material> {
+    vertices: [Vec3; 3],
+    normal: Vec3,
+    area: f64,
+    /// Normals at the vertices, blended across the triangle so that a mesh
+    /// of triangles shades smoothly.
+    normals: Option<[Vec3; 3]>,
+    /// Surface coordinates at the vertices.
+    uvs: [(f64, f64); 3],
+    material: Rc<RefCell<Box<dyn Material + 'material>>>,
+}
+
+impl<'material> Triangle<'material> {
+    /// A flat triangle, whose surface coordinates are the barycentric
+    /// coordinates of the second and third vertices.
+    pub fn new(vertices: [Vec3; 3], material: Rc<RefCell<Box<dyn Material + 'material>>>) -> Self {
+        let n = cross(vertices[1] - vertices[0], vertices[2] - vertices[0]);
+        Self {
+            vertices,
+            normal: unit_vector(n),
+            area: 0.5 * n.length(),
+            normals: None,
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            material,
+        }
+    }
+
+    /// A triangle from a mesh, with the mesh's outward `normals` and
+    /// surface coordinates at its vertices.
+    pub fn smooth(
+        vertices: [Vec3; 3],
+        normals: [Vec3; 3],
+        uvs: [(f64, f64); 3],
+        material: Rc<RefCell<Box<dyn Material + 'material>>>,
+    ) -> Self {
+        Self {
+            normals: Some(normals),
+            uvs,
+            ..Self::new(vertices, material)
+        }
+    }
+
+    /// How the point moves as each surface coordinate grows.
+    fn uv_derivatives(&self) -> (Vec3, Vec3) {
+        let [a, b, c] = self.vertices;
+        let [uv_a, uv_b, uv_c] = self.uvs;
+        let (du_ac, dv_ac) = (uv_a.0 - uv_c.0, uv_a.1 - uv_c.1);
+        let (du_bc, dv_bc) = (uv_b.0 - uv_c.0, uv_b.1 - uv_c.1);
+        let determinant = du_ac * dv_bc - dv_ac * du_bc;
+        if determinant.abs() < 1e-12 {
+            return (b - a, c - a);
+        }
+        let (ac, bc) = (a - c, b - c);
+        (
+            (dv_bc * ac - dv_ac * bc) / determinant,
+            (du_ac * bc - du_bc * ac) / determinant,
+        )
+    }
+}
+
+impl<'material> Hit<'material> for Triangle<'material> {
+    /// Möller and Trumbore's test, which finds the barycentric coordinates
+    /// of the hit on the way.
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'material>> {
+        let [a, b, c] = self.vertices;
+        let (edge1, edge2) = (b - a, c - a);
+        let p = cross(ray.direction, edge2);
+        let determinant = dot(edge1, p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let offset = ray.origin - a;
+        let u = dot(offset, p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = cross(offset, edge1);
+        let v = dot(ray.direction, q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = dot(edge2, q) * inverse;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        let weights = [1.0 - u - v, u, v];
+        let outward_normal = match self.normals {
+            Some(normals) => unit_vector(
+                weights[0] * normals[0] + weights[1] * normals[1] + weights[2] * normals[2],
+            ),
+            None => self.normal,
+        };
+        // Which side was hit depends on the true surface, not the blended
+        // normal.
+        let front_face = face_normal(ray, self.normal).0;
+        let normal = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        let blend = |coordinate: fn(&(f64, f64)) -> f64| {
+            (0..3)
+                .map(|vertex| weights[vertex] * coordinate(&self.uvs[vertex]))
+                .sum::<f64>()
+        };
+        let (dpdu, dpdv) = self.uv_derivatives();
+        let (tangent, bitangent) = tangent_frame(outward_normal, dpdu, dpdv);
+        Some(HitRecord {
+            point: ray.at(t),
+            normal,
+            t,
+            front_face,
+            uv: (blend(|uv| uv.0), blend(|uv| uv.1)),
+            barycentric: Some(Vec3::new(weights[0], weights[1], weights[2])),
+            material: self.material.clone(),
+            light: None,
+            tangent,
+            bitangent,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::around(&self.vertices, 1e-4)
+    }
+
+    fn specular_bounds(&self) -> Vec<Aabb> {
+        if self.material.borrow().is_specular() {
+            vec![self.bounding_box()]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Triangle<'_> {
+    /// The density per unit solid angle of picking by area the point `t`
+    /// along a unit `direction`, in a shape with this triangle's normal and
+    /// total area `area`.
+    pub fn density(&self, t: f64, direction: Vec3, area: f64) -> f64 {
+        let cosine = dot(self.normal, direction).abs();
+        if cosine == 0.0 {
+            0.0
+        } else {
+            t.powi(2) / (cosine * area)
+        }
+    }
+}
+
+impl<'material> Shape<'material> for Triangle<'material> {
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_area(&self, u: (f64, f64)) -> ShapeSample {
+        // Folding the square onto the triangle this way keeps the density
+        // even.
+        let root = u.0.sqrt();
+        let (b0, b1) = (1.0 - root, u.1 * root);
+        let [a, b, c] = self.vertices;
+        ShapeSample {
+            point: b0 * a + b1 * b + (1.0 - b0 - b1) * c,
+            normal: self.normal,
+            pdf: 1.0 / self.area,
+        }
+    }
+
+    /// Uses the true normal, which a smooth triangle's hits don't give.
+    fn pdf(&self, reference: Vec3, direction: Vec3) -> f64 {
+        let ray = Ray::new(reference, unit_vector(direction));
+        match self.hit(&ray, 0.001, f64::INFINITY) {
+            Some(record) => self.density(record.t, ray.direction, self.area),
+            None => 0.0,
+        }
+    }
+
+    fn normal_cone(&self) -> (Vec3, f64) {
+        (self.normal, 1.0)
+    }
+}
@@ -33,6 +33,19 @@ impl Vec3 {
         let s = 1e-8;
         (self.x.abs() < s) && (self.y.abs() < s) && (self.z.abs() < s)
     }
+
+    /// The x, y or z component, for axes 0, 1 and 2.
+    pub fn component(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
 }
 
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
@@ -44,30 +57,6 @@ pub fn random_bounded(min: f64, max: f64) -> f64 {
     min + (max - min) * rng.gen_range(min..max)
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    loop {
-        let p = Vec3::random(-1.0, 1.0);
-        if p.length_squared() >= 1.0 {
-            continue;
-        }
-        return p;
-    }
-}
-
-pub fn random_unit_vector() -> Vec3 {
-    unit_vector(random_in_unit_sphere())
-}
-
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::new(random_bounded(-1.0, 1.0), random_bounded(-1.0, 1.0), 0.0);
-        if p.length_squared() >= 1.0 {
-            continue;
-        }
-        return p;
-    }
-}
-
 pub fn dot(u: Vec3, v: Vec3) -> f64 {
     u.x * v.x + u.y * v.y + u.z * v.z
 }
@@ -80,6 +69,18 @@ pub fn cross(u: Vec3, v: Vec3) -> Vec3 {
     }
 }
 
+/// Two unit vectors perpendicular to the unit vector `direction` and to
+/// each other.
+pub fn perpendicular(direction: Vec3) -> (Vec3, Vec3) {
+    let helper = if direction.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = unit_vector(cross(direction, helper));
+    (tangent, cross(direction, tangent))
+}
+
 pub fn unit_vector(v: Vec3) -> Vec3 {
     v / v.length()
 }